@@ -0,0 +1,49 @@
+//! Compares evaluating a formula over columns with evaluating it row by row, run with
+//! `cargo bench`
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use culator::batch::evaluate_batch;
+use culator::compile::compile;
+use culator::{lexer, parser};
+
+const ROWS: usize = 1_000_000;
+const FORMULA: &str = "price * quantity * (1 - discount) + shipping / 2";
+
+fn main() {
+    let expression = parser::parse(&lexer::lex(FORMULA).unwrap()).unwrap();
+    let column =
+        |scale: f64| -> Vec<f64> { (0..ROWS).map(|row| scale * (row % 97) as f64).collect() };
+    let (price, quantity, discount, shipping) =
+        (column(1.5), column(1.0), column(0.001), column(0.25));
+    let columns: [(&str, &[f64]); 4] = [
+        ("price", &price),
+        ("quantity", &quantity),
+        ("discount", &discount),
+        ("shipping", &shipping),
+    ];
+
+    let start = Instant::now();
+    let compiled = compile(&expression, &columns.map(|(name, _)| name)).unwrap();
+    let by_row: Vec<f64> = (0..ROWS)
+        .map(|row| {
+            let arguments = columns.map(|(_, values)| values[row]);
+            compiled.evaluate(black_box(&arguments)).unwrap()
+        })
+        .collect();
+    let row_time = start.elapsed();
+
+    let start = Instant::now();
+    let by_column = evaluate_batch(black_box(&expression), &columns).unwrap();
+    let column_time = start.elapsed();
+
+    assert_eq!(by_row, by_column);
+    println!(
+        "{} rows: row by row {:.0?}, by column {:.0?} ({:.1}x faster)",
+        ROWS,
+        row_time,
+        column_time,
+        row_time.as_secs_f64() / column_time.as_secs_f64()
+    );
+}
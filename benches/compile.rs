@@ -0,0 +1,75 @@
+//! Compares the tree-walking evaluator with compiled bytecode, run with `cargo bench`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use culator::compile::compile_in;
+use culator::context::Context;
+use culator::evaluator::evaluate_in;
+use culator::value::Value;
+use culator::{lexer, parser};
+
+const ITERATIONS: u32 = 100_000;
+
+/// Formulas with their parameters, as in Monte Carlo pricing
+const FORMULAS: [(&str, &str); 3] = [
+    ("arithmetic", "s * (1 + r * t) - k"),
+    (
+        "terminal price",
+        "s * exp((r - v^2 / 2) * t + v * sqrt(t) * z)",
+    ),
+    (
+        "discounted payoff",
+        "payoff(s * exp((r - v^2 / 2) * t + v * sqrt(t) * z)) * exp(-r * t)",
+    ),
+];
+const PARAMETERS: [&str; 6] = ["s", "k", "r", "v", "t", "z"];
+
+/// Parameter values for the given iteration, with a varying draw `z`
+fn arguments(iteration: u32) -> [f64; 6] {
+    let z = (iteration % 1000) as f64 / 250.0 - 2.0;
+    [100.0, 105.0, 0.03, 0.2, 1.5, z]
+}
+
+fn time(mut run: impl FnMut(u32) -> f64) -> Duration {
+    let start = Instant::now();
+    let mut total = 0.0;
+    for iteration in 0..ITERATIONS {
+        total += run(iteration);
+    }
+    black_box(total);
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let mut context = Context::new();
+    let definition = parser::parse(&lexer::lex("payoff(price) = max(price - k, 0)").unwrap());
+    context.evaluate(&definition.unwrap()).unwrap();
+
+    for (name, formula) in FORMULAS {
+        let expression = parser::parse(&lexer::lex(formula).unwrap()).unwrap();
+
+        let mut scope = context.clone();
+        let tree_walker = time(|iteration| {
+            for (parameter, value) in PARAMETERS.iter().zip(arguments(iteration)) {
+                scope.set_variable(parameter, Value::Number(value));
+            }
+            evaluate_in(black_box(&expression), &scope)
+                .unwrap()
+                .as_number()
+                .unwrap()
+        });
+
+        let compiled = compile_in(&expression, &PARAMETERS, &context).unwrap();
+        let bytecode =
+            time(|iteration| compiled.evaluate(black_box(&arguments(iteration))).unwrap());
+
+        println!(
+            "{:<20} tree walker {:>8.0?}  compiled {:>8.0?}  ({:.1}x faster)",
+            name,
+            tree_walker,
+            bytecode,
+            tree_walker.as_secs_f64() / bytecode.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! Lexes, parses, evaluates and symbolically transforms arbitrary input, which must fail
+//! with an error rather than panic, overflow the stack or hang, and must print back to
+//! the same tree
+
+#![no_main]
+
+use culator::compile::compile_in;
+use culator::context::Context;
+use culator::limits::{self, Limits};
+use culator::{derivative, document, lexer, parser, simplify};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &str| {
+    let mut context = Context::new();
+    // A smaller budget than the default keeps each run quick
+    context.set_limits(Limits {
+        max_steps: 100_000,
+        max_list_length: 10_000,
+        ..Limits::default()
+    });
+
+    if let Ok(tokens) = lexer::lex(input) {
+        if let Ok(expression) = parser::parse_with_limits(&tokens, context.limits()) {
+            // Printing must keep the tree, though the parentheses it adds may nest the
+            // printed form too deeply to parse again
+            let printed = expression.to_string();
+            let tokens = lexer::lex(&printed).expect("printed expressions lex");
+            match parser::parse_with_limits(&tokens, context.limits()) {
+                Err(limits::TOO_DEEP) => {}
+                reparsed => assert_eq!(reparsed.as_ref(), Ok(&expression), "{}", printed),
+            }
+            if let Ok(compiled) = compile_in(&expression, &["x"], &context) {
+                let _ = compiled.evaluate(&[2.5]);
+            }
+            let _ = expression.to_latex();
+            let _ = expression.to_mathml();
+            let _ = simplify::simplify_with_limits(&expression, context.limits());
+            let _ = derivative::derive_with_limits(&expression, "x", context.limits());
+        }
+    }
+    let _ = context.evaluate_str(input);
+    let _ = document::evaluate_document_in(input, &mut context);
+});
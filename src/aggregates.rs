@@ -0,0 +1,233 @@
+//! Functions over a collection of values, given either as a list, `sum([1, 2, 3])`,
+//! or as separate arguments, `sum(1, 2, 3)`
+
+use std::cmp::Ordering;
+
+use crate::functions::{Builtin, FunctionRegistry};
+use crate::value::Value;
+
+pub fn register(registry: &mut FunctionRegistry) {
+    let functions = [
+        Builtin::new("sum", 0.., |arguments| sum(spread(arguments)?)).doc("Sum of the values"),
+        Builtin::new("prod", 0.., |arguments| product(spread(arguments)?))
+            .doc("Product of the values"),
+        Builtin::new("mean", 0.., |arguments| mean(values(arguments)?))
+            .doc("Arithmetic mean of the values"),
+        Builtin::new("median", 0.., |arguments| median(values(arguments)?))
+            .doc("Middle value, or the mean of the two middle values"),
+        Builtin::new("min", 0.., |arguments| {
+            extreme(values(arguments)?, Ordering::Less)
+        })
+        .doc("Smallest value"),
+        Builtin::new("max", 0.., |arguments| {
+            extreme(values(arguments)?, Ordering::Greater)
+        })
+        .doc("Largest value"),
+        Builtin::new("stdev", 0.., |arguments| {
+            let values = values(arguments)?;
+            let template = values[0].clone();
+            let variance = variance(values)?;
+            template.with_magnitude(variance.magnitude()?.sqrt())
+        })
+        .doc("Sample standard deviation"),
+        Builtin::new("variance", 0.., |arguments| variance(values(arguments)?))
+            .doc("Sample variance"),
+        Builtin::new("count", 0.., |arguments| {
+            let count = arguments.iter().map(|argument| match argument {
+                Value::List(elements) => elements.len(),
+                _ => 1,
+            });
+            Ok(Value::Number(count.sum::<usize>() as f64))
+        })
+        .doc("Number of values"),
+    ];
+    for function in functions {
+        registry.register(function);
+    }
+}
+
+/// The arguments with lists spread, so `sum([1, 2], 3)` is the same as `sum(1, 2, 3)`,
+/// failing on booleans even where a single value would be returned as it is
+fn spread(arguments: &[Value]) -> Result<Vec<Value>, &'static str> {
+    let values: Vec<Value> = arguments
+        .iter()
+        .flat_map(|argument| match argument {
+            Value::List(elements) => elements.as_slice(),
+            value => std::slice::from_ref(value),
+        })
+        .cloned()
+        .collect();
+    // Lists within lists are rows of a matrix, aggregated element by element
+    for value in &values {
+        if !matches!(value, Value::List(_)) {
+            value.magnitude()?;
+        }
+    }
+    Ok(values)
+}
+
+/// The spread arguments, of which there must be at least one
+fn values(arguments: &[Value]) -> Result<Vec<Value>, &'static str> {
+    let values = spread(arguments)?;
+    if values.is_empty() {
+        return Err("Aggregate of no values");
+    }
+    Ok(values)
+}
+
+/// The sum of the values, where the empty sum is zero, as for `sum(k, 1, 0, k)`
+fn sum(values: Vec<Value>) -> Result<Value, &'static str> {
+    let mut values = values.into_iter();
+    let Some(first) = values.next() else {
+        return Ok(Value::Number(0.0));
+    };
+    values.try_fold(first, Value::checked_add)
+}
+
+/// The product of the values, where the empty product is one
+fn product(values: Vec<Value>) -> Result<Value, &'static str> {
+    let mut values = values.into_iter();
+    let Some(first) = values.next() else {
+        return Ok(Value::Number(1.0));
+    };
+    values.try_fold(first, Value::checked_mul)
+}
+
+fn mean(values: Vec<Value>) -> Result<Value, &'static str> {
+    let count = values.len() as f64;
+    sum(values)?.checked_div(Value::Number(count))
+}
+
+/// The middle value, or the mean of the two middle values for an even count
+fn median(values: Vec<Value>) -> Result<Value, &'static str> {
+    let mut keyed: Vec<(f64, Value)> = Vec::with_capacity(values.len());
+    for value in values {
+        if let Some((_, first)) = keyed.first() {
+            // Fails for mismatched units, so that the magnitudes are comparable
+            first.compare(&value)?;
+        }
+        let magnitude = value.magnitude()?;
+        if magnitude.is_nan() {
+            return Err("Median of a value that is not a number");
+        }
+        keyed.push((magnitude, value));
+    }
+    keyed.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    let mut values: Vec<Value> = keyed.into_iter().map(|(_, value)| value).collect();
+
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        Ok(values.swap_remove(middle))
+    } else {
+        mean(values.drain(middle - 1..=middle).collect())
+    }
+}
+
+/// The smallest value for `Ordering::Less`, the largest for `Ordering::Greater`
+fn extreme(values: Vec<Value>, wanted: Ordering) -> Result<Value, &'static str> {
+    let mut values = values.into_iter();
+    let mut extreme = values.next().ok_or("Aggregate of no values")?;
+    for value in values {
+        if value.compare(&extreme)? == Some(wanted) {
+            extreme = value;
+        }
+    }
+    Ok(extreme)
+}
+
+/// The sample variance, dividing by one less than the number of values
+fn variance(values: Vec<Value>) -> Result<Value, &'static str> {
+    if values.len() < 2 {
+        return Err("Variance needs at least two values");
+    }
+    let degrees_of_freedom = Value::Number((values.len() - 1) as f64);
+    let mean = mean(values.clone())?;
+    let squared_deviations = values
+        .into_iter()
+        .map(|value| {
+            value
+                .checked_sub(mean.clone())?
+                .checked_pow(Value::Number(2.0))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    sum(squared_deviations)?.checked_div(degrees_of_freedom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(name: &str, arguments: Vec<Value>) -> Result<Value, &'static str> {
+        let mut registry = FunctionRegistry::empty();
+        register(&mut registry);
+        registry.call(name, &arguments)
+    }
+
+    fn numbers(values: &[f64]) -> Vec<Value> {
+        values.iter().map(|value| Value::Number(*value)).collect()
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let values = || numbers(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+        let cases = [
+            ("sum", 40.0),
+            ("prod", 201600.0),
+            ("mean", 5.0),
+            ("median", 4.5),
+            ("min", 2.0),
+            ("max", 9.0),
+            ("count", 8.0),
+            ("variance", 32.0 / 7.0),
+            ("stdev", (32.0f64 / 7.0).sqrt()),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                aggregate(name, values()).unwrap(),
+                Value::Number(expected),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_arguments_are_spread() {
+        let arguments = vec![
+            Value::List(numbers(&[1.0, 2.0])),
+            Value::Number(3.0),
+            Value::List(vec![]),
+        ];
+        assert_eq!(aggregate("sum", arguments).unwrap(), Value::Number(6.0));
+    }
+
+    #[test]
+    fn test_aggregate_errors() {
+        assert_eq!(aggregate("count", vec![]).unwrap(), Value::Number(0.0));
+        assert_eq!(aggregate("sum", vec![]).unwrap(), Value::Number(0.0));
+        assert_eq!(
+            aggregate("prod", vec![Value::List(vec![])]).unwrap(),
+            Value::Number(1.0)
+        );
+        assert!(aggregate("mean", vec![]).is_err());
+        assert!(aggregate("min", vec![Value::List(vec![])]).is_err());
+        assert!(aggregate("variance", numbers(&[1.0])).is_err());
+        assert!(aggregate("max", vec![Value::Number(1.0), Value::Bool(true)]).is_err());
+        // Sorting needs a total order, which NaN would break
+        let mut with_nan = numbers(&[1.0; 40]);
+        with_nan[20] = Value::Number(f64::NAN);
+        assert_eq!(
+            aggregate("median", with_nan),
+            Err("Median of a value that is not a number")
+        );
+        // A single boolean is rejected like one among numbers
+        for name in ["sum", "prod", "min", "median"] {
+            assert_eq!(
+                aggregate(name, vec![Value::Bool(true)]),
+                Err("Expected a number, found a boolean"),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,352 @@
+//! Evaluation of a formula over many rows of inputs at once, e.g. over CSV columns
+//!
+//! Formulas of numbers are run an instruction at a time over chunks of whole columns,
+//! so that the inner loops are simple enough for the compiler to vectorize. Rows that
+//! fail on that path, and formulas with conditions, are evaluated row by row.
+//!
+//! ```
+//! use culator::batch::evaluate_batch;
+//! use culator::{lexer, parser};
+//!
+//! let tokens = lexer::lex("price * quantity").unwrap();
+//! let formula = parser::parse(&tokens).unwrap();
+//! let totals = evaluate_batch(&formula, &[("price", &[2.5, 4.0]), ("quantity", &[4.0, 0.5])]);
+//! assert_eq!(totals, Ok(vec![10.0, 2.0]));
+//! ```
+
+use crate::compile::{compile_in, CompiledExpr, Instruction};
+use crate::context::Context;
+use crate::parser::Expression;
+use crate::special;
+use crate::value::Value;
+
+/// Rows evaluated together, small enough for intermediate columns to stay in cache
+const CHUNK_SIZE: usize = 1024;
+
+/// Evaluates `expression` for each row of the named columns, which must be of equal
+/// length
+///
+/// Names that are not columns resolve as in [`crate::evaluator::evaluate`]. Rows that
+/// fail to evaluate, e.g. on a division by zero, are NaN.
+pub fn evaluate_batch(
+    expression: &Expression,
+    columns: &[(&str, &[f64])],
+) -> Result<Vec<f64>, &'static str> {
+    evaluate_batch_in(expression, columns, &Context::default())
+}
+
+/// Evaluates `expression` for each row of the named columns, resolving other names
+/// against `context`
+pub fn evaluate_batch_in(
+    expression: &Expression,
+    columns: &[(&str, &[f64])],
+    context: &Context,
+) -> Result<Vec<f64>, &'static str> {
+    let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+    let compiled = compile_in(expression, &names, context)?;
+    let columns: Vec<&[f64]> = columns.iter().map(|(_, values)| *values).collect();
+    compiled.evaluate_batch(&columns)
+}
+
+impl CompiledExpr {
+    /// Evaluates for each row of the columns, given in the order of the parameters
+    ///
+    /// Rows that fail to evaluate are NaN, as in [`evaluate_batch`].
+    pub fn evaluate_batch(&self, columns: &[&[f64]]) -> Result<Vec<f64>, &'static str> {
+        if columns.len() != self.parameters().len() {
+            return Err("Wrong number of function arguments");
+        }
+        let rows = columns.first().map_or(0, |values| values.len());
+        if columns.iter().any(|values| values.len() != rows) {
+            return Err("Columns differ in length");
+        }
+
+        let by_column = self.instructions.iter().all(is_columnar);
+        let mut results = Vec::with_capacity(rows);
+        for start in (0..rows).step_by(CHUNK_SIZE) {
+            let end = (start + CHUNK_SIZE).min(rows);
+            let chunk: Vec<&[f64]> = columns.iter().map(|column| &column[start..end]).collect();
+            let mut fallback = vec![!by_column; end - start];
+            if by_column {
+                let values = execute_columns(self, &chunk, &mut fallback);
+                results.extend(values.values(end - start));
+            } else {
+                results.resize(end, f64::NAN);
+            }
+            for (row, _) in fallback
+                .iter()
+                .enumerate()
+                .filter(|(_, fallback)| **fallback)
+            {
+                results[start + row] = evaluate_row(self, &chunk, row);
+            }
+        }
+        Ok(results)
+    }
+}
+
+fn evaluate_row(compiled: &CompiledExpr, columns: &[&[f64]], row: usize) -> f64 {
+    let arguments: Vec<f64> = columns.iter().map(|column| column[row]).collect();
+    compiled.evaluate(&arguments).unwrap_or(f64::NAN)
+}
+
+/// Whether the instruction works on numbers alone, without branching or limits to check
+fn is_columnar(instruction: &Instruction) -> bool {
+    if let Instruction::Call(builtin, _) = instruction {
+        return !builtin.integer_arguments;
+    }
+    matches!(
+        instruction,
+        Instruction::Constant(Value::Number(_))
+            | Instruction::Load(_)
+            | Instruction::Store(_)
+            | Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::Power
+            | Instruction::Negate
+            | Instruction::Factorial
+            | Instruction::DoubleFactorial
+    )
+}
+
+/// Values of a chunk of rows, where constants are not repeated for every row
+#[derive(Debug, Clone)]
+enum Column<'a> {
+    Constant(f64),
+    Borrowed(&'a [f64]),
+    Owned(Vec<f64>),
+}
+
+impl Column<'_> {
+    fn get(&self, row: usize) -> f64 {
+        match self {
+            Column::Constant(value) => *value,
+            Column::Borrowed(values) => values[row],
+            Column::Owned(values) => values[row],
+        }
+    }
+
+    fn values(self, rows: usize) -> Vec<f64> {
+        match self {
+            Column::Constant(value) => vec![value; rows],
+            Column::Borrowed(values) => values.to_vec(),
+            Column::Owned(values) => values,
+        }
+    }
+
+    fn map(&self, operation: impl Fn(f64) -> f64) -> Column<'static> {
+        match self.slice() {
+            None => Column::Constant(operation(self.get(0))),
+            Some(values) => Column::Owned(values.iter().map(|x| operation(*x)).collect()),
+        }
+    }
+
+    fn slice(&self) -> Option<&[f64]> {
+        match self {
+            Column::Constant(_) => None,
+            Column::Borrowed(values) => Some(values),
+            Column::Owned(values) => Some(values),
+        }
+    }
+}
+
+/// Applies an operation row by row, in loops over plain slices
+fn binary<'a>(
+    left: &Column<'a>,
+    right: &Column<'a>,
+    operation: impl Fn(f64, f64) -> f64,
+) -> Column<'a> {
+    let values = match (left.slice(), right.slice()) {
+        (None, None) => return Column::Constant(operation(left.get(0), right.get(0))),
+        (Some(left), None) => {
+            let right = right.get(0);
+            left.iter().map(|x| operation(*x, right)).collect()
+        }
+        (None, Some(right)) => {
+            let left = left.get(0);
+            right.iter().map(|y| operation(left, *y)).collect()
+        }
+        (Some(left), Some(right)) => left
+            .iter()
+            .zip(right)
+            .map(|(x, y)| operation(*x, *y))
+            .collect(),
+    };
+    Column::Owned(values)
+}
+
+/// Runs the instructions over a chunk of columns, marking rows that fail for
+/// evaluation row by row
+fn execute_columns<'a>(
+    compiled: &CompiledExpr,
+    columns: &[&'a [f64]],
+    fallback: &mut [bool],
+) -> Column<'a> {
+    let rows = fallback.len();
+    let mut slots: Vec<Column> = columns
+        .iter()
+        .map(|column| Column::Borrowed(column))
+        .collect();
+    slots.resize(compiled.slot_count, Column::Constant(0.0));
+    let mut stack: Vec<Column> = vec![];
+
+    for instruction in &compiled.instructions {
+        let result = match instruction {
+            Instruction::Constant(Value::Number(value)) => Column::Constant(*value),
+            Instruction::Load(slot) => slots[*slot].clone(),
+            Instruction::Store(slot) => {
+                slots[*slot] = pop(&mut stack);
+                continue;
+            }
+            Instruction::Negate => pop(&mut stack).map(|x| -x),
+            Instruction::Factorial | Instruction::DoubleFactorial => {
+                let operand = pop(&mut stack);
+                let function = match instruction {
+                    Instruction::Factorial => special::factorial,
+                    _ => special::double_factorial,
+                };
+                let values = (0..rows)
+                    .map(|row| {
+                        function(operand.get(row)).unwrap_or_else(|_| {
+                            fallback[row] = true;
+                            f64::NAN
+                        })
+                    })
+                    .collect();
+                Column::Owned(values)
+            }
+            Instruction::Call(builtin, count) => {
+                let arguments = stack.split_off(stack.len() - count);
+                let mut values = Vec::with_capacity(rows);
+                let mut row_arguments = Vec::with_capacity(*count);
+                for (row, fallback) in fallback.iter_mut().enumerate() {
+                    row_arguments.clear();
+                    row_arguments.extend(
+                        arguments
+                            .iter()
+                            .map(|column| Value::Number(column.get(row))),
+                    );
+                    let value = builtin
+                        .call(&row_arguments)
+                        .and_then(|value| value.as_number());
+                    values.push(value.unwrap_or_else(|_| {
+                        *fallback = true;
+                        f64::NAN
+                    }));
+                }
+                Column::Owned(values)
+            }
+            _ => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                match instruction {
+                    Instruction::Add => binary(&left, &right, |x, y| x + y),
+                    Instruction::Subtract => binary(&left, &right, |x, y| x - y),
+                    Instruction::Multiply => binary(&left, &right, |x, y| x * y),
+                    Instruction::Power => binary(&left, &right, f64::powf),
+                    Instruction::Divide => {
+                        for (row, fallback) in fallback.iter_mut().enumerate() {
+                            *fallback |= right.get(row) == 0.0;
+                        }
+                        binary(&left, &right, |x, y| x / y)
+                    }
+                    _ => unreachable!("checked by is_columnar"),
+                }
+            }
+        };
+        stack.push(result);
+    }
+    pop(&mut stack)
+}
+
+fn pop<'a>(stack: &mut Vec<Column<'a>>) -> Column<'a> {
+    stack.pop().expect("operands are on the stack")
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{evaluator, lexer, parser, strategies};
+
+    fn parse(input: &str) -> Expression {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    /// Each row must match evaluating it on its own, with errors as NaN
+    fn assert_matches_rows(expression: &Expression, x: &[f64], y: &[f64]) {
+        let results = evaluate_batch(expression, &[("x", x), ("y", y)]).unwrap();
+        assert_eq!(results.len(), x.len());
+        for (row, result) in results.iter().enumerate() {
+            let context = Context::new()
+                .with_variables([("x", Value::Number(x[row])), ("y", Value::Number(y[row]))]);
+            let expected = evaluator::evaluate_in(expression, &context)
+                .and_then(|value| value.as_number())
+                .unwrap_or(f64::NAN);
+            assert!(
+                result.to_bits() == expected.to_bits() || (result.is_nan() && expected.is_nan()),
+                "{} at row {}: {} != {}",
+                expression,
+                row,
+                result,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_row_evaluation() {
+        // More rows than a chunk, with zeros for divisions and negatives for roots
+        let x: Vec<f64> = (0..2500).map(|i| (i % 7) as f64 - 2.0).collect();
+        let y: Vec<f64> = (0..2500).map(|i| (i % 5) as f64 * 0.5).collect();
+        let cases = [
+            "x * y + 2 x - 1",
+            "x / y",
+            "(x + 1)! + y^x",
+            "sqrt(x) + max(x, y)",
+            "x^2 == 1",
+            "if(x > 0, x, -x) * y",
+            "hypot(x, y) / 2 km in m",
+            "x km in m",
+            "7",
+        ];
+        for input in cases {
+            assert_matches_rows(&parse(input), &x, &y);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_matches_row_evaluation(
+            expression in strategies::number(),
+            rows in prop::collection::vec((-4i8..5, -4i8..5), 0..40),
+        ) {
+            let (x, y): (Vec<f64>, Vec<f64>) = rows
+                .into_iter()
+                .map(|(x, y)| (f64::from(x) * 0.5, f64::from(y)))
+                .unzip();
+            assert_matches_rows(&expression, &x, &y);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let expression = parse("x + z");
+        assert_eq!(
+            evaluate_batch(&expression, &[("x", &[1.0])]),
+            Err("Unknown name")
+        );
+        let expression = parse("x + y");
+        assert_eq!(
+            evaluate_batch(&expression, &[("x", &[1.0]), ("y", &[1.0, 2.0])]),
+            Err("Columns differ in length")
+        );
+        assert_eq!(
+            evaluate_batch(&expression, &[("x", &[]), ("y", &[])]),
+            Ok(vec![])
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Numeric integration and limits for `integrate` and `limit`
+
+use std::cell::Cell;
+
+use crate::limits;
+use crate::solve::RealFunction;
+
+/// Nodes of the 15-point Kronrod rule on [-1, 1], from the outside in; every other
+/// node is also one of the 7-point Gauss rule
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_5,
+    0.0,
+];
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22,
+    0.063_092_092_629_978_55,
+    0.104_790_010_322_250_2,
+    0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_4,
+    0.204_432_940_075_298_9,
+    0.209_482_141_084_727_8,
+];
+/// Weights of the 7-point Gauss rule, for the odd-numbered Kronrod nodes
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_7,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+
+const ABSOLUTE_TOLERANCE: f64 = 1e-10;
+const RELATIVE_TOLERANCE: f64 = 1e-10;
+const MAX_SUBDIVISIONS: usize = 1000;
+/// Intervals narrower than this fraction of the whole are not subdivided further
+const MIN_WIDTH: f64 = 1e-9;
+
+/// Step sizes `limit` approaches the point with, each half the previous one
+const LIMIT_STEPS: usize = 12;
+const LIMIT_TOLERANCE: f64 = 1e-8;
+
+pub const INACCURATE_INTEGRAL: &str = "Integral did not reach the requested accuracy";
+pub const DISCONTINUOUS_INTEGRAND: &str =
+    "Integrand may be discontinuous or singular, the integral may be inaccurate";
+
+/// The result of numeric integration
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Integral {
+    pub value: f64,
+    /// Estimated absolute error of the value
+    pub error: f64,
+    /// Whether subintervals had to become too narrow to subdivide, which happens
+    /// around jumps and singularities, or the integrand failed at some point
+    pub discontinuous: bool,
+}
+
+impl Integral {
+    /// Warnings about the accuracy of the value, if any
+    pub fn warning(&self) -> Option<&'static str> {
+        if self.discontinuous {
+            Some(DISCONTINUOUS_INTEGRAND)
+        } else if self.error > tolerance(self.value) {
+            Some(INACCURATE_INTEGRAL)
+        } else {
+            None
+        }
+    }
+}
+
+fn tolerance(value: f64) -> f64 {
+    ABSOLUTE_TOLERANCE.max(RELATIVE_TOLERANCE * value.abs())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: f64,
+    end: f64,
+    value: f64,
+    error: f64,
+}
+
+impl Segment {
+    /// Integrates over the segment with the Gauss-Kronrod 7-15 rule
+    fn new(f: RealFunction, start: f64, end: f64) -> Result<Segment, &'static str> {
+        let center = 0.5 * (start + end);
+        let half_width = 0.5 * (end - start);
+        let mut kronrod = 0.0;
+        let mut gauss = 0.0;
+        for (i, (node, weight)) in KRONROD_NODES.iter().zip(KRONROD_WEIGHTS).enumerate() {
+            let values = if *node == 0.0 {
+                f(center)?
+            } else {
+                f(center - half_width * node)? + f(center + half_width * node)?
+            };
+            kronrod += weight * values;
+            if i % 2 == 1 {
+                gauss += GAUSS_WEIGHTS[i / 2] * values;
+            }
+        }
+        Ok(Segment {
+            start,
+            end,
+            value: kronrod * half_width,
+            error: ((kronrod - gauss) * half_width).abs(),
+        })
+    }
+}
+
+/// The integral of `f` from `start` to `end`, subdividing wherever the error is largest
+///
+/// Points where `f` fails, such as the singularity of `1 / x` at 0, count as zero and
+/// mark the integrand as discontinuous, unless `f` fails everywhere it is sampled.
+pub fn integrate(f: RealFunction, start: f64, end: f64) -> Result<Integral, &'static str> {
+    if !start.is_finite() || !end.is_finite() {
+        return Err("Integration bounds must be finite");
+    }
+    let failure = Cell::new(None);
+    let defined = Cell::new(false);
+    let f = &|x: f64| match f(x) {
+        Ok(value) => {
+            defined.set(true);
+            Ok(value)
+        }
+        Err(error) if limits::is_exhausted(error) => Err(error),
+        Err(error) => {
+            failure.set(failure.get().or(Some(error)));
+            Ok(0.0)
+        }
+    };
+    let min_width = MIN_WIDTH * (end - start).abs();
+    let mut segments = vec![Segment::new(f, start, end)?];
+    // Segments too narrow to subdivide, kept apart so they are not picked again
+    let mut narrowest = vec![];
+    for _ in 0..MAX_SUBDIVISIONS {
+        let value: f64 = segments.iter().chain(&narrowest).map(|s| s.value).sum();
+        let error: f64 = segments.iter().chain(&narrowest).map(|s| s.error).sum();
+        if error <= tolerance(value) {
+            break;
+        }
+        let Some((worst, _)) = segments
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.error.total_cmp(&b.error))
+        else {
+            break;
+        };
+        let segment = segments.swap_remove(worst);
+        if (segment.end - segment.start).abs() < min_width {
+            narrowest.push(segment);
+            continue;
+        }
+        let middle = 0.5 * (segment.start + segment.end);
+        segments.push(Segment::new(f, segment.start, middle)?);
+        segments.push(Segment::new(f, middle, segment.end)?);
+    }
+
+    if let (false, Some(error)) = (defined.get(), failure.get()) {
+        return Err(error);
+    }
+    let all = || segments.iter().chain(&narrowest);
+    let value: f64 = all().map(|s| s.value).sum();
+    if !value.is_finite() {
+        return Err("Integral does not converge");
+    }
+    Ok(Integral {
+        value,
+        error: all().map(|s| s.error).sum(),
+        discontinuous: !narrowest.is_empty() || failure.get().is_some(),
+    })
+}
+
+/// The limit of `f` at `point`, extrapolated from values approaching it from both sides
+pub fn limit(f: RealFunction, point: f64) -> Result<f64, &'static str> {
+    if !point.is_finite() {
+        return Err("Limit point must be finite");
+    }
+    let step = 0.125 * point.abs().max(1.0);
+    let left = one_sided_limit(f, point, -step)?;
+    let right = one_sided_limit(f, point, step)?;
+    if (left - right).abs() > LIMIT_TOLERANCE * left.abs().max(right.abs()).max(1.0) {
+        return Err("Left and right limits differ");
+    }
+    Ok(0.5 * (left + right))
+}
+
+/// Richardson extrapolation of `f(point + step / 2^k)` to `k = ∞`
+fn one_sided_limit(f: RealFunction, point: f64, step: f64) -> Result<f64, &'static str> {
+    let mut previous_row: Vec<f64> = vec![];
+    let mut best: Option<(f64, f64)> = None;
+    for k in 0..LIMIT_STEPS {
+        let mut row = vec![f(point + step / 2f64.powi(k as i32))?];
+        for j in 1..=k {
+            let factor = 2f64.powi(j as i32) - 1.0;
+            row.push(row[j - 1] + (row[j - 1] - previous_row[j - 1]) / factor);
+        }
+        if k > 0 {
+            let estimate = row[k];
+            let error = (estimate - previous_row[k - 1]).abs();
+            if estimate.is_finite() && best.is_none_or(|(_, best_error)| error < best_error) {
+                best = Some((estimate, error));
+            }
+        }
+        previous_row = row;
+    }
+    match best {
+        Some((estimate, error)) if error <= LIMIT_TOLERANCE * estimate.abs().max(1.0) => {
+            Ok(estimate)
+        }
+        _ => Err("Limit did not converge"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9 * expected.abs().max(1.0),
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_integrate() {
+        let integral = integrate(&|x: f64| Ok(x.sin()), 0.0, PI).unwrap();
+        assert_close(integral.value, 2.0);
+        assert!(integral.error < 1e-10);
+        assert_eq!(integral.warning(), None);
+
+        assert_close(integrate(&|x: f64| Ok(x * x), 0.0, 3.0).unwrap().value, 9.0);
+        assert_close(
+            integrate(&|x: f64| Ok(x * x), 3.0, 0.0).unwrap().value,
+            -9.0,
+        );
+        let gaussian = integrate(&|x: f64| Ok((-x * x).exp()), -10.0, 10.0).unwrap();
+        assert_close(gaussian.value, PI.sqrt());
+        assert_eq!(gaussian.warning(), None);
+    }
+
+    #[test]
+    fn test_integrate_discontinuous() {
+        let step = integrate(&|x: f64| Ok(x.floor()), 0.0, 2.5).unwrap();
+        assert!((step.value - 2.0).abs() < 1e-6);
+        assert_eq!(step.warning(), Some(DISCONTINUOUS_INTEGRAND));
+
+        let singular = integrate(&|x: f64| Ok(1.0 / x.sqrt()), 0.0, 1.0).unwrap();
+        assert!((singular.value - 2.0).abs() < 1e-3);
+        assert_eq!(singular.warning(), Some(DISCONTINUOUS_INTEGRAND));
+
+        // A sample on the singularity itself fails
+        let reciprocal = |x: f64| match x {
+            0.0 => Err("Division by zero"),
+            x => Ok(1.0 / x),
+        };
+        let odd = integrate(&reciprocal, -1.0, 1.0).unwrap();
+        assert!(odd.value.abs() < 1e-9);
+        assert_eq!(odd.warning(), Some(DISCONTINUOUS_INTEGRAND));
+        assert_eq!(
+            integrate(&|_| Err("Unknown name"), 0.0, 1.0),
+            Err("Unknown name")
+        );
+    }
+
+    #[test]
+    fn test_limit() {
+        assert_close(limit(&|x: f64| Ok(x.sin() / x), 0.0).unwrap(), 1.0);
+        assert_close(limit(&|x: f64| Ok((x.exp() - 1.0) / x), 0.0).unwrap(), 1.0);
+        assert_close(
+            limit(&|x: f64| Ok((x * x - 4.0) / (x - 2.0)), 2.0).unwrap(),
+            4.0,
+        );
+        assert_eq!(
+            limit(&|x: f64| Ok(x.signum()), 0.0),
+            Err("Left and right limits differ")
+        );
+        assert_eq!(
+            limit(&|x: f64| Ok(1.0 / x), 0.0),
+            Err("Limit did not converge")
+        );
+    }
+}
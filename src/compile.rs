@@ -0,0 +1,883 @@
+//! Compilation of expressions into flat bytecode, for evaluating the same formula many
+//! times with different parameter values
+//!
+//! Names, units and functions are resolved once when compiling, user-defined functions
+//! are inlined, and constant parts such as `2 km` are folded, so running the bytecode
+//! needs no lookups. Bytecode over plain numbers and booleans runs without allocating.
+//! Special forms without bytecode of their own, such as `integrate`, fall back to the
+//! tree-walking evaluator.
+//!
+//! ```
+//! use culator::compile::compile;
+//! use culator::{lexer, parser};
+//!
+//! let tokens = lexer::lex("s * exp(r * t)").unwrap();
+//! let formula = compile(&parser::parse(&tokens).unwrap(), &["s", "r", "t"]).unwrap();
+//! assert_eq!(formula.evaluate(&[100.0, 0.0, 1.0]), Ok(100.0));
+//! ```
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::context::Context;
+use crate::evaluator::{self, evaluate_in};
+use crate::functions::Builtin;
+use crate::limits::Limits;
+use crate::parser::Expression;
+use crate::special;
+use crate::value::Value;
+
+/// An expression compiled by [`compile`] or [`compile_in`]
+#[derive(Debug, Clone)]
+pub struct CompiledExpr {
+    pub(crate) instructions: Vec<Instruction>,
+    parameters: Vec<String>,
+    /// Parameters followed by the arguments of inlined function calls
+    pub(crate) slot_count: usize,
+    /// The context compiled in, for the special forms left to the tree walker
+    context: Context,
+    /// Whether every instruction can run on the fast path over numbers and booleans
+    scalar: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Instruction {
+    Constant(Value),
+    Load(usize),
+    /// Pops a value into a slot
+    Store(usize),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+    Negate,
+    Not,
+    Factorial,
+    DoubleFactorial,
+    Convert,
+    Range,
+    Compare(fn(Ordering) -> bool),
+    Equal,
+    NotEqual,
+    ApproximatelyEqual,
+    /// Calls a built-in function with the given number of arguments from the stack
+    Call(Arc<Builtin>, usize),
+    List(usize),
+    Jump(usize),
+    /// Pops a boolean and jumps when it is the given one
+    JumpIf(bool, usize),
+    Fail(&'static str),
+    /// Evaluates with the tree walker, binding names to the values in their slots
+    Interpret(Box<Expression>, Vec<(String, usize)>),
+}
+
+/// Most values on the stack and in slots of the fast path over numbers and booleans
+const MAX_SCALARS: usize = 32;
+/// Most arguments of built-in functions called on the fast path
+const MAX_SCALAR_ARGUMENTS: usize = 8;
+
+/// A number or boolean, which the fast path keeps without allocating
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    Number(f64),
+    Bool(bool),
+}
+
+impl Scalar {
+    fn of(value: &Value) -> Option<Scalar> {
+        match value {
+            Value::Number(value) => Some(Scalar::Number(*value)),
+            Value::Bool(value) => Some(Scalar::Bool(*value)),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Scalar::Number(value) => Value::Number(value),
+            Scalar::Bool(value) => Value::Bool(value),
+        }
+    }
+}
+
+impl Instruction {
+    /// Whether the fast path over numbers and booleans can run the instruction
+    fn is_scalar(&self) -> bool {
+        match self {
+            Instruction::Constant(value) => Scalar::of(value).is_some(),
+            Instruction::Call(_, count) => *count <= MAX_SCALAR_ARGUMENTS,
+            Instruction::Convert
+            | Instruction::Range
+            | Instruction::List(_)
+            | Instruction::Interpret(..) => false,
+            _ => true,
+        }
+    }
+
+    /// Whether the instruction always gives the same result for the same operands
+    fn is_foldable(&self) -> bool {
+        match self {
+            Instruction::Load(_)
+            | Instruction::Store(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpIf(..)
+            | Instruction::Fail(_)
+            | Instruction::Interpret(..) => false,
+            Instruction::Call(builtin, _) => builtin.pure,
+            _ => true,
+        }
+    }
+}
+
+/// Compiles an expression whose free names are the given parameters, resolving other
+/// names and functions as [`evaluator::evaluate_value`] does
+pub fn compile(expression: &Expression, parameters: &[&str]) -> Result<CompiledExpr, &'static str> {
+    compile_in(expression, parameters, &Context::default())
+}
+
+/// Compiles an expression against the units, variables and functions of `context`
+///
+/// Parameters take precedence over names in the context. Later changes to the context
+/// do not affect the compiled expression.
+pub fn compile_in(
+    expression: &Expression,
+    parameters: &[&str],
+    context: &Context,
+) -> Result<CompiledExpr, &'static str> {
+    let mut compiler = Compiler {
+        context,
+        instructions: vec![],
+        scope: parameters
+            .iter()
+            .enumerate()
+            .map(|(slot, name)| (name.to_string(), slot))
+            .collect(),
+        slot_count: parameters.len(),
+        inlining: vec![],
+    };
+    compiler.compile(expression)?;
+    let scalar = compiler.slot_count <= MAX_SCALARS
+        && compiler.instructions.iter().all(Instruction::is_scalar);
+    Ok(CompiledExpr {
+        instructions: compiler.instructions,
+        parameters: parameters.iter().map(|name| name.to_string()).collect(),
+        slot_count: compiler.slot_count,
+        context: context.clone(),
+        scalar,
+    })
+}
+
+impl CompiledExpr {
+    pub fn parameters(&self) -> &[String] {
+        &self.parameters
+    }
+
+    /// Evaluates with the parameters bound to the given numbers, in order
+    pub fn evaluate(&self, arguments: &[f64]) -> Result<f64, &'static str> {
+        self.check_arguments(arguments.len())?;
+        let scalars = arguments.iter().map(|x| Some(Scalar::Number(*x)));
+        if let Some(result) = self.run_scalar(scalars) {
+            return result?.as_number();
+        }
+        let arguments: Vec<Value> = arguments.iter().map(|x| Value::Number(*x)).collect();
+        self.run(&arguments)?.as_number()
+    }
+
+    /// Evaluates with the parameters bound to the given values, in order
+    pub fn evaluate_value(&self, arguments: &[Value]) -> Result<Value, &'static str> {
+        self.check_arguments(arguments.len())?;
+        match self.run_scalar(arguments.iter().map(Scalar::of)) {
+            Some(result) => result,
+            None => self.run(arguments),
+        }
+    }
+
+    fn check_arguments(&self, count: usize) -> Result<(), &'static str> {
+        if count != self.parameters.len() {
+            return Err("Wrong number of function arguments");
+        }
+        Ok(())
+    }
+
+    fn run(&self, arguments: &[Value]) -> Result<Value, &'static str> {
+        let mut slots = Vec::with_capacity(self.slot_count);
+        slots.extend_from_slice(arguments);
+        slots.resize(self.slot_count, Value::Bool(false));
+        execute(&self.instructions, &mut slots, &self.context)
+    }
+
+    /// Runs on the fast path, or `None` when an argument or intermediate value needs
+    /// the general one
+    fn run_scalar(
+        &self,
+        arguments: impl Iterator<Item = Option<Scalar>>,
+    ) -> Option<Result<Value, &'static str>> {
+        if !self.scalar {
+            return None;
+        }
+        let mut slots = [Scalar::Bool(false); MAX_SCALARS];
+        for (slot, argument) in slots.iter_mut().zip(arguments) {
+            *slot = argument?;
+        }
+        execute_scalar(&self.instructions, &mut slots, self.context.limits())
+    }
+
+    /// Warnings raised by evaluations since they were last taken
+    pub fn take_warnings(&self) -> Vec<&'static str> {
+        self.context.take_warnings()
+    }
+}
+
+struct Compiler<'a> {
+    context: &'a Context,
+    instructions: Vec<Instruction>,
+    /// Names bound to slots, innermost last
+    scope: Vec<(String, usize)>,
+    slot_count: usize,
+    /// User-defined functions being inlined, whose recursive calls are left to the tree walker
+    inlining: Vec<String>,
+}
+
+impl Compiler<'_> {
+    /// Compiles an expression, folding it into a constant when it does not depend on
+    /// any parameter
+    fn compile(&mut self, expression: &Expression) -> Result<(), &'static str> {
+        let _nesting = self.context.enter()?;
+        let start = self.instructions.len();
+        self.compile_unfolded(expression)?;
+        self.fold(start);
+        Ok(())
+    }
+
+    /// Folds the instructions from `start` into a constant when they do not depend on any
+    /// parameter
+    fn fold(&mut self, start: usize) {
+        let code = &self.instructions[start..];
+        if code.len() > 1 && code.iter().all(Instruction::is_foldable) {
+            // Errors such as division by zero are left for evaluation to report
+            if let Ok(value) = execute(code, &mut [], self.context) {
+                self.instructions.truncate(start);
+                self.instructions.push(Instruction::Constant(value));
+            }
+        }
+    }
+
+    fn compile_unfolded(&mut self, expression: &Expression) -> Result<(), &'static str> {
+        let instruction = match expression {
+            Expression::NumericLiteral(value) => Instruction::Constant(Value::Number(*value)),
+            Expression::BooleanLiteral(value) => Instruction::Constant(Value::Bool(*value)),
+            Expression::Name(name) => self.resolve(name)?,
+            Expression::Addition(..)
+            | Expression::Subtraction(..)
+            | Expression::Multiplication(..)
+            | Expression::Division(..) => return self.compile_arithmetic(expression),
+            Expression::Exponentiation(left, right) => {
+                self.operands([left, right], Instruction::Power)?
+            }
+            Expression::Minus(operand) => self.operands([operand], Instruction::Negate)?,
+            Expression::Not(operand) => self.operands([operand], Instruction::Not)?,
+            Expression::Factorial(operand) => self.operands([operand], Instruction::Factorial)?,
+            Expression::DoubleFactorial(operand) => {
+                self.operands([operand], Instruction::DoubleFactorial)?
+            }
+            Expression::Conversion(value, target) => {
+                self.operands([value, target], Instruction::Convert)?
+            }
+            Expression::Range(start, end) => self.operands([start, end], Instruction::Range)?,
+            Expression::LessThan(left, right) => {
+                self.operands([left, right], Instruction::Compare(Ordering::is_lt))?
+            }
+            Expression::LessThanOrEqual(left, right) => {
+                self.operands([left, right], Instruction::Compare(Ordering::is_le))?
+            }
+            Expression::GreaterThan(left, right) => {
+                self.operands([left, right], Instruction::Compare(Ordering::is_gt))?
+            }
+            Expression::GreaterThanOrEqual(left, right) => {
+                self.operands([left, right], Instruction::Compare(Ordering::is_ge))?
+            }
+            Expression::Equal(left, right) => self.operands([left, right], Instruction::Equal)?,
+            Expression::NotEqual(left, right) => {
+                self.operands([left, right], Instruction::NotEqual)?
+            }
+            Expression::ApproximatelyEqual(left, right) => {
+                self.operands([left, right], Instruction::ApproximatelyEqual)?
+            }
+            // `and` and `or` skip their right side when the left decides the result
+            Expression::And(left, right) | Expression::Or(left, right) => {
+                let deciding = matches!(expression, Expression::Or(..));
+                self.compile(left)?;
+                let skip_left = self.placeholder();
+                self.compile(right)?;
+                let skip_right = self.placeholder();
+                self.instructions
+                    .push(Instruction::Constant(Value::Bool(!deciding)));
+                let end = self.placeholder();
+                self.patch(
+                    skip_left,
+                    Instruction::JumpIf(deciding, self.instructions.len()),
+                );
+                self.patch(
+                    skip_right,
+                    Instruction::JumpIf(deciding, self.instructions.len()),
+                );
+                self.instructions
+                    .push(Instruction::Constant(Value::Bool(deciding)));
+                self.patch(end, Instruction::Jump(self.instructions.len()));
+                return Ok(());
+            }
+            Expression::FunctionCall(name, args) => return self.compile_call(name, args),
+            Expression::Labeled(_, expression) => return self.compile(expression),
+            Expression::List(elements) => {
+                for element in elements {
+                    self.compile(element)?;
+                }
+                Instruction::List(elements.len())
+            }
+            Expression::UnitDefinition(..)
+            | Expression::Assignment(..)
+            | Expression::FunctionDefinition(..) => {
+                return Err("Definitions are only allowed at the top level")
+            }
+        };
+        self.instructions.push(instruction);
+        Ok(())
+    }
+
+    /// Compiles a chain in a loop, see [`Expression`], folding its constant prefixes as
+    /// compiling each level would
+    fn compile_arithmetic(&mut self, expression: &Expression) -> Result<(), &'static str> {
+        let start = self.instructions.len();
+        let mut operations = vec![];
+        let mut leftmost = expression;
+        loop {
+            let (left, right, instruction) = match leftmost {
+                Expression::Addition(left, right) => (left, right, Instruction::Add),
+                Expression::Subtraction(left, right) => (left, right, Instruction::Subtract),
+                Expression::Multiplication(left, right) => (left, right, Instruction::Multiply),
+                Expression::Division(left, right) => (left, right, Instruction::Divide),
+                _ => break,
+            };
+            operations.push((right, instruction));
+            leftmost = left;
+        }
+        self.compile(leftmost)?;
+        for (right, instruction) in operations.into_iter().rev() {
+            self.compile(right)?;
+            self.instructions.push(instruction);
+            self.fold(start);
+        }
+        Ok(())
+    }
+
+    /// Compiles operands onto the stack for the given instruction
+    fn operands<const N: usize>(
+        &mut self,
+        operands: [&Expression; N],
+        instruction: Instruction,
+    ) -> Result<Instruction, &'static str> {
+        for operand in operands {
+            self.compile(operand)?;
+        }
+        Ok(instruction)
+    }
+
+    /// Resolves a name to a slot, a variable or a unit, in the evaluator's order
+    fn resolve(&self, name: &str) -> Result<Instruction, &'static str> {
+        if let Some((_, slot)) = self.scope.iter().rev().find(|(bound, _)| bound == name) {
+            return Ok(Instruction::Load(*slot));
+        }
+        if let Some(value) = self.context.variable(name) {
+            return Ok(Instruction::Constant(value.clone()));
+        }
+        match self.context.lookup_unit(name) {
+            Some(unit) => Ok(Instruction::Constant(Value::from_unit(name, &unit))),
+            None => Err("Unknown name"),
+        }
+    }
+
+    fn compile_call(&mut self, name: &str, args: &[Expression]) -> Result<(), &'static str> {
+        match (name, args) {
+            ("if", [condition, then, otherwise]) => {
+                return self.compile_cases(&[(condition, then)], Some(otherwise))
+            }
+            ("if", _) => return Err("if takes a condition and two values"),
+            ("piecewise", _) => {
+                let (pieces, otherwise) = match args.len() % 2 {
+                    0 => (args, None),
+                    _ => (&args[..args.len() - 1], args.last()),
+                };
+                let cases: Vec<_> = pieces
+                    .chunks(2)
+                    .map(|piece| (&piece[0], &piece[1]))
+                    .collect();
+                return self.compile_cases(&cases, otherwise);
+            }
+            _ => {}
+        }
+
+        let recursive = self.inlining.iter().any(|function| function == name);
+        if evaluator::is_special_form(name, args) || recursive {
+            let bindings = self.scope.clone();
+            let call = Expression::FunctionCall(name.to_string(), args.to_vec());
+            self.instructions
+                .push(Instruction::Interpret(Box::new(call), bindings));
+            return Ok(());
+        }
+
+        if let Some(function) = self.context.function(name) {
+            if args.len() != function.parameters.len() {
+                return Err("Wrong number of function arguments");
+            }
+            // Arguments are evaluated in the caller's scope before the parameters are bound
+            let mut bindings = vec![];
+            for (parameter, arg) in function.parameters.iter().zip(args) {
+                self.compile(arg)?;
+                let slot = self.slot_count;
+                self.slot_count += 1;
+                self.instructions.push(Instruction::Store(slot));
+                bindings.push((parameter.clone(), slot));
+            }
+            let scope_length = self.scope.len();
+            self.scope.extend(bindings);
+            self.inlining.push(name.to_string());
+            let body = self.compile(&function.body);
+            self.inlining.pop();
+            self.scope.truncate(scope_length);
+            return body;
+        }
+
+        let builtin = self
+            .context
+            .builtins()
+            .get_shared(name)
+            .ok_or("Unknown function")?
+            .clone();
+        if !builtin.accepts_arguments(args.len()) {
+            return Err("Wrong number of function arguments");
+        }
+        for arg in args {
+            self.compile(arg)?;
+        }
+        self.instructions
+            .push(Instruction::Call(builtin, args.len()));
+        Ok(())
+    }
+
+    /// Compiles the value of the first case whose condition holds, like `piecewise`
+    fn compile_cases(
+        &mut self,
+        cases: &[(&Expression, &Expression)],
+        otherwise: Option<&Expression>,
+    ) -> Result<(), &'static str> {
+        let mut ends = vec![];
+        for (condition, value) in cases {
+            self.compile(condition)?;
+            let next = self.placeholder();
+            self.compile(value)?;
+            ends.push(self.placeholder());
+            self.patch(next, Instruction::JumpIf(false, self.instructions.len()));
+        }
+        match otherwise {
+            Some(otherwise) => self.compile(otherwise)?,
+            None => self
+                .instructions
+                .push(Instruction::Fail("No piecewise condition matched")),
+        }
+        for end in ends {
+            self.patch(end, Instruction::Jump(self.instructions.len()));
+        }
+        Ok(())
+    }
+
+    /// Reserves room for a jump whose target is not known yet
+    fn placeholder(&mut self) -> usize {
+        self.instructions.push(Instruction::Fail("Unpatched jump"));
+        self.instructions.len() - 1
+    }
+
+    fn patch(&mut self, index: usize, jump: Instruction) {
+        self.instructions[index] = jump;
+    }
+}
+
+fn execute(
+    instructions: &[Instruction],
+    slots: &mut [Value],
+    context: &Context,
+) -> Result<Value, &'static str> {
+    let mut stack: Vec<Value> = Vec::with_capacity(8);
+    let mut next = 0;
+    while let Some(instruction) = instructions.get(next) {
+        next += 1;
+        let result = match instruction {
+            Instruction::Constant(value) => value.clone(),
+            Instruction::Load(slot) => slots[*slot].clone(),
+            Instruction::Store(slot) => {
+                slots[*slot] = pop(&mut stack);
+                continue;
+            }
+            Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::Power
+            | Instruction::Convert
+            | Instruction::Range
+            | Instruction::Compare(_)
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::ApproximatelyEqual => {
+                let right = pop(&mut stack);
+                let left = pop(&mut stack);
+                match (instruction, &left, &right) {
+                    // Plain numbers skip the checks for units and lists
+                    (Instruction::Add, Value::Number(a), Value::Number(b)) => {
+                        stack.push(Value::Number(a + b));
+                        continue;
+                    }
+                    (Instruction::Subtract, Value::Number(a), Value::Number(b)) => {
+                        stack.push(Value::Number(a - b));
+                        continue;
+                    }
+                    (Instruction::Multiply, Value::Number(a), Value::Number(b)) => {
+                        stack.push(Value::Number(a * b));
+                        continue;
+                    }
+                    _ => {}
+                }
+                match instruction {
+                    Instruction::Add => left.checked_add(right)?,
+                    Instruction::Subtract => left.checked_sub(right)?,
+                    Instruction::Multiply => left.checked_mul(right)?,
+                    Instruction::Divide => left.checked_div(right)?,
+                    Instruction::Power => left.checked_pow(right)?,
+                    Instruction::Convert => left.convert(right)?,
+                    Instruction::Range => {
+                        Value::List(evaluator::range_between(&left, &right, context.limits())?)
+                    }
+                    Instruction::Compare(predicate) => {
+                        Value::Bool(left.compare(&right)?.is_some_and(predicate))
+                    }
+                    Instruction::Equal => Value::Bool(left.equals(&right)?),
+                    Instruction::NotEqual => Value::Bool(!left.equals(&right)?),
+                    _ => Value::Bool(left.approximately_equals(&right)?),
+                }
+            }
+            Instruction::Negate => pop(&mut stack).negate()?,
+            Instruction::Not => Value::Bool(!pop(&mut stack).as_bool()?),
+            Instruction::Factorial => {
+                Value::Number(special::factorial(pop(&mut stack).as_number()?)?)
+            }
+            Instruction::DoubleFactorial => {
+                Value::Number(special::double_factorial(pop(&mut stack).as_number()?)?)
+            }
+            Instruction::Call(builtin, count) => {
+                let arguments = stack.len() - count;
+                context
+                    .limits()
+                    .check_arguments(builtin, &stack[arguments..])?;
+                let result = builtin.call(&stack[arguments..])?;
+                stack.truncate(arguments);
+                result
+            }
+            Instruction::List(count) => Value::List(stack.split_off(stack.len() - count)),
+            Instruction::Jump(target) => {
+                next = *target;
+                continue;
+            }
+            Instruction::JumpIf(condition, target) => {
+                if pop(&mut stack).as_bool()? == *condition {
+                    next = *target;
+                }
+                continue;
+            }
+            Instruction::Fail(error) => return Err(error),
+            Instruction::Interpret(expression, bindings) => {
+                let bindings = bindings
+                    .iter()
+                    .map(|(name, slot)| (name.as_str(), slots[*slot].clone()));
+                evaluate_in(expression, &context.with_variables(bindings))?
+            }
+        };
+        stack.push(result);
+    }
+    Ok(pop(&mut stack))
+}
+
+/// Runs instructions on numbers and booleans without allocating, or returns `None` for
+/// the general [`execute`] to run them from the start, e.g. on a division by zero
+fn execute_scalar(
+    instructions: &[Instruction],
+    slots: &mut [Scalar],
+    limits: &Limits,
+) -> Option<Result<Value, &'static str>> {
+    let mut stack = [Scalar::Bool(false); MAX_SCALARS];
+    let mut depth = 0;
+    let mut next = 0;
+    while let Some(instruction) = instructions.get(next) {
+        next += 1;
+        let result = match instruction {
+            Instruction::Constant(value) => Scalar::of(value)?,
+            Instruction::Load(slot) => slots[*slot],
+            Instruction::Store(slot) => {
+                depth -= 1;
+                slots[*slot] = stack[depth];
+                continue;
+            }
+            Instruction::Negate
+            | Instruction::Not
+            | Instruction::Factorial
+            | Instruction::DoubleFactorial
+            | Instruction::JumpIf(..) => {
+                depth -= 1;
+                match (instruction, stack[depth]) {
+                    (Instruction::Negate, Scalar::Number(value)) => Scalar::Number(-value),
+                    (Instruction::Not, Scalar::Bool(value)) => Scalar::Bool(!value),
+                    (Instruction::Factorial, Scalar::Number(value)) => {
+                        match special::factorial(value) {
+                            Ok(result) => Scalar::Number(result),
+                            Err(error) => return Some(Err(error)),
+                        }
+                    }
+                    (Instruction::DoubleFactorial, Scalar::Number(value)) => {
+                        match special::double_factorial(value) {
+                            Ok(result) => Scalar::Number(result),
+                            Err(error) => return Some(Err(error)),
+                        }
+                    }
+                    (Instruction::JumpIf(condition, target), Scalar::Bool(value)) => {
+                        if value == *condition {
+                            next = *target;
+                        }
+                        continue;
+                    }
+                    _ => return None,
+                }
+            }
+            Instruction::Call(builtin, count) => {
+                depth -= count;
+                let call = |arguments: &[Value]| {
+                    limits.check_arguments(builtin, arguments)?;
+                    builtin.call(arguments)
+                };
+                let result = match &stack[depth..depth + count] {
+                    [argument] => call(&[argument.into_value()]),
+                    [left, right] => call(&[left.into_value(), right.into_value()]),
+                    arguments => {
+                        let arguments: [Value; MAX_SCALAR_ARGUMENTS] =
+                            std::array::from_fn(|i| match arguments.get(i) {
+                                Some(argument) => argument.into_value(),
+                                None => Value::Bool(false),
+                            });
+                        call(&arguments[..*count])
+                    }
+                };
+                match result {
+                    Ok(result) => Scalar::of(&result)?,
+                    Err(error) => return Some(Err(error)),
+                }
+            }
+            Instruction::Jump(target) => {
+                next = *target;
+                continue;
+            }
+            Instruction::Fail(error) => return Some(Err(error)),
+            Instruction::Convert
+            | Instruction::Range
+            | Instruction::List(_)
+            | Instruction::Interpret(..) => return None,
+            _ => {
+                depth -= 2;
+                match (instruction, stack[depth], stack[depth + 1]) {
+                    (Instruction::Add, Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Number(a + b)
+                    }
+                    (Instruction::Subtract, Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Number(a - b)
+                    }
+                    (Instruction::Multiply, Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Number(a * b)
+                    }
+                    (Instruction::Divide, Scalar::Number(a), Scalar::Number(b)) if b != 0.0 => {
+                        Scalar::Number(a / b)
+                    }
+                    (Instruction::Power, Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Number(a.powf(b))
+                    }
+                    (Instruction::Compare(predicate), Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Bool(a.partial_cmp(&b).is_some_and(predicate))
+                    }
+                    (Instruction::Equal, Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Bool(a == b)
+                    }
+                    (Instruction::Equal, Scalar::Bool(a), Scalar::Bool(b)) => Scalar::Bool(a == b),
+                    (Instruction::NotEqual, Scalar::Number(a), Scalar::Number(b)) => {
+                        Scalar::Bool(a != b)
+                    }
+                    (Instruction::NotEqual, Scalar::Bool(a), Scalar::Bool(b)) => {
+                        Scalar::Bool(a != b)
+                    }
+                    (Instruction::ApproximatelyEqual, left, right) => Scalar::Bool(
+                        left.into_value()
+                            .approximately_equals(&right.into_value())
+                            .ok()?,
+                    ),
+                    _ => return None,
+                }
+            }
+        };
+        if depth == MAX_SCALARS {
+            return None;
+        }
+        stack[depth] = result;
+        depth += 1;
+    }
+    Some(Ok(stack[0].into_value()))
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("operands are on the stack")
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::functions::FunctionRegistry;
+    use crate::{lexer, parser, strategies};
+
+    fn parse(input: &str) -> Expression {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    type Outcome = Result<String, &'static str>;
+
+    /// Evaluates both compiled and with the tree walker
+    fn evaluate_both(context: &Context, expression: &Expression) -> (Outcome, Outcome) {
+        let expected = context
+            .with_variables([("x", Value::Number(3.0)), ("y", Value::Number(-0.5))])
+            .evaluate(expression)
+            .map(|value| value.unwrap().to_string());
+        let compiled = compile_in(expression, &["x", "y"], context)
+            .and_then(|compiled| {
+                compiled.evaluate_value(&[Value::Number(3.0), Value::Number(-0.5)])
+            })
+            .map(|value| value.to_string());
+        (compiled, expected)
+    }
+
+    /// Evaluates both compiled and with the tree walker, which must agree
+    fn evaluate(context: &mut Context, input: &str) -> Outcome {
+        let (compiled, expected) = evaluate_both(context, &parse(input));
+        assert_eq!(compiled, expected, "{}", input);
+        compiled
+    }
+
+    #[test]
+    fn test_matches_evaluator() {
+        let mut context = Context::new();
+        context.evaluate(&parse("k = 2")).unwrap();
+        context.evaluate(&parse("square(a) = a^2")).unwrap();
+        context
+            .evaluate(&parse("fact(n) = if(n <= 1, 1, n * fact(n - 1))"))
+            .unwrap();
+        context.evaluate(&parse("f(x) = x + y")).unwrap();
+        let cases = [
+            "2 x + y^2 * k",
+            "-x! + 5!!",
+            "x km in m",
+            "square(x + 1) + square(square(y))",
+            "fact(x + 2)",
+            "f(10)",
+            "if(x > y, 1, 2) + piecewise(x < 0, -1, x == 0, 0, 1)",
+            "x > 0 and y > 0 or not (x ~= 3)",
+            "false and 1 / 0 > 0",
+            "true or 1 / 0 > 0",
+            "sum([x, y, 1..4]) + max(x, y)",
+            "sum(k, 1, x, k^2) + derive(t^3, t, x)",
+            "integrate(t, t, 0, x)",
+            "solve(t^2 = x^2, t)",
+            "[[x, 0], [0, y]] * 2",
+            "1 / (x - 3)",
+            "piecewise(x < 0, 1)",
+            "sqrt(y)",
+            "x + true",
+        ];
+        for input in cases {
+            evaluate(&mut context, input).ok();
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_matches_evaluator(expression in strategies::expression()) {
+            let (compiled, expected) = evaluate_both(&Context::new(), &expression);
+            prop_assert_eq!(compiled, expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let compiled = compile(&parse("x * (2 + 3) km / sqrt(16)"), &["x"]).unwrap();
+        assert_eq!(compiled.instructions.len(), 5);
+        assert_eq!(
+            compiled
+                .evaluate_value(&[Value::Number(4.0)])
+                .unwrap()
+                .to_string(),
+            "5 km"
+        );
+        // Errors are left for evaluation, where they may not be reached
+        let compiled = compile(&parse("if(x > 0, x, 1 / 0)"), &["x"]).unwrap();
+        assert_eq!(compiled.evaluate(&[2.0]), Ok(2.0));
+        assert_eq!(compiled.evaluate(&[-2.0]), Err("Division by zero"));
+    }
+
+    #[test]
+    fn test_calls_share_builtins() {
+        let compiled = compile(&parse("sqrt(x)"), &["x"]).unwrap();
+        let Some(Instruction::Call(builtin, 1)) = compiled.instructions.last() else {
+            panic!("expected a call");
+        };
+        let sqrt = FunctionRegistry::standard().get_shared("sqrt").unwrap();
+        assert!(Arc::ptr_eq(builtin, sqrt));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            compile(&parse("x + z"), &["x"]).unwrap_err(),
+            "Unknown name"
+        );
+        assert_eq!(
+            compile(&parse("nope(x)"), &["x"]).unwrap_err(),
+            "Unknown function"
+        );
+        assert_eq!(
+            compile(&parse("sqrt(x, x)"), &["x"]).unwrap_err(),
+            "Wrong number of function arguments"
+        );
+        let compiled = compile(&parse("x m"), &["x"]).unwrap();
+        assert_eq!(
+            compiled.evaluate(&[1.0]),
+            Err("Expected a dimensionless number")
+        );
+        assert_eq!(
+            compiled.evaluate(&[]),
+            Err("Wrong number of function arguments")
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let compiled = compile(&parse("integrate(floor(t), t, 0, x)"), &["x"]).unwrap();
+        assert!((compiled.evaluate(&[2.5]).unwrap() - 2.0).abs() < 1e-6);
+        assert_eq!(
+            compiled.take_warnings(),
+            [crate::calculus::DISCONTINUOUS_INTEGRAND]
+        );
+    }
+}
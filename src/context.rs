@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::f64::consts::{E, PI};
+use std::sync::{Arc, Mutex};
+
+use crate::definitions::{self, Definition, DefinitionError, UnitDefinition};
+use crate::evaluator::evaluate_in;
+use crate::functions::FunctionRegistry;
+use crate::lexer;
+use crate::limits::{self, Budget, Limits, Nesting};
+use crate::parser::{self, Expression};
+use crate::units::{self, Dimension, Unit};
+use crate::value::Value;
+
+/// Mathematical constants, which variables of the same name shadow
+static CONSTANTS: [(&str, Value); 2] = [("pi", Value::Number(PI)), ("e", Value::Number(E))];
+
+/// State shared between evaluations, such as units, variables and functions defined at runtime
+///
+/// The definitions are shared with clones and scopes until either changes them.
+#[derive(Debug)]
+pub struct Context {
+    units: Arc<HashMap<String, Unit>>,
+    variables: Arc<HashMap<String, Value>>,
+    functions: Arc<HashMap<String, Function>>,
+    /// Variables bound by [`Context::with_variables`], which shadow `variables`
+    scope: Option<Arc<Scope>>,
+    builtins: Arc<FunctionRegistry>,
+    /// Shared with the scopes made by [`Context::with_variables`], so that warnings
+    /// raised inside function calls reach the caller
+    warnings: Arc<Mutex<Vec<&'static str>>>,
+    limits: Limits,
+    /// Shared with the scopes like the warnings, so that nested calls count towards
+    /// the limits
+    budget: Arc<Budget>,
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context {
+            units: Arc::default(),
+            variables: Arc::default(),
+            functions: Arc::default(),
+            scope: None,
+            builtins: FunctionRegistry::standard().clone(),
+            warnings: Arc::default(),
+            limits: Limits::default(),
+            budget: Arc::default(),
+        }
+    }
+}
+
+/// Clones start without warnings or evaluations in progress of their own
+impl Clone for Context {
+    fn clone(&self) -> Context {
+        Context {
+            units: self.units.clone(),
+            variables: self.variables.clone(),
+            functions: self.functions.clone(),
+            scope: self.scope.clone(),
+            builtins: self.builtins.clone(),
+            warnings: Arc::default(),
+            limits: self.limits,
+            budget: Arc::default(),
+        }
+    }
+}
+
+/// Variables bound together, e.g. the parameters of a function call, within the scope
+/// they were bound in
+#[derive(Debug)]
+struct Scope {
+    variables: Vec<(String, Value)>,
+    parent: Option<Arc<Scope>>,
+}
+
+/// A user-defined function, e.g. `f(x, y) = x^2 + y`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub parameters: Vec<String>,
+    pub body: Expression,
+}
+
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    /// The value of a variable, searching the innermost scope first and the constants
+    /// `pi` and `e` last
+    pub fn variable(&self, name: &str) -> Option<&Value> {
+        let mut scope = self.scope.as_deref();
+        while let Some(Scope { variables, parent }) = scope {
+            // Later bindings of the same name replace earlier ones
+            let bound = variables.iter().rev().find(|(bound, _)| bound == name);
+            if let Some((_, value)) = bound {
+                return Some(value);
+            }
+            scope = parent.as_deref();
+        }
+        self.variables.get(name).or_else(|| {
+            let constant = CONSTANTS.iter().find(|(constant, _)| *constant == name);
+            constant.map(|(_, value)| value)
+        })
+    }
+
+    /// Sets a variable outside of any scope, where variables of the same name in scopes
+    /// still shadow it
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        Arc::make_mut(&mut self.variables).insert(name.to_string(), value);
+    }
+
+    pub fn remove_variable(&mut self, name: &str) -> Option<Value> {
+        Arc::make_mut(&mut self.variables).remove(name)
+    }
+
+    /// A scope of this context with additional variables, e.g. bound function parameters
+    ///
+    /// The scope refers to this context's definitions rather than copying them, so
+    /// that calls and iterations stay cheap.
+    pub fn with_variables<'a>(
+        &self,
+        variables: impl IntoIterator<Item = (&'a str, Value)>,
+    ) -> Context {
+        let variables = variables
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        Context {
+            units: self.units.clone(),
+            variables: self.variables.clone(),
+            functions: self.functions.clone(),
+            scope: Some(Arc::new(Scope {
+                variables,
+                parent: self.scope.clone(),
+            })),
+            builtins: self.builtins.clone(),
+            warnings: self.warnings.clone(),
+            limits: self.limits,
+            budget: self.budget.clone(),
+        }
+    }
+
+    /// Reports a problem that does not prevent a result, e.g. an inaccurate integral
+    pub fn warn(&self, warning: &'static str) {
+        let mut warnings = self.warnings.lock().unwrap();
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+
+    /// The warnings raised since they were last taken, in the order they were raised
+    pub fn take_warnings(&self) -> Vec<&'static str> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Limits the resources used by evaluations, e.g. for untrusted input
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Enters a subexpression, counting it towards the limits until the result is dropped
+    pub(crate) fn enter(&self) -> Result<Nesting<'_>, &'static str> {
+        self.budget.enter(&self.limits)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn define_function(&mut self, name: &str, parameters: Vec<String>, body: Expression) {
+        Arc::make_mut(&mut self.functions).insert(name.to_string(), Function { parameters, body });
+    }
+
+    /// The built-in functions available to expressions
+    pub fn builtins(&self) -> &FunctionRegistry {
+        &self.builtins
+    }
+
+    /// The built-in functions for extending or restricting, copied on first change
+    pub fn builtins_mut(&mut self) -> &mut FunctionRegistry {
+        Arc::make_mut(&mut self.builtins)
+    }
+
+    /// Looks up a user-defined or built-in unit, accepting plurals such as `weeks`
+    pub fn lookup_unit(&self, name: &str) -> Option<Unit> {
+        self.lookup_unit_exact(name)
+            .or_else(|| self.lookup_unit_exact(name.strip_suffix('s')?))
+    }
+
+    fn lookup_unit_exact(&self, name: &str) -> Option<Unit> {
+        self.units
+            .get(name)
+            .cloned()
+            .or_else(|| units::lookup(name))
+    }
+
+    /// Defines a unit in terms of existing units, replacing any earlier definition
+    pub fn define_unit(
+        &mut self,
+        name: &str,
+        definition: &Expression,
+    ) -> Result<Value, &'static str> {
+        let value = evaluate_in(definition, self)?;
+        let unit = match &value {
+            Value::Number(factor) => Unit {
+                factor: *factor,
+                dimension: Dimension::dimensionless(),
+            },
+            Value::Quantity(quantity) => Unit {
+                factor: quantity.value,
+                dimension: quantity.dimension.clone(),
+            },
+            Value::Bool(_) | Value::List(_) | Value::Expression(_) => {
+                return Err("Units must be defined as a number or quantity")
+            }
+        };
+        if unit.factor == 0.0 || !unit.factor.is_finite() {
+            return Err("Unit must be a finite, non-zero amount");
+        }
+        Arc::make_mut(&mut self.units).insert(name.to_string(), unit);
+        Ok(value)
+    }
+
+    /// Defines a new base unit with a dimension of its own, e.g. `story_point`
+    ///
+    /// Names of the built-in base units such as `kg` are rejected, as their
+    /// dimensions would merge.
+    pub fn define_primitive_unit(&mut self, name: &str) -> Result<(), &'static str> {
+        if Dimension::is_base_unit(name) {
+            return Err("Primitive unit would redefine a base dimension");
+        }
+        let unit = Unit {
+            factor: 1.0,
+            dimension: Dimension::primitive(name),
+        };
+        Arc::make_mut(&mut self.units).insert(name.to_string(), unit);
+        Ok(())
+    }
+
+    /// Loads unit definitions in the format described in [`crate::definitions`]
+    ///
+    /// Definitions may refer to each other in any order. When any definition is
+    /// invalid or circular, none of them are applied.
+    pub fn load_definitions(&mut self, source: &str) -> Result<(), DefinitionError> {
+        let definitions = definitions::parse_definitions(source)?;
+        let by_name: HashMap<&str, &UnitDefinition> = definitions
+            .iter()
+            .map(|definition| (definition.name.as_str(), definition))
+            .collect();
+
+        let mut context = self.clone();
+        let mut resolved = vec![];
+        for definition in &definitions {
+            context.resolve(definition, &by_name, &mut resolved, &mut vec![])?;
+        }
+        *self = context;
+        Ok(())
+    }
+
+    /// Defines `definition` after the definitions it refers to, detecting cycles
+    fn resolve<'a>(
+        &mut self,
+        definition: &'a UnitDefinition,
+        by_name: &HashMap<&str, &'a UnitDefinition>,
+        resolved: &mut Vec<&'a str>,
+        resolving: &mut Vec<&'a str>,
+    ) -> Result<(), DefinitionError> {
+        let name = definition.name.as_str();
+        if resolved.contains(&name) {
+            return Ok(());
+        }
+        if resolving.contains(&name) {
+            return Err(DefinitionError {
+                line: definition.line,
+                message: "Circular unit definition",
+            });
+        }
+
+        match &definition.definition {
+            Definition::Primitive => {
+                self.define_primitive_unit(name)
+                    .map_err(|message| DefinitionError {
+                        line: definition.line,
+                        message,
+                    })?;
+            }
+            Definition::Derived(expression) => {
+                resolving.push(name);
+                for referenced in expression.referenced_names() {
+                    let singular = referenced.strip_suffix('s');
+                    let dependency = by_name.get(referenced).or_else(|| by_name.get(singular?));
+                    if let Some(dependency) = dependency {
+                        self.resolve(dependency, by_name, resolved, resolving)?;
+                    }
+                }
+                resolving.pop();
+
+                self.define_unit(name, expression)
+                    .map_err(|message| DefinitionError {
+                        line: definition.line,
+                        message,
+                    })?;
+            }
+        }
+
+        resolved.push(name);
+        Ok(())
+    }
+
+    /// Lexes and parses an input within the limits
+    pub fn parse(&self, input: &str) -> Result<Expression, &'static str> {
+        if input.chars().count() > self.limits.max_input_length {
+            return Err(limits::INPUT_TOO_LONG);
+        }
+        let tokens = lexer::lex(input).map_err(|_| "Invalid character")?;
+        parser::parse_with_limits(&tokens, &self.limits)
+    }
+
+    /// Lexes, parses and evaluates an input within the limits, as [`Context::evaluate`]
+    pub fn evaluate_str(&mut self, input: &str) -> Result<Option<Value>, &'static str> {
+        let expression = self.parse(input)?;
+        self.evaluate(&expression)
+    }
+
+    /// Evaluates an expression, applying definitions such as `unit furlong = 201.168 m`
+    /// or `f(x) = x^2`
+    ///
+    /// Returns `None` for function definitions, which have no value of their own.
+    pub fn evaluate(&mut self, expression: &Expression) -> Result<Option<Value>, &'static str> {
+        match expression {
+            Expression::UnitDefinition(name, definition) => {
+                self.define_unit(name, definition).map(Some)
+            }
+            Expression::Assignment(name, value) => {
+                let value = evaluate_in(value, self)?;
+                self.set_variable(name, value.clone());
+                Ok(Some(value))
+            }
+            Expression::FunctionDefinition(name, parameters, body) => {
+                self.define_function(name, parameters.clone(), (**body).clone());
+                Ok(None)
+            }
+            Expression::Labeled(_, expression) => self.evaluate(expression),
+            _ => evaluate_in(expression, self).map(Some),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lexer, parser};
+
+    fn evaluate(context: &mut Context, input: &str) -> Result<String, &'static str> {
+        let tokens = lexer::lex(input).expect("Lexing failed");
+        let parsed = parser::parse(&tokens)?;
+        let value = context.evaluate(&parsed)?;
+        Ok(value.map(|value| value.to_string()).unwrap_or_default())
+    }
+
+    #[test]
+    fn test_in_expression_definition() {
+        let mut context = Context::new();
+        assert_eq!(
+            evaluate(&mut context, "unit furlong = 201.168 m").unwrap(),
+            "201.168 m"
+        );
+        assert_eq!(
+            evaluate(&mut context, "2 furlong in m").unwrap(),
+            "402.336 m"
+        );
+        assert_eq!(
+            evaluate(&mut context, "1 mi in furlongs").unwrap(),
+            "8 furlongs"
+        );
+    }
+
+    #[test]
+    fn test_load_definitions() {
+        let mut context = Context::new();
+        context
+            .load_definitions(
+                "
+                # Defined before the units they depend on
+                sprint      2 fortnight
+                fortnight   2 weeks
+                rack_unit   1.75 in
+                story_point !
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(evaluate(&mut context, "sprint in d").unwrap(), "28 d");
+        assert_eq!(
+            evaluate(&mut context, "48 rack_unit in in").unwrap(),
+            "84 in"
+        );
+        assert_eq!(
+            evaluate(
+                &mut context,
+                "13 story_point / sprint * 1 wk in story_point"
+            )
+            .unwrap(),
+            "3.25 story_point"
+        );
+        assert!(evaluate(&mut context, "1 story_point + 1 s").is_err());
+    }
+
+    #[test]
+    fn test_load_definitions_errors() {
+        let mut context = Context::new();
+        let error = context
+            .load_definitions("foo 2 bar\nbaz 1 m\nbar 3 foo")
+            .unwrap_err();
+        assert_eq!(
+            error,
+            DefinitionError {
+                line: 1,
+                message: "Circular unit definition"
+            }
+        );
+
+        let error = context
+            .load_definitions("foo 2 m\n\nbar 3 qux")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "line 3: Unknown name");
+
+        let error = context.load_definitions("foo 2 m\nkg !").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: Primitive unit would redefine a base dimension"
+        );
+
+        // Failed loads leave the context untouched
+        assert_eq!(context.lookup_unit("foo"), None);
+        assert_eq!(context.lookup_unit("baz"), None);
+    }
+
+    #[test]
+    fn test_variables_and_functions() {
+        let mut context = Context::new();
+        assert_eq!(evaluate(&mut context, "rate = 0.2").unwrap(), "0.2");
+        assert_eq!(
+            evaluate(&mut context, "f(x, y) = x^2 + rate y").unwrap(),
+            ""
+        );
+        assert_eq!(evaluate(&mut context, "f(3, 10)").unwrap(), "11");
+        // Parameters shadow variables only within the function body
+        assert_eq!(evaluate(&mut context, "x = 5").unwrap(), "5");
+        assert_eq!(evaluate(&mut context, "f(2, x) + x").unwrap(), "10");
+        assert!(evaluate(&mut context, "f(1)").is_err());
+    }
+
+    #[test]
+    fn test_comments_and_labels() {
+        let mut context = Context::new();
+        assert_eq!(
+            evaluate(&mut context, "Tax rate: rate = 0.25 // since 2024").unwrap(),
+            "0.25"
+        );
+        assert_eq!(
+            evaluate(&mut context, "Net: 100 (1 - rate) # rounded").unwrap(),
+            "75"
+        );
+        assert_eq!(
+            evaluate(&mut context, "Empty: # nothing"),
+            Err("Empty input")
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let mut context = Context::new();
+        evaluate(&mut context, "inverse(x) = if(x == 0, 0, 1/x)").unwrap();
+        assert_eq!(evaluate(&mut context, "inverse(0)").unwrap(), "0");
+        assert_eq!(evaluate(&mut context, "inverse(4)").unwrap(), "0.25");
+        evaluate(&mut context, "fact(n) = if(n <= 1, 1, n fact(n - 1))").unwrap();
+        assert_eq!(evaluate(&mut context, "fact(5)").unwrap(), "120");
+        assert_eq!(
+            evaluate(&mut context, "if(sqrt(4) = 2, 1, 0)").unwrap(),
+            "1"
+        );
+        assert_eq!(
+            evaluate(&mut context, "if(x = 2, 1, 0)"),
+            Err("Unknown name")
+        );
+        assert!(evaluate(&mut context, "if(1, 2, 3)").is_err());
+        assert!(evaluate(&mut context, "if(true, 2)").is_err());
+    }
+
+    #[test]
+    fn test_piecewise_functions() {
+        let mut context = Context::new();
+        evaluate(
+            &mut context,
+            "tax(income) = piecewise(
+                income <= 10000, 0.1 income,
+                income <= 40000, 1000 + 0.2 (income - 10000),
+                7000 + 0.4 (income - 40000)
+            )",
+        )
+        .unwrap();
+        assert_eq!(evaluate(&mut context, "tax(5000)").unwrap(), "500");
+        assert_eq!(evaluate(&mut context, "tax(20000)").unwrap(), "3000");
+        assert_eq!(evaluate(&mut context, "tax(50000)").unwrap(), "11000");
+
+        // Without a fallback value, inputs outside every piece are an error
+        evaluate(&mut context, "sign(x) = piecewise(x < 0, -1, x > 0, 1)").unwrap();
+        assert_eq!(evaluate(&mut context, "sign(-3)").unwrap(), "-1");
+        assert!(evaluate(&mut context, "sign(0)").is_err());
+    }
+
+    #[test]
+    fn test_scopes() {
+        let mut context = Context::new();
+        context.set_variable("x", Value::Number(1.0));
+        let outer = context.with_variables([("x", Value::Number(2.0)), ("y", Value::Number(3.0))]);
+        let inner = outer.with_variables([("x", Value::Number(4.0))]);
+        assert_eq!(inner.variable("x"), Some(&Value::Number(4.0)));
+        assert_eq!(inner.variable("y"), Some(&Value::Number(3.0)));
+        assert_eq!(outer.variable("x"), Some(&Value::Number(2.0)));
+        assert_eq!(context.variable("y"), None);
+        assert_eq!(context.variable("pi"), Some(&Value::Number(PI)));
+        let shadowed = context.with_variables([("e", Value::Number(5.0))]);
+        assert_eq!(shadowed.variable("e"), Some(&Value::Number(5.0)));
+    }
+
+    #[test]
+    fn test_derivatives() {
+        let mut context = Context::new();
+        assert_eq!(evaluate(&mut context, "derive(x^3, x, 2)").unwrap(), "12");
+        assert_eq!(evaluate(&mut context, "a = 5").unwrap(), "5");
+        assert_eq!(
+            evaluate(&mut context, "derive(a t^2 / 2, t, 3 s)").unwrap(),
+            "15 s"
+        );
+        // The variable is only bound within the derivative
+        assert_eq!(
+            evaluate(&mut context, "derive(sum(x, 1, 3, x^2), x, 5)").unwrap(),
+            "0"
+        );
+        assert!(evaluate(&mut context, "derive(x^2, x, 1) + x").is_err());
+        assert!(evaluate(&mut context, "derive(x^2, 2, 1)").is_err());
+
+        // Without a point, the derivative itself
+        assert_eq!(
+            evaluate(&mut context, "derive(x^2 * sin(x), x)").unwrap(),
+            "2 * (x * sin(x)) + x^2 * cos(x)"
+        );
+        assert!(evaluate(&mut context, "derive(x^2, x) + 1").is_err());
+
+        // User-defined functions are inlined, renaming the variables they bind where
+        // an argument would be captured
+        evaluate(&mut context, "f(x) = x^2").unwrap();
+        evaluate(&mut context, "g(t) = sum(k, 1, 2, k t) + f(t)").unwrap();
+        assert_eq!(evaluate(&mut context, "derive(f(x), x, 3)").unwrap(), "6");
+        assert_eq!(evaluate(&mut context, "derive(f(x), x)").unwrap(), "2 x");
+        assert_eq!(evaluate(&mut context, "derive(g(k), k, 1)").unwrap(), "5");
+        assert_eq!(
+            evaluate(&mut context, "derive(f(2 y) + y, y, 1)").unwrap(),
+            "9"
+        );
+        // Recursive functions cannot be inlined
+        evaluate(&mut context, "fact(n) = if(n <= 1, 1, n fact(n - 1))").unwrap();
+        assert_eq!(
+            evaluate(&mut context, "derive(fact(x), x, 3)"),
+            Err("Expression is not differentiable")
+        );
+    }
+
+    #[test]
+    fn test_solve() {
+        let mut context = Context::new();
+        assert_eq!(
+            evaluate(&mut context, "solve(x^2 = 4, x)").unwrap(),
+            "[-2, 2]"
+        );
+        assert_eq!(
+            evaluate(&mut context, "sum(solve(x^2 - 2 = 0, x, 0, 10)) ~= sqrt(2)"),
+            Ok("true".into())
+        );
+        assert_eq!(evaluate(&mut context, "solve(x^2 + 1, x)").unwrap(), "[]");
+        assert_eq!(
+            evaluate(&mut context, "solve((x - 3)^2 = 0, x)").unwrap(),
+            "[3]"
+        );
+        assert_eq!(
+            evaluate(&mut context, "solve(2 x m = 3 m, x)").unwrap(),
+            "[1.5]"
+        );
+        assert_eq!(
+            evaluate(&mut context, "solve(sin(x), x, 1, 10)").unwrap(),
+            "[3.141592653589793, 6.283185307179586, 9.42477796076938]"
+        );
+        // An equation whose left side is a call compares rather than defines a function
+        assert_eq!(
+            evaluate(&mut context, "solve(sin(x) = 0.5, x, 0, 3)").unwrap(),
+            "[0.5235987755982989, 2.6179938779901866]"
+        );
+        // A matrix and a vector still solve the linear system
+        assert_eq!(
+            evaluate(&mut context, "solve([[2, 0], [0, 4]], [2, 2])").unwrap(),
+            "[1, 0.5]"
+        );
+        assert_eq!(
+            evaluate(&mut context, "solve(y = 1, x)"),
+            Err("Unknown name")
+        );
+
+        evaluate(&mut context, "f(t) = t^3 - t - 1").unwrap();
+        assert_eq!(
+            evaluate(&mut context, "root(f, 1, 2) ~= 1.324717957244746"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            evaluate(&mut context, "root(cos, 0, 2) ~= acos(0)"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            evaluate(&mut context, "root(f, 2, 3)"),
+            Err("No root between the bounds")
+        );
+    }
+
+    #[test]
+    fn test_integrals_and_limits() {
+        let mut context = Context::new();
+        assert_eq!(
+            evaluate(&mut context, "integrate(sin(x), x, 0, pi) ~= 2"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            evaluate(&mut context, "integrate(1 / x, x, 1, e) ~= 1"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            evaluate(&mut context, "integrate(2 t * 1 m/s^2, t, 0 s, 3 s) in m").unwrap(),
+            "9 m"
+        );
+        assert_eq!(
+            evaluate(&mut context, "limit(sin(x) / x, x, 0)").unwrap(),
+            "1"
+        );
+        assert_eq!(
+            evaluate(&mut context, "limit(1 / x, x, 0)"),
+            Err("Limit did not converge")
+        );
+        assert!(context.take_warnings().is_empty());
+
+        // Warnings from inside function calls reach the context, once each
+        evaluate(&mut context, "f(a) = integrate(floor(x), x, 0, a)").unwrap();
+        assert_eq!(
+            evaluate(&mut context, "f(2.5) + f(2.5) ~= 4"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            context.take_warnings(),
+            [crate::calculus::DISCONTINUOUS_INTEGRAND]
+        );
+        assert!(context.take_warnings().is_empty());
+
+        // Integrals whose error estimate stays above the tolerance are reported
+        evaluate(&mut context, "integrate(sin(1 / x), x, 0, 1)").unwrap();
+        assert_eq!(
+            context.take_warnings(),
+            [crate::calculus::INACCURATE_INTEGRAL]
+        );
+
+        // Samples that land on a singularity are a discontinuity rather than an error
+        assert_eq!(
+            evaluate(&mut context, "integrate(1 / x, x, -1, 1) ~= 0"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            context.take_warnings(),
+            [crate::calculus::DISCONTINUOUS_INTEGRAND]
+        );
+    }
+
+    #[test]
+    fn test_custom_builtins() {
+        let mut context = Context::new();
+        context
+            .builtins_mut()
+            .register(crate::functions::Builtin::unary("double", |x| 2.0 * x));
+        context.builtins_mut().remove("sqrt");
+        assert_eq!(evaluate(&mut context, "double(21)").unwrap(), "42");
+        assert_eq!(evaluate(&mut context, "sqrt(4)"), Err("Unknown function"));
+        // Other contexts keep the standard functions
+        assert_eq!(evaluate(&mut Context::new(), "sqrt(4)").unwrap(), "2");
+    }
+}
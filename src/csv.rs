@@ -0,0 +1,118 @@
+//! Reading and writing comma-separated values one record at a time, so that files of
+//! any size can be streamed
+//!
+//! Fields may be quoted to hold commas, line breaks and doubled quotes, e.g.
+//! `"Widget, large","12"" pipe"`.
+
+use std::io::{self, BufRead};
+
+/// A record as it appeared in the input, along with its fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The text of the record without its line ending
+    pub raw: String,
+    pub fields: Vec<String>,
+}
+
+/// Reads records from a source of comma-separated lines
+pub struct Reader<R> {
+    source: R,
+}
+
+impl<R: BufRead> Reader<R> {
+    pub fn new(source: R) -> Reader<R> {
+        Reader { source }
+    }
+}
+
+impl<R: BufRead> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        let mut raw = String::new();
+        // A quoted field spanning line breaks continues on the following lines
+        loop {
+            match self.source.read_line(&mut raw) {
+                Ok(0) if raw.is_empty() => return None,
+                Ok(0) => break,
+                Ok(_) if raw.matches('"').count() % 2 == 1 => continue,
+                Ok(_) => break,
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        let raw = raw
+            .strip_suffix('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .unwrap_or(&raw)
+            .to_string();
+        let fields = split_fields(&raw);
+        Some(Ok(Record { raw, fields }))
+    }
+}
+
+fn split_fields(record: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(char),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Quotes a field if it holds characters that would otherwise split it
+pub fn escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str) -> Vec<Vec<String>> {
+        Reader::new(input.as_bytes())
+            .map(|record| record.unwrap().fields)
+            .collect()
+    }
+
+    #[test]
+    fn test_read() {
+        assert_eq!(
+            read("price,qty\n2.5,4\r\n3,\n"),
+            [vec!["price", "qty"], vec!["2.5", "4"], vec!["3", ""]]
+        );
+        assert_eq!(
+            read("name,note\n\"Widget, large\",\"12\"\" pipe\nspare\"\nlast,1"),
+            [
+                vec!["name", "note"],
+                vec!["Widget, large", "12\" pipe\nspare"],
+                vec!["last", "1"]
+            ]
+        );
+        assert_eq!(read(""), Vec::<Vec<String>>::new());
+
+        let record = Reader::new("a,\"b\nc\"\r\nd".as_bytes()).next().unwrap();
+        assert_eq!(record.unwrap().raw, "a,\"b\nc\"");
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("total"), "total");
+        assert_eq!(escape("price * qty, net"), "\"price * qty, net\"");
+        assert_eq!(escape("12\" pipe"), "\"12\"\" pipe\"");
+    }
+}
@@ -0,0 +1,149 @@
+//! Plain-text unit definitions, loosely following the GNU units format
+//!
+//! Every line holds a unit name followed by its definition, blank lines and
+//! everything after a `#` are ignored:
+//!
+//! ```text
+//! # Sizes of things in our data center
+//! rack_unit    1.75 in
+//! sprint     = 2 weeks
+//! story_point  !
+//! ```
+//!
+//! A definition of `!` declares a new primitive unit with a dimension of its own.
+
+use std::fmt;
+
+use crate::lexer;
+use crate::parser::{self, Expression};
+
+#[derive(Debug, PartialEq)]
+pub enum Definition {
+    /// A new base unit, e.g. `story_point !`
+    Primitive,
+    /// A unit defined in terms of other units, e.g. `rack_unit 1.75 in`
+    Derived(Expression),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UnitDefinition {
+    pub name: String,
+    pub definition: Definition,
+    /// One-based line number in the definitions source
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DefinitionError {
+    /// One-based line number of the offending definition
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for DefinitionError {}
+
+pub fn parse_definitions(source: &str) -> Result<Vec<UnitDefinition>, DefinitionError> {
+    let mut definitions = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |message| DefinitionError {
+            line: line_number,
+            message,
+        };
+
+        let content = match line.split_once('#') {
+            Some((content, _comment)) => content,
+            None => line,
+        }
+        .trim();
+        if content.is_empty() {
+            continue;
+        }
+
+        let (name, definition) = content
+            .split_once(char::is_whitespace)
+            .ok_or(error("Missing unit definition"))?;
+        if !is_valid_name(name) {
+            return Err(error("Invalid unit name"));
+        }
+
+        let definition = definition.trim();
+        let definition = definition.strip_prefix('=').unwrap_or(definition).trim();
+        let definition = match definition {
+            "!" => Definition::Primitive,
+            _ => {
+                let tokens = lexer::lex(definition).map_err(|_| error("Invalid character"))?;
+                Definition::Derived(parser::parse(&tokens).map_err(error)?)
+            }
+        };
+
+        if definitions
+            .iter()
+            .any(|existing: &UnitDefinition| existing.name == name)
+        {
+            return Err(error("Unit defined more than once"));
+        }
+
+        definitions.push(UnitDefinition {
+            name: name.to_string(),
+            definition,
+            line: line_number,
+        });
+    }
+
+    Ok(definitions)
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(char::is_alphabetic)
+        && chars.all(|char| char.is_alphanumeric() || char == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_definitions() {
+        let source = "
+            # Comments and blank lines are skipped
+
+            rack_unit   1.75 in  # trailing comments too
+            sprint    = 2 weeks
+            story_point !
+        ";
+        let definitions = parse_definitions(source).unwrap();
+        assert_eq!(definitions.len(), 3);
+        assert_eq!(definitions[0].name, "rack_unit");
+        assert_eq!(definitions[0].line, 4);
+        assert_eq!(definitions[1].name, "sprint");
+        assert_eq!(definitions[1].line, 5);
+        assert_eq!(definitions[2].definition, Definition::Primitive);
+    }
+
+    #[test]
+    fn test_parse_definitions_errors() {
+        let cases = [
+            ("furlong 201.168 m\nchain", 2, "Missing unit definition"),
+            ("2fast 1 m", 1, "Invalid unit name"),
+            ("\n\nfoo 1 $", 3, "Invalid character"),
+            ("foo 1 m\nfoo 2 m", 2, "Unit defined more than once"),
+        ];
+        for (source, line, message) in cases {
+            assert_eq!(
+                parse_definitions(source),
+                Err(DefinitionError { line, message }),
+                "{}",
+                source
+            );
+        }
+    }
+}
@@ -0,0 +1,698 @@
+//! Symbolic differentiation of expression trees
+//!
+//! The result is not simplified, so `derive(x^2, x)` is `2 * x^(2 - 1) * 1`.
+//! Names other than the variable, such as units and other variables, are constants.
+//! Calls of user-defined functions are derived once the evaluator has inlined their
+//! bodies.
+//!
+//! Aggregates take list literals among their arguments element by element, and any
+//! other argument as a single number.
+//!
+//! Deriving `x^2 + 1` derives `x^2` and `1` in turn, down to the names and numbers,
+//! so [`derive_with_limits`] first checks the height of the tree against the shared
+//! [`Limits::max_depth`].
+
+use crate::evaluator;
+use crate::limits::{self, Limits};
+use crate::parser::Expression;
+
+const NOT_DIFFERENTIABLE: &str = "Expression is not differentiable";
+
+/// Built-in functions without a derivative rule: the gamma function and factorials,
+/// whose derivatives need the digamma function, order statistics, functions of
+/// integers, and matrix functions whose derivatives need a trace or are of integers
+pub const UNSUPPORTED: [&str; 20] = [
+    "gamma",
+    "lgamma",
+    "factorial",
+    "median",
+    "stdev",
+    "variance",
+    "choose",
+    "nCr",
+    "binomial",
+    "nPr",
+    "permutations",
+    "gcd",
+    "lcm",
+    "isprime",
+    "nextprime",
+    "factor",
+    "totient",
+    "powmod",
+    "det",
+    "identity",
+];
+
+/// The derivative of `expression` with respect to `variable`
+pub fn derive(expression: &Expression, variable: &str) -> Result<Expression, &'static str> {
+    derive_with_limits(expression, variable, &Limits::default())
+}
+
+/// Derives like [`derive()`], failing when the expression is taller than the limits allow
+pub fn derive_with_limits(
+    expression: &Expression,
+    variable: &str,
+    limits: &Limits,
+) -> Result<Expression, &'static str> {
+    if expression.height() > limits.max_depth {
+        return Err(limits::TOO_DEEP);
+    }
+    derive_expression(expression, variable)
+}
+
+fn derive_expression(expression: &Expression, variable: &str) -> Result<Expression, &'static str> {
+    if !depends_on(expression, variable) {
+        return match expression {
+            Expression::List(elements) => Ok(Expression::List(vec![number(0.0); elements.len()])),
+            _ if is_numeric(expression) => Ok(number(0.0)),
+            _ => Err(NOT_DIFFERENTIABLE),
+        };
+    }
+
+    let d = |expression: &Expression| derive_expression(expression, variable);
+    Ok(match expression {
+        Expression::NumericLiteral(_) | Expression::BooleanLiteral(_) => number(0.0),
+        Expression::Name(name) => number(if name == variable { 1.0 } else { 0.0 }),
+        Expression::Minus(operand) => Expression::Minus(Box::new(d(operand)?)),
+        // Constant operands are left out rather than derived to zero, as a
+        // dimensionless zero cannot be added to a quantity
+        Expression::Addition(left, right) | Expression::Subtraction(left, right)
+            if !depends_on(left, variable) || !depends_on(right, variable) =>
+        {
+            match (depends_on(left, variable), expression) {
+                (true, _) => d(left)?,
+                (false, Expression::Subtraction(..)) => Expression::Minus(Box::new(d(right)?)),
+                (false, _) => d(right)?,
+            }
+        }
+        Expression::Addition(left, right) => add(d(left)?, d(right)?),
+        Expression::Subtraction(left, right) => subtract(d(left)?, d(right)?),
+        Expression::Multiplication(left, right) if !depends_on(left, variable) => {
+            multiply((**left).clone(), d(right)?)
+        }
+        Expression::Multiplication(left, right) if !depends_on(right, variable) => {
+            multiply(d(left)?, (**right).clone())
+        }
+        // (f g)' = f' g + f g'
+        Expression::Multiplication(left, right) => add(
+            multiply(d(left)?, (**right).clone()),
+            multiply((**left).clone(), d(right)?),
+        ),
+        Expression::Division(left, right) if !depends_on(right, variable) => {
+            divide(d(left)?, (**right).clone())
+        }
+        // (f / g)' = (f' g - f g') / g^2
+        Expression::Division(left, right) => divide(
+            subtract(
+                multiply(d(left)?, (**right).clone()),
+                multiply((**left).clone(), d(right)?),
+            ),
+            power((**right).clone(), number(2.0)),
+        ),
+        Expression::Exponentiation(base, exponent) => derive_power(base, exponent, variable)?,
+        Expression::FunctionCall(name, args) => derive_call(name, args, variable)?,
+        Expression::Conversion(value, unit) => {
+            Expression::Conversion(Box::new(d(value)?), unit.clone())
+        }
+        Expression::List(elements) => {
+            Expression::List(elements.iter().map(d).collect::<Result<_, _>>()?)
+        }
+        Expression::Labeled(_, expression) => d(expression)?,
+        Expression::Factorial(_)
+        | Expression::DoubleFactorial(_)
+        | Expression::Range(..)
+        | Expression::LessThan(..)
+        | Expression::LessThanOrEqual(..)
+        | Expression::GreaterThan(..)
+        | Expression::GreaterThanOrEqual(..)
+        | Expression::Equal(..)
+        | Expression::NotEqual(..)
+        | Expression::ApproximatelyEqual(..)
+        | Expression::And(..)
+        | Expression::Or(..)
+        | Expression::Not(_)
+        | Expression::UnitDefinition(..)
+        | Expression::Assignment(..)
+        | Expression::FunctionDefinition(..) => return Err(NOT_DIFFERENTIABLE),
+    })
+}
+
+fn derive_power(
+    base: &Expression,
+    exponent: &Expression,
+    variable: &str,
+) -> Result<Expression, &'static str> {
+    let (f, g) = (base.clone(), exponent.clone());
+    if !depends_on(exponent, variable) {
+        // (f^c)' = c f^(c - 1) f'
+        return Ok(multiply(
+            multiply(g.clone(), power(f, subtract(g, number(1.0)))),
+            derive_expression(base, variable)?,
+        ));
+    }
+    if !depends_on(base, variable) {
+        // (c^g)' = c^g ln(c) g'
+        return Ok(multiply(
+            multiply(power(f.clone(), g), call("log", vec![f])),
+            derive_expression(exponent, variable)?,
+        ));
+    }
+    // (f^g)' = f^g (g' ln(f) + g f' / f)
+    Ok(multiply(
+        power(f.clone(), g.clone()),
+        add(
+            multiply(
+                derive_expression(exponent, variable)?,
+                call("log", vec![f.clone()]),
+            ),
+            divide(multiply(g, derive_expression(base, variable)?), f),
+        ),
+    ))
+}
+
+fn derive_call(
+    name: &str,
+    args: &[Expression],
+    variable: &str,
+) -> Result<Expression, &'static str> {
+    let d = |expression: &Expression| derive_expression(expression, variable);
+
+    // Functions of several arguments, and those that are not plain functions
+    match (name, args) {
+        ("log", [base, value]) => {
+            return d(&divide(
+                call("log", vec![value.clone()]),
+                call("log", vec![base.clone()]),
+            ))
+        }
+        ("pow", [base, exponent]) => return derive_power(base, exponent, variable),
+        // (atan2(y, x))' = (x y' - y x') / (x^2 + y^2)
+        ("atan2", [y, x]) => {
+            return Ok(divide(
+                subtract(multiply(x.clone(), d(y)?), multiply(y.clone(), d(x)?)),
+                add(power(x.clone(), number(2.0)), power(y.clone(), number(2.0))),
+            ))
+        }
+        ("hypot", [x, y]) => {
+            let sum_of_squares = add(power(x.clone(), number(2.0)), power(y.clone(), number(2.0)));
+            return d(&call("sqrt", vec![sum_of_squares]));
+        }
+        ("if", [condition, then, otherwise]) => {
+            return Ok(call("if", vec![condition.clone(), d(then)?, d(otherwise)?]))
+        }
+        ("piecewise", _) => {
+            let pieces = args
+                .chunks(2)
+                .map(|piece| match piece {
+                    [condition, value] => Ok(vec![condition.clone(), d(value)?]),
+                    [otherwise] => Ok(vec![d(otherwise)?]),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<Vec<_>, &str>>()?;
+            return Ok(call("piecewise", pieces.concat()));
+        }
+        // A sum or product over the variable itself depends on it only through its
+        // limits, which are whole numbers
+        ("sum" | "prod", [Expression::Name(bound), start, end, _]) if bound == variable => {
+            if depends_on(start, variable) || depends_on(end, variable) {
+                return Err(NOT_DIFFERENTIABLE);
+            }
+            return Ok(number(0.0));
+        }
+        ("sum", [Expression::Name(bound), start, end, body]) if bound != variable => {
+            if depends_on(start, variable) || depends_on(end, variable) {
+                return Err(NOT_DIFFERENTIABLE);
+            }
+            let body = d(body)?;
+            return Ok(call(
+                "sum",
+                vec![args[0].clone(), start.clone(), end.clone(), body],
+            ));
+        }
+        // (prod f)' = sum(k, a, b, f' prod(j, a, b, if(j == k, 1, f at j)))
+        ("prod", [Expression::Name(bound), start, end, body]) => {
+            if depends_on(start, variable) || depends_on(end, variable) {
+                return Err(NOT_DIFFERENTIABLE);
+            }
+            let taken = |name: &str| {
+                name == variable || [body, start, end].iter().any(|e| depends_on(e, name))
+            };
+            // The inner product is within the sum, so its bounds must not see the sum's
+            // variable
+            let (outer, mut body) = (fresh_name(bound, taken), body.clone());
+            substitute(&mut body, &[(bound, &Expression::Name(outer.clone()))]);
+            let inner = fresh_name(&outer, taken);
+            let mut other = body.clone();
+            substitute(&mut other, &[(&outer, &Expression::Name(inner.clone()))]);
+            let is_same = Expression::Equal(
+                Box::new(Expression::Name(inner.clone())),
+                Box::new(Expression::Name(outer.clone())),
+            );
+            let factor = call("if", vec![is_same, number(1.0), other]);
+            let others = call(
+                "prod",
+                vec![Expression::Name(inner), start.clone(), end.clone(), factor],
+            );
+            return Ok(call(
+                "sum",
+                vec![
+                    Expression::Name(outer),
+                    start.clone(),
+                    end.clone(),
+                    multiply(d(&body)?, others),
+                ],
+            ));
+        }
+        // Linear in each value
+        ("sum" | "mean", _) => {
+            return Ok(call(name, args.iter().map(d).collect::<Result<_, _>>()?))
+        }
+        // (f g h)' = f' g h + f g' h + f g h', leaving out the constant factors
+        ("prod", _) => {
+            let factors = spread(args);
+            let mut terms = vec![];
+            for (i, factor) in factors.iter().enumerate() {
+                if depends_on(factor, variable) {
+                    let mut others = factors.clone();
+                    others.remove(i);
+                    terms.push(multiply(d(factor)?, call("prod", others)));
+                }
+            }
+            return terms.into_iter().reduce(add).ok_or(NOT_DIFFERENTIABLE);
+        }
+        ("count", _) => return Ok(number(0.0)),
+        // The derivative of the extreme value, of the first one at ties
+        ("max" | "min", _) => {
+            let values = spread(args);
+            let [first, rest @ ..] = values.as_slice() else {
+                return Err(NOT_DIFFERENTIABLE);
+            };
+            let mut derivative = d(first)?;
+            for (i, value) in rest.iter().enumerate() {
+                let (extreme, value) = (Box::new(call(name, values[..=i].to_vec())), value);
+                let kept = match name {
+                    "max" => Expression::GreaterThanOrEqual(extreme, Box::new(value.clone())),
+                    _ => Expression::LessThanOrEqual(extreme, Box::new(value.clone())),
+                };
+                derivative = call("if", vec![kept, derivative, d(value)?]);
+            }
+            return Ok(derivative);
+        }
+        ("clamp", [value, min, max]) => {
+            let below = Expression::LessThan(Box::new(value.clone()), Box::new(min.clone()));
+            let above = Expression::GreaterThan(Box::new(value.clone()), Box::new(max.clone()));
+            let within = call("if", vec![above, d(max)?, d(value)?]);
+            return Ok(call("if", vec![below, d(min)?, within]));
+        }
+        ("round", [_, digits]) if !depends_on(digits, variable) => return Ok(number(0.0)),
+        // mod(a, b) = a - b floor(a / b), where the floor is piecewise constant
+        ("mod", [dividend, divisor]) => {
+            if !depends_on(divisor, variable) {
+                return d(dividend);
+            }
+            let quotient = call("floor", vec![divide(dividend.clone(), divisor.clone())]);
+            let divisor_term = multiply(d(divisor)?, quotient);
+            return Ok(match depends_on(dividend, variable) {
+                true => subtract(d(dividend)?, divisor_term),
+                false => Expression::Minus(Box::new(divisor_term)),
+            });
+        }
+        ("transpose", [matrix]) => return Ok(call("transpose", vec![d(matrix)?])),
+        // (A B)' = A' B + A B'
+        ("matmul", [left, right]) => {
+            let left_term = || d(left).map(|change| call("matmul", vec![change, right.clone()]));
+            let right_term = || d(right).map(|change| call("matmul", vec![left.clone(), change]));
+            return match (depends_on(left, variable), depends_on(right, variable)) {
+                (true, false) => left_term(),
+                (false, _) => right_term(),
+                (true, true) => Ok(add(left_term()?, right_term()?)),
+            };
+        }
+        // (A^-1)' = -A^-1 A' A^-1
+        ("inv" | "inverse", [matrix]) => {
+            let inverse = call("inv", vec![matrix.clone()]);
+            let change = call("matmul", vec![d(matrix)?, inverse.clone()]);
+            return Ok(Expression::Minus(Box::new(call(
+                "matmul",
+                vec![inverse, change],
+            ))));
+        }
+        // x = A^-1 b has x' = A^-1 (b' - A' x)
+        ("solve", [matrix, constants]) if !evaluator::is_special_form(name, args) => {
+            let solution = call("solve", args.to_vec());
+            let matrix_term =
+                || d(matrix).map(|change| call("matmul", vec![change, solution.clone()]));
+            let change = match (
+                depends_on(matrix, variable),
+                depends_on(constants, variable),
+            ) {
+                (false, _) => d(constants)?,
+                (true, false) => Expression::Minus(Box::new(matrix_term()?)),
+                (true, true) => subtract(d(constants)?, matrix_term()?),
+            };
+            return Ok(call("solve", vec![matrix.clone(), change]));
+        }
+        (name, _) if UNSUPPORTED.contains(&name) => return Err(NOT_DIFFERENTIABLE),
+        _ => {}
+    }
+
+    let [argument] = args else {
+        return Err(NOT_DIFFERENTIABLE);
+    };
+    let x = || argument.clone();
+    let outer = match name {
+        "sin" => call("cos", vec![x()]),
+        "cos" => Expression::Minus(Box::new(call("sin", vec![x()]))),
+        "tan" => power(call("sec", vec![x()]), number(2.0)),
+        "sec" => multiply(call("sec", vec![x()]), call("tan", vec![x()])),
+        "csc" => Expression::Minus(Box::new(multiply(
+            call("csc", vec![x()]),
+            call("cot", vec![x()]),
+        ))),
+        "cot" => Expression::Minus(Box::new(power(call("csc", vec![x()]), number(2.0)))),
+        "asin" => divide(number(1.0), call("sqrt", vec![one_minus_square(x())])),
+        "acos" => Expression::Minus(Box::new(divide(
+            number(1.0),
+            call("sqrt", vec![one_minus_square(x())]),
+        ))),
+        "atan" => divide(number(1.0), add(number(1.0), power(x(), number(2.0)))),
+        "sinh" => call("cosh", vec![x()]),
+        "cosh" => call("sinh", vec![x()]),
+        "tanh" => subtract(number(1.0), power(call("tanh", vec![x()]), number(2.0))),
+        "asinh" => divide(
+            number(1.0),
+            call("sqrt", vec![add(power(x(), number(2.0)), number(1.0))]),
+        ),
+        "acosh" => divide(
+            number(1.0),
+            call("sqrt", vec![subtract(power(x(), number(2.0)), number(1.0))]),
+        ),
+        "atanh" => divide(number(1.0), one_minus_square(x())),
+        "exp" | "expm1" => call("exp", vec![x()]),
+        "log" | "ln" => divide(number(1.0), x()),
+        "log2" => divide(number(1.0), multiply(x(), call("log", vec![number(2.0)]))),
+        "log10" => divide(number(1.0), multiply(x(), call("log", vec![number(10.0)]))),
+        "log1p" => divide(number(1.0), add(number(1.0), x())),
+        "sqrt" => divide(number(1.0), multiply(number(2.0), call("sqrt", vec![x()]))),
+        "cbrt" => divide(
+            number(1.0),
+            multiply(number(3.0), power(call("cbrt", vec![x()]), number(2.0))),
+        ),
+        "abs" => call("signum", vec![x()]),
+        // Piecewise constant, so the derivative is zero wherever it exists
+        "ceil" | "floor" | "round" | "trunc" | "signum" => return Ok(number(0.0)),
+        // Special forms other than those above, unknown functions, and user-defined
+        // functions left as calls because they are recursive
+        _ => return Err(NOT_DIFFERENTIABLE),
+    };
+    // Chain rule
+    Ok(multiply(outer, d(argument)?))
+}
+
+/// Replaces the free occurrences of each name by its expression, e.g. to inline the body
+/// of a user-defined function
+///
+/// Variables bound by special forms such as `sum` shadow the names, and are renamed
+/// where they would capture a name of a replacement.
+pub(crate) fn substitute(expression: &mut Expression, bindings: &[(&str, &Expression)]) {
+    match expression {
+        Expression::Name(name) => {
+            if let Some((_, replacement)) = bindings.iter().find(|(bound, _)| bound == name) {
+                *expression = (*replacement).clone();
+            }
+        }
+        Expression::FunctionCall(name, args) => match bound_variable(name, args) {
+            Some((binder, body)) => substitute_bound(args, binder, body, bindings),
+            // `root(f, 1, 2)` names a function rather than a variable
+            None if name == "root" => {
+                for arg in args.iter_mut().skip(1) {
+                    substitute(arg, bindings);
+                }
+            }
+            None => {
+                for arg in args {
+                    substitute(arg, bindings);
+                }
+            }
+        },
+        _ => {
+            for child in expression.children_mut() {
+                substitute(child, bindings);
+            }
+        }
+    }
+}
+
+fn substitute_bound(
+    args: &mut [Expression],
+    binder: usize,
+    body: usize,
+    bindings: &[(&str, &Expression)],
+) {
+    let Expression::Name(variable) = &args[binder] else {
+        unreachable!("checked by bound_variable");
+    };
+    let variable = variable.clone();
+    let inner: Vec<_> = bindings
+        .iter()
+        .filter(|(name, _)| *name != variable)
+        .copied()
+        .collect();
+    let mut renamed = None;
+    if inner
+        .iter()
+        .any(|(_, replacement)| depends_on(replacement, &variable))
+    {
+        let fresh = fresh_name(&variable, |name| {
+            depends_on(&args[body], name)
+                || inner
+                    .iter()
+                    .any(|(bound, replacement)| *bound == name || depends_on(replacement, name))
+        });
+        let fresh_variable = Expression::Name(fresh);
+        substitute(&mut args[body], &[(&variable, &fresh_variable)]);
+        renamed = Some(fresh_variable);
+    }
+    for (index, arg) in args.iter_mut().enumerate() {
+        if index == body {
+            substitute(arg, &inner);
+        } else if index != binder {
+            substitute(arg, bindings);
+        }
+    }
+    if let Some(fresh_variable) = renamed {
+        args[binder] = fresh_variable;
+    }
+}
+
+/// The indices of the variable a special form binds and of the argument it is bound in
+fn bound_variable(name: &str, args: &[Expression]) -> Option<(usize, usize)> {
+    match (name, args) {
+        ("sum" | "prod", [Expression::Name(_), _, _, _]) => Some((0, 3)),
+        ("derive" | "integrate" | "limit", [_, Expression::Name(_), ..]) => Some((1, 0)),
+        ("solve", [_, Expression::Name(_), ..]) if evaluator::is_special_form(name, args) => {
+            Some((1, 0))
+        }
+        _ => None,
+    }
+}
+
+/// The name with the first number appended that makes it not taken, e.g. `k_1`, so
+/// that it still parses
+fn fresh_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    (1..)
+        .map(|number| format!("{}_{}", name, number))
+        .find(|fresh| !taken(fresh))
+        .expect("only finitely many names are taken")
+}
+
+/// The arguments of an aggregate, with list literals spread into their elements
+fn spread(args: &[Expression]) -> Vec<Expression> {
+    args.iter()
+        .flat_map(|arg| match arg {
+            Expression::List(elements) => elements.clone(),
+            arg => vec![arg.clone()],
+        })
+        .collect()
+}
+
+/// Whether the expression refers to the variable anywhere
+fn depends_on(expression: &Expression, variable: &str) -> bool {
+    expression.referenced_names().contains(&variable)
+}
+
+/// Whether the expression has a number or quantity as its value, as opposed to
+/// a boolean or a definition
+fn is_numeric(expression: &Expression) -> bool {
+    !matches!(
+        expression,
+        Expression::BooleanLiteral(_)
+            | Expression::LessThan(..)
+            | Expression::LessThanOrEqual(..)
+            | Expression::GreaterThan(..)
+            | Expression::GreaterThanOrEqual(..)
+            | Expression::Equal(..)
+            | Expression::NotEqual(..)
+            | Expression::ApproximatelyEqual(..)
+            | Expression::And(..)
+            | Expression::Or(..)
+            | Expression::Not(_)
+            | Expression::UnitDefinition(..)
+            | Expression::Assignment(..)
+            | Expression::FunctionDefinition(..)
+    )
+}
+
+fn one_minus_square(x: Expression) -> Expression {
+    subtract(number(1.0), power(x, number(2.0)))
+}
+
+fn number(value: f64) -> Expression {
+    Expression::NumericLiteral(value)
+}
+
+fn call(name: &str, args: Vec<Expression>) -> Expression {
+    Expression::FunctionCall(name.to_string(), args)
+}
+
+fn add(left: Expression, right: Expression) -> Expression {
+    Expression::Addition(Box::new(left), Box::new(right))
+}
+
+fn subtract(left: Expression, right: Expression) -> Expression {
+    Expression::Subtraction(Box::new(left), Box::new(right))
+}
+
+fn multiply(left: Expression, right: Expression) -> Expression {
+    Expression::Multiplication(Box::new(left), Box::new(right))
+}
+
+fn divide(left: Expression, right: Expression) -> Expression {
+    Expression::Division(Box::new(left), Box::new(right))
+}
+
+fn power(base: Expression, exponent: Expression) -> Expression {
+    Expression::Exponentiation(Box::new(base), Box::new(exponent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::evaluator::evaluate_in;
+    use crate::functions::FunctionRegistry;
+    use crate::value::Value;
+    use crate::{lexer, parser};
+
+    fn parse(input: &str) -> Expression {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    fn evaluate_at(expression: &Expression, x: f64) -> f64 {
+        let context = Context::new().with_variables([("x", Value::Number(x))]);
+        evaluate_in(expression, &context)
+            .unwrap()
+            .magnitude()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_derivatives_match_finite_differences() {
+        let functions = [
+            "x^2 * sin(x)",
+            "3 x^3 - 2 x + 7",
+            "-x / (1 + x^2)",
+            "x^x",
+            "2^x",
+            "log(2, x) + ln(x) + log2(x) + log10(x) + log1p(x)",
+            "sqrt(x) + cbrt(x) + abs(x - 2) + exp(x) + expm1(x)",
+            "tan(x) + sec(x) + csc(x) + cot(x) + cos(2 x)",
+            "asin(x / 2) + acos(x / 2) + atan(x)",
+            "sinh(x) + cosh(x) + tanh(x) + asinh(x) + acosh(x + 1) + atanh(x / 2)",
+            "atan2(x, 2) + hypot(x, 3) + pow(x, 3)",
+            "if(x > 1, x^2, 2 x)",
+            "sum(k, 1, 3, k x^k)",
+            "sum(x, 1, 3, x^2) + prod(x, 1, 3, x) x",
+            "floor(x) + 5",
+            "max(x^2, 2 x, 1) + min([x, 1 - x]) + clamp(x^2, 0.2, 1) + round(x, 2)",
+            "mod(3 x, 1) + mod(5, x + 2) + mod(x^2, x + 1)",
+            "prod(x, 2, x^2) + prod(k, 1, 3, k + x) + mean([x, x^2]) + count(x, x)",
+            "sum(matmul([[x, 1], [2, x^2]], [x, 1])) + sum(sum(transpose([[x, x^2]])))",
+            "sum(sum(inv([[x + 2, 1], [1, 3]]))) + sum(solve([[x + 2, 1], [1, 3]], [1, x]))",
+        ];
+        for function in functions {
+            let expression = parse(function);
+            let derivative = derive(&expression, "x").unwrap();
+            for x in [0.3, 0.7, 1.3] {
+                let h = 1e-6;
+                let expected =
+                    (evaluate_at(&expression, x + h) - evaluate_at(&expression, x - h)) / (2.0 * h);
+                let actual = evaluate_at(&derivative, x);
+                assert!(
+                    (actual - expected).abs() < 1e-5 * expected.abs().max(1.0),
+                    "d/dx {} at {}: {} != {}",
+                    function,
+                    x,
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_renamed_variables_parse_back() {
+        let derivative = derive(&parse("prod(k, 1, 3, k + x)"), "x").unwrap();
+        let printed = derivative.to_string();
+        assert!(printed.starts_with("sum(k_1, 1, 3, "), "{}", printed);
+        assert_eq!(parse(&printed), derivative);
+    }
+
+    #[test]
+    fn test_constants_and_other_names() {
+        assert_eq!(derive(&parse("y^2 + 3"), "x"), Ok(number(0.0)));
+        assert_eq!(derive(&parse("x"), "x"), Ok(number(1.0)));
+        assert_eq!(
+            derive(&parse("[x, 2]"), "x"),
+            Ok(Expression::List(vec![number(1.0), number(0.0)]))
+        );
+    }
+
+    #[test]
+    fn test_not_differentiable() {
+        for input in [
+            "x > 1",
+            "x!",
+            "gamma(x)",
+            "median(x, 1)",
+            "identity(x)",
+            "f(x)",
+            "1..x",
+            "sum(x, 1, x, x)",
+        ] {
+            assert_eq!(
+                derive(&parse(input), "x"),
+                Err(NOT_DIFFERENTIABLE),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_every_builtin_has_a_rule_or_is_unsupported() {
+        let registry = FunctionRegistry::standard();
+        for name in registry.names() {
+            let arity = registry.get(name).unwrap().min_arguments.max(1);
+            let call = call(name, vec![parse("x y"); arity]);
+            match derive(&call, "x") {
+                Ok(_) => assert!(!UNSUPPORTED.contains(&name), "{}", name),
+                Err(error) => {
+                    assert_eq!(error, NOT_DIFFERENTIABLE);
+                    assert!(UNSUPPORTED.contains(&name), "{}", name);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,550 @@
+//! Evaluation of whole notepad-style documents, one expression per line
+//!
+//! Later lines may refer to variables assigned on earlier lines and to the values of
+//! earlier lines as `line1`, `line2` and so on. Text around the numbers that does not
+//! evaluate is ignored, so `Rent: $1,200 a month` is `1200`. A line of just `sum` or
+//! `total` adds up the lines above it, back to the nearest blank line.
+//!
+//! As in [`lexer::lex`], `#` and `//` start comments, and text up to a colon is a
+//! label, kept in [`Line::label`].
+//!
+//! ```
+//! use culator::document::evaluate_document;
+//! use culator::value::Value;
+//!
+//! let lines = evaluate_document("Rent: $1200\nGroceries: 450 # weekly\ntotal");
+//! assert_eq!(lines[2].result, Ok(Some(Value::Number(1650.0))));
+//! assert_eq!(lines[0].label.as_deref(), Some("Rent"));
+//! assert_eq!(lines[0].expression, Some(7..11));
+//! ```
+
+use std::ops::Range;
+
+use crate::context::Context;
+use crate::lexer;
+use crate::parser::KEYWORDS;
+use crate::value::Value;
+
+/// The outcome of evaluating one line of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    /// Byte range of the line within the document, without its line ending
+    pub line: Range<usize>,
+    /// The label before the expression, e.g. `Rent` in `Rent: 1200`
+    pub label: Option<String>,
+    /// Byte range of the text that was evaluated, if any
+    pub expression: Option<Range<usize>>,
+    /// The value of the line, or `None` for free text and definitions without a value
+    pub result: Result<Option<Value>, &'static str>,
+    pub warnings: Vec<&'static str>,
+    /// Whether the line adds up the lines above it
+    subtotal: bool,
+    /// Whether the line is empty or whitespace, ending the lines a subtotal adds up
+    blank: bool,
+}
+
+/// Evaluates every line of `source` in a fresh context
+pub fn evaluate_document(source: &str) -> Vec<Line> {
+    evaluate_document_in(source, &mut Context::new())
+}
+
+/// Evaluates every line of `source`, keeping variables and definitions in `context`
+pub fn evaluate_document_in(source: &str, context: &mut Context) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    let mut start = 0;
+    for text in source.split_inclusive('\n') {
+        let end = start + text.len();
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let text = text.strip_suffix('\r').unwrap_or(text);
+        let mut line = evaluate_line(text, context, &lines);
+        line.line = start..start + text.len();
+        line.expression = line
+            .expression
+            .map(|expression| start + expression.start..start + expression.end);
+        line.warnings = context.take_warnings();
+        if let Ok(Some(value)) = &line.result {
+            context.set_variable(&format!("line{}", lines.len() + 1), value.clone());
+        }
+        lines.push(line);
+        start = end;
+    }
+    lines
+}
+
+/// Evaluates a line, with the ranges relative to its start
+fn evaluate_line(text: &str, context: &mut Context, above: &[Line]) -> Line {
+    let mut line = Line {
+        line: 0..text.len(),
+        label: None,
+        expression: None,
+        result: Ok(None),
+        warnings: vec![],
+        subtotal: false,
+        blank: text.trim().is_empty(),
+    };
+    // A label with nothing after it, as in `Total:`, is part of the expression
+    let rest = match lexer::split_label(text) {
+        (Some(label), expression)
+            if !label.is_empty() && !lexer::strip_comment(expression).trim().is_empty() =>
+        {
+            line.label = Some(label.to_string());
+            expression
+        }
+        _ => text,
+    };
+    let offset = text.len() - rest.len();
+    let text = lexer::strip_comment(rest);
+    let pieces = split_pieces(text, context);
+
+    // `sum` and `total` are variables for the line, unless defined otherwise
+    let mut keywords: Vec<&str> = vec![];
+    for (index, piece) in pieces.iter().enumerate() {
+        let word = &text[piece.range.clone()];
+        if piece.kind == Kind::Word
+            && is_subtotal_keyword(word)
+            && !is_assigned(&pieces, index, text)
+            && !text[piece.range.end..].starts_with('(')
+            && context.variable(word).is_none()
+            && !keywords.contains(&word)
+        {
+            keywords.push(word);
+            match subtotal(above) {
+                Ok(value) => context.set_variable(word, value),
+                Err(error) => {
+                    line.expression = Some(piece.range.clone());
+                    line.result = Err(error);
+                    break;
+                }
+            }
+        }
+    }
+    if line.result.is_ok() {
+        (line.expression, line.result) = evaluate_pieces(text, &pieces, context);
+    }
+    for keyword in &keywords {
+        context.remove_variable(keyword);
+    }
+    line.subtotal = !keywords.is_empty();
+    line.expression = line
+        .expression
+        .map(|expression| offset + expression.start..offset + expression.end);
+    line
+}
+
+/// Evaluates the whole line, then all that is not free text unless that joins two
+/// operands, then the longest stretches between free text, until one succeeds
+fn evaluate_pieces(
+    text: &str,
+    pieces: &[Piece],
+    context: &mut Context,
+) -> (Option<Range<usize>>, Result<Option<Value>, &'static str>) {
+    let trimmed = text.trim();
+    if !trimmed.is_empty() {
+        if let Ok(value) = context.evaluate_str(trimmed) {
+            let start = text.len() - text.trim_start().len();
+            return (Some(start..start + trimmed.len()), Ok(value));
+        }
+    }
+
+    let mut candidates = vec![];
+    if !separates_operands(pieces, text) {
+        candidates.push(pieces);
+    }
+    let mut runs: Vec<&[Piece]> = pieces
+        .split(|piece| piece.kind == Kind::Free)
+        .filter(|run| run.len() < pieces.len())
+        .collect();
+    runs.sort_by_key(|run| {
+        let length: usize = run.iter().map(|piece| piece.range.len()).sum();
+        std::cmp::Reverse(length)
+    });
+    candidates.extend(runs);
+
+    let mut first_error = None;
+    for candidate in candidates {
+        let Some(range) = content_range(candidate) else {
+            continue;
+        };
+        let input: String = candidate.iter().map(|piece| piece.text(text)).collect();
+        match context.evaluate_str(&input) {
+            Ok(value) => return (Some(range), Ok(value)),
+            Err(error) => {
+                first_error.get_or_insert((range, error));
+            }
+        }
+    }
+    match first_error {
+        Some((range, error)) => (Some(range), Err(error)),
+        None => (None, Ok(None)),
+    }
+}
+
+fn is_subtotal_keyword(word: &str) -> bool {
+    word.eq_ignore_ascii_case("sum") || word.eq_ignore_ascii_case("total")
+}
+
+/// The sum of the values above, back to the nearest blank line
+fn subtotal(above: &[Line]) -> Result<Value, &'static str> {
+    let values: Vec<Value> = above
+        .iter()
+        .rev()
+        .take_while(|line| !line.blank)
+        .filter(|line| !line.subtotal)
+        .filter_map(|line| match &line.result {
+            Ok(Some(value @ (Value::Number(_) | Value::Quantity(_)))) => Some(value.clone()),
+            _ => None,
+        })
+        .collect();
+    // Added from the top, so that the total is in the units of the first line
+    let mut values = values.into_iter().rev();
+    match values.next() {
+        Some(first) => values.try_fold(first, Value::checked_add),
+        None => Ok(Value::Number(0.0)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    Word,
+    /// Operators, parentheses and the like
+    Symbol,
+    Space,
+    /// Text that is not part of the expression
+    Free,
+}
+
+impl Kind {
+    fn is_kept(self) -> bool {
+        self != Kind::Free
+    }
+}
+
+#[derive(Debug)]
+struct Piece {
+    range: Range<usize>,
+    kind: Kind,
+}
+
+impl Piece {
+    /// The text to evaluate, with thousands separators dropped from numbers
+    fn text<'a>(&self, line: &'a str) -> std::borrow::Cow<'a, str> {
+        let text = &line[self.range.clone()];
+        match self.kind {
+            Kind::Number if text.contains(',') => text.replace(',', "").into(),
+            Kind::Free => " ".into(),
+            _ => text.into(),
+        }
+    }
+}
+
+/// The range from the first to the last number or word of the pieces
+fn content_range(pieces: &[Piece]) -> Option<Range<usize>> {
+    let is_content = |piece: &&Piece| matches!(piece.kind, Kind::Number | Kind::Word);
+    pieces.iter().find(is_content)?;
+    let kept: Vec<&Piece> = pieces
+        .iter()
+        .filter(|piece| !matches!(piece.kind, Kind::Space | Kind::Free))
+        .collect();
+    Some(kept[0].range.start..kept[kept.len() - 1].range.end)
+}
+
+/// Whether free text sits between two operands, which dropping it would multiply
+/// implicitly, as `10` and `200` in `10% of 200`
+fn separates_operands(pieces: &[Piece], line: &str) -> bool {
+    let mut previous_ends_operand = false;
+    let mut free_since = false;
+    for piece in pieces {
+        let text = &line[piece.range.clone()];
+        match piece.kind {
+            Kind::Space => continue,
+            Kind::Free => {
+                free_since = true;
+                continue;
+            }
+            Kind::Number | Kind::Word => {}
+            Kind::Symbol if ["(", "["].contains(&text) => {}
+            Kind::Symbol => {
+                previous_ends_operand = [")", "]", "!"].contains(&text);
+                free_since = false;
+                continue;
+            }
+        }
+        if previous_ends_operand && free_since {
+            return true;
+        }
+        previous_ends_operand = piece.kind != Kind::Symbol;
+        free_since = false;
+    }
+    false
+}
+
+/// Splits a line into numbers, words and symbols, marking what does not look like
+/// part of an expression as free text
+fn split_pieces(line: &str, context: &Context) -> Vec<Piece> {
+    let mut pieces: Vec<Piece> = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    while let Some(char) = line[start..].chars().next() {
+        let rest = &line[start..];
+        let first = char.len_utf8();
+        let (kind, length) = match char {
+            '0'..='9' => (Kind::Number, number_length(rest, depth == 0)),
+            letter if letter.is_alphabetic() => {
+                let length =
+                    prefix_length(&rest[first..], |char| char.is_alphanumeric() || char == '_');
+                (Kind::Word, first + length)
+            }
+            space if space.is_whitespace() => {
+                (Kind::Space, prefix_length(rest, char::is_whitespace))
+            }
+            '(' | '[' => {
+                depth += 1;
+                (Kind::Symbol, first)
+            }
+            ')' | ']' => {
+                depth -= 1;
+                (Kind::Symbol, first)
+            }
+            // Commas only separate arguments and list items
+            ',' if depth > 0 => (Kind::Symbol, first),
+            '+' | '-' | '*' | '/' | '^' | '=' | '<' | '>' | '!' | '~' | '.' => {
+                (Kind::Symbol, first)
+            }
+            _ => (Kind::Free, first),
+        };
+        pieces.push(Piece {
+            range: start..start + length,
+            kind,
+        });
+        start += length;
+    }
+
+    for index in 0..pieces.len() {
+        if pieces[index].kind != Kind::Word {
+            continue;
+        }
+        let word = &line[pieces[index].range.clone()];
+        let follows_expression = pieces[..index]
+            .iter()
+            .rev()
+            .find(|piece| piece.kind != Kind::Space)
+            .is_some_and(|piece| piece.kind.is_kept());
+        let is_known = context.variable(word).is_some()
+            || is_subtotal_keyword(word)
+            || is_line_reference(word)
+            || ["not", "true", "false"].contains(&word)
+            || line[pieces[index].range.end..].starts_with('(')
+            || is_assigned(&pieces, index, line)
+            // Units and operators such as `in` only after a number or the like
+            || follows_expression
+                && (KEYWORDS.contains(&word) || context.lookup_unit(word).is_some());
+        if !is_known {
+            pieces[index].kind = Kind::Free;
+        }
+    }
+    pieces
+}
+
+/// Whether the word at `index` is followed by `=`, as in `rent = 1200`
+fn is_assigned(pieces: &[Piece], index: usize, line: &str) -> bool {
+    let after = line[pieces[index].range.end..].trim_start();
+    after.starts_with('=') && !after.starts_with("==")
+}
+
+/// The length of a number, including thousands separators as in `1,200,000` unless
+/// commas separate arguments
+fn number_length(text: &str, separators: bool) -> usize {
+    let is_number = |char: char| char.is_ascii_digit() || char == '.';
+    let mut length = prefix_length(text, is_number);
+    while let Some(group) = text[length..].strip_prefix(',').filter(|_| separators) {
+        if prefix_length(group, |char| char.is_ascii_digit()) != 3 {
+            break;
+        }
+        length += 1 + prefix_length(group, is_number);
+    }
+    length
+}
+
+fn prefix_length(text: &str, predicate: impl Fn(char) -> bool) -> usize {
+    text.find(|char| !predicate(char)).unwrap_or(text.len())
+}
+
+fn is_line_reference(word: &str) -> bool {
+    word.strip_prefix("line")
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(source: &str) -> Vec<Result<Option<String>, &'static str>> {
+        evaluate_document(source)
+            .into_iter()
+            .map(|line| {
+                line.result
+                    .map(|value| value.map(|value| value.to_string()))
+            })
+            .collect()
+    }
+
+    fn value(value: &str) -> Result<Option<String>, &'static str> {
+        Ok(Some(value.to_string()))
+    }
+
+    #[test]
+    fn test_lines_and_variables() {
+        let source = "rent = 1200\n\
+                      utilities = 150\n\
+                      rent + utilities\n\
+                      \n\
+                      line3 * 12\n\
+                      f(x) = x * 2\n\
+                      f(line5)\n\
+                      2 km + 300 m in m";
+        assert_eq!(
+            results(source),
+            [
+                value("1200"),
+                value("150"),
+                value("1350"),
+                Ok(None),
+                value("16200"),
+                Ok(None),
+                value("32400"),
+                value("2300 m"),
+            ]
+        );
+        assert_eq!(results(""), []);
+        assert_eq!(results("line2"), [Err("Unknown name")]);
+        assert_eq!(
+            results("1 / 0\n1 +\n()\nversion 1.2.3"),
+            [
+                Err("Division by zero"),
+                Err("Empty input"),
+                Ok(None),
+                Err("Invalid number")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_free_text() {
+        let source = "Shopping list\n\
+                      Rent: $1,200 a month\n\
+                      Coffee 3.5 * 20 cups\n\
+                      Meeting in Paris at 10\n\
+                      Rent (monthly): 1200\n\
+                      Bob's budget: line2 - 200\n\
+                      distance = 5 km, roughly\n\
+                      Round trip: 2 distance in m\n\
+                      ----";
+        assert_eq!(
+            results(source),
+            [
+                Ok(None),
+                value("1200"),
+                value("70"),
+                value("10"),
+                value("1200"),
+                value("1000"),
+                value("5 km"),
+                value("10000 m"),
+                Ok(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_free_text_between_numbers() {
+        // Never multiplied implicitly, the longest stretch of code is taken instead
+        let source = "10% of 200\n\
+                      In 2024 I paid 300\n\
+                      Coffee 3.5 times 20\n\
+                      total";
+        assert_eq!(
+            results(source),
+            [value("200"), value("2024"), value("3.5"), value("2227.5")]
+        );
+    }
+
+    #[test]
+    fn test_subtotals() {
+        let source = "Rent: 1200\n\
+                      Food: 450\n\
+                      Notes\n\
+                      Total:\n\
+                      sum * 2\n\
+                      \t\n\
+                      3 m\n\
+                      40 cm\n\
+                      sum\n\
+                      5 s\n\
+                      sum";
+        assert_eq!(
+            results(source),
+            [
+                value("1200"),
+                value("450"),
+                Ok(None),
+                value("1650"),
+                value("3300"),
+                Ok(None),
+                value("3 m"),
+                value("40 cm"),
+                value("3.4 m"),
+                value("5 s"),
+                Err("Incompatible units"),
+            ]
+        );
+        // Variables named like the keywords take precedence
+        assert_eq!(
+            results("total = 3\n4\ntotal"),
+            [value("3"), value("4"), value("3")]
+        );
+    }
+
+    #[test]
+    fn test_comments_and_labels() {
+        let source = "# Budget for 2024\n\
+                      Rent: 1200 // due on the 1st\n\
+                      Food (weekly): 4 * 50 # about 3 meals a day\n\
+                      Total:\n\
+                      // 3 apples";
+        let lines = evaluate_document(source);
+        let labels: Vec<Option<&str>> = lines.iter().map(|line| line.label.as_deref()).collect();
+        assert_eq!(
+            labels,
+            [None, Some("Rent"), Some("Food (weekly)"), None, None]
+        );
+        assert_eq!(
+            results(source),
+            [
+                Ok(None),
+                value("1200"),
+                value("200"),
+                value("1400"),
+                Ok(None)
+            ]
+        );
+        assert_eq!(lines[2].expression, Some(62..68));
+
+        let source = "Item #3: 5 # each\n2 # ratio: halved";
+        let lines = evaluate_document(source);
+        assert_eq!(lines[0].label.as_deref(), Some("Item #3"));
+        assert_eq!(lines[0].expression, Some(9..10));
+        assert_eq!(lines[1].label, None);
+        assert_eq!(results(source), [value("5"), value("2")]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let lines = evaluate_document("  1 + 2  \r\nRent: $1,200 a month\nNotes");
+        assert_eq!(lines[0].line, 0..9);
+        assert_eq!(lines[0].expression, Some(2..7));
+        assert_eq!(lines[1].line, 11..31);
+        assert_eq!(lines[1].expression, Some(18..23));
+        assert_eq!(lines[2].expression, None);
+    }
+}
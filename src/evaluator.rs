@@ -1,118 +1,532 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+
+use crate::calculus;
+use crate::context::{Context, Function};
+use crate::derivative;
+use crate::limits::{self, Limits};
 use crate::parser::Expression;
+use crate::simplify;
+use crate::solve;
+use crate::special;
+use crate::value::Value;
 
 /// Evaluates a parsed expression and returns the numeric result
 pub fn evaluate(expression: &Expression) -> Result<f64, &'static str> {
+    evaluate_number(expression, &Context::default())
+}
+
+/// Evaluates a parsed expression that may carry units, e.g. `1.5 TB in GiB`
+pub fn evaluate_value(expression: &Expression) -> Result<Value, &'static str> {
+    evaluate_in(expression, &Context::default())
+}
+
+/// Evaluates a parsed expression, resolving names against the units defined in `context`
+pub fn evaluate_in(expression: &Expression, context: &Context) -> Result<Value, &'static str> {
+    let _nesting = context.enter()?;
+    evaluate_expression(expression, context)
+}
+
+/// Evaluates a chain like `1 + 2 - 3 * 4` in a loop, see [`Expression`]
+fn evaluate_arithmetic(expression: &Expression, context: &Context) -> Result<Value, &'static str> {
+    type Operation = fn(Value, Value) -> Result<Value, &'static str>;
+    let mut operations: Vec<(Operation, &Expression)> = Vec::new();
+    let mut leftmost = expression;
+    loop {
+        let (operation, left, right): (Operation, _, _) = match leftmost {
+            Expression::Addition(left, right) => (Value::checked_add, left, right),
+            Expression::Subtraction(left, right) => (Value::checked_sub, left, right),
+            Expression::Multiplication(left, right) => (Value::checked_mul, left, right),
+            Expression::Division(left, right) => (Value::checked_div, left, right),
+            _ => break,
+        };
+        operations.push((operation, right));
+        leftmost = left;
+    }
+    operations.into_iter().rev().try_fold(
+        evaluate_in(leftmost, context)?,
+        |result, (operation, right)| operation(result, evaluate_in(right, context)?),
+    )
+}
+
+fn evaluate_expression(expression: &Expression, context: &Context) -> Result<Value, &'static str> {
     match expression {
-        Expression::NumericLiteral(value) => Ok(*value),
+        Expression::NumericLiteral(value) => Ok(Value::Number(*value)),
 
-        Expression::Addition(left, right) => {
-            let left_val = evaluate(left)?;
-            let right_val = evaluate(right)?;
-            Ok(left_val + right_val)
+        Expression::Labeled(_, expression) => evaluate_in(expression, context),
+
+        Expression::Name(name) => {
+            if let Some(value) = context.variable(name) {
+                return Ok(value.clone());
+            }
+            match context.lookup_unit(name) {
+                Some(unit) => Ok(Value::from_unit(name, &unit)),
+                None => Err("Unknown name"),
+            }
         }
 
-        Expression::Subtraction(left, right) => {
-            let left_val = evaluate(left)?;
-            let right_val = evaluate(right)?;
-            Ok(left_val - right_val)
+        Expression::Addition(..)
+        | Expression::Subtraction(..)
+        | Expression::Multiplication(..)
+        | Expression::Division(..) => evaluate_arithmetic(expression, context),
+
+        Expression::Minus(operand) => evaluate_in(operand, context)?.negate(),
+
+        Expression::Exponentiation(left, right) => {
+            evaluate_in(left, context)?.checked_pow(evaluate_in(right, context)?)
         }
 
-        Expression::Minus(operand) => {
-            let val = evaluate(operand)?;
-            Ok(-val)
+        Expression::Factorial(operand) => {
+            special::factorial(evaluate_number(operand, context)?).map(Value::Number)
         }
 
-        Expression::Multiplication(left, right) => {
-            let left_val = evaluate(left)?;
-            let right_val = evaluate(right)?;
-            Ok(left_val * right_val)
+        Expression::DoubleFactorial(operand) => {
+            special::double_factorial(evaluate_number(operand, context)?).map(Value::Number)
         }
 
-        Expression::Division(left, right) => {
-            let left_val = evaluate(left)?;
-            let right_val = evaluate(right)?;
-            if right_val == 0.0 {
-                Err("Division by zero")
+        Expression::Conversion(value, target) => {
+            evaluate_in(value, context)?.convert(evaluate_in(target, context)?)
+        }
+
+        // Special forms only evaluate the arguments they need
+        Expression::FunctionCall(name, args) if name == "if" => {
+            let [condition, then, otherwise] = args.as_slice() else {
+                return Err("if takes a condition and two values");
+            };
+            if evaluate_in(condition, context)?.as_bool()? {
+                evaluate_in(then, context)
             } else {
-                Ok(left_val / right_val)
+                evaluate_in(otherwise, context)
             }
         }
 
-        Expression::Exponentiation(left, right) => {
-            let left_val = evaluate(left)?;
-            let right_val = evaluate(right)?;
-            Ok(left_val.powf(right_val))
+        Expression::FunctionCall(name, args) if name == "piecewise" => {
+            for piece in args.chunks(2) {
+                match piece {
+                    [condition, value] => {
+                        if evaluate_in(condition, context)?.as_bool()? {
+                            return evaluate_in(value, context);
+                        }
+                    }
+                    [otherwise] => return evaluate_in(otherwise, context),
+                    _ => unreachable!(),
+                }
+            }
+            Err("No piecewise condition matched")
         }
 
-        Expression::FunctionCall(name, args) => match (name.as_str(), args.len()) {
-            ("log", 1) => {
-                let arg = evaluate(&args[0])?;
-                if arg <= 0.0 {
-                    return Err("Logarithm of non-positive number");
-                }
-                Ok(arg.ln())
+        // `sum(k, 1, 100, k^2)` binds `k` to each number from 1 to 100 in turn
+        Expression::FunctionCall(name, args)
+            if (name == "sum" || name == "prod") && is_bound_iteration(args) =>
+        {
+            let [Expression::Name(variable), start, end, body] = args.as_slice() else {
+                unreachable!("checked by is_bound_iteration");
+            };
+            let start = evaluate_in(start, context)?;
+            let (first, count) = steps(&start, &evaluate_in(end, context)?)?;
+            let mut result: Option<Value> = None;
+            // The range is not built as a list, so the step limit rather than the list
+            // length limit bounds it
+            for i in 0..count {
+                let value = Value::Number(first + i as f64);
+                let scope = context.with_variables([(variable.as_str(), value)]);
+                let term = evaluate_in(body, &scope)?;
+                result = Some(match result {
+                    None => term,
+                    Some(result) if name == "sum" => result.checked_add(term)?,
+                    Some(result) => result.checked_mul(term)?,
+                });
             }
-            ("log10", 1) => {
-                let arg = evaluate(&args[0])?;
-                if arg <= 0.0 {
-                    return Err("Logarithm of non-positive number");
-                }
-                Ok(arg.log10())
+            // The empty sum is zero, the empty product one
+            Ok(result.unwrap_or(Value::Number(if name == "sum" { 0.0 } else { 1.0 })))
+        }
+
+        // `derive(x^2, x, 3)` is the derivative with respect to `x` at 3, and
+        // `derive(x^2, x)` the simplified derivative itself, `2 x`
+        Expression::FunctionCall(name, args) if name == "derive" => match args.as_slice() {
+            [body, Expression::Name(variable)] => {
+                let derivative = derive(body, variable, context)?;
+                let derivative = simplify::simplify_with_limits(&derivative, context.limits())?;
+                Ok(Value::Expression(derivative))
             }
-            ("log", 2) => {
-                let base = evaluate(&args[0])?;
-                let value = evaluate(&args[1])?;
-                if base <= 0.0 || base == 1.0 || value <= 0.0 {
-                    return Err("Invalid logarithm base or value");
-                }
-                Ok(value.log(base))
+            [body, Expression::Name(variable), at] => {
+                let derivative = derive(body, variable, context)?;
+                let scope =
+                    context.with_variables([(variable.as_str(), evaluate_in(at, context)?)]);
+                evaluate_in(&derivative, &scope)
             }
-            ("pow", 2) => {
-                let base = evaluate(&args[0])?;
-                let exponent = evaluate(&args[1])?;
-                Ok(base.powf(exponent))
+            _ => Err("derive takes an expression, a variable and optionally a point"),
+        },
+
+        // `integrate(x^2, x, 0, 3)` is the definite integral of `x^2` from 0 to 3
+        Expression::FunctionCall(name, args) if name == "integrate" => {
+            let [integrand, Expression::Name(variable), start, end] = args.as_slice() else {
+                return Err("integrate takes an expression, a variable and two bounds");
+            };
+            let start = evaluate_in(start, context)?;
+            let end = evaluate_in(end, context)?;
+            start.clone().checked_sub(end.clone())?;
+            let sample = RefCell::new(None);
+            let f = |x: f64| {
+                sample_at(
+                    integrand,
+                    variable,
+                    start.with_magnitude(x)?,
+                    &sample,
+                    context,
+                )
+            };
+            let integral = calculus::integrate(&f, start.magnitude()?, end.magnitude()?)?;
+            // An error estimate above the tolerance, or a discontinuity, is reported
+            if let Some(warning) = integral.warning() {
+                context.warn(warning);
             }
-            ("sqrt", 1) => {
-                let arg = evaluate(&args[0])?;
-                if arg < 0.0 {
-                    return Err("Square root of negative number");
+            // The integral has the dimension of the integrand times that of the variable
+            let sample = sample.into_inner().expect("the integrand was evaluated");
+            sample
+                .with_magnitude(integral.value)?
+                .checked_mul(start.with_magnitude(1.0)?)
+        }
+
+        // `limit(sin(x) / x, x, 0)` is the value `sin(x) / x` approaches as `x` approaches 0
+        Expression::FunctionCall(name, args) if name == "limit" => {
+            let [body, Expression::Name(variable), point] = args.as_slice() else {
+                return Err("limit takes an expression, a variable and a point");
+            };
+            let point = evaluate_in(point, context)?;
+            let sample = RefCell::new(None);
+            let f = |x: f64| sample_at(body, variable, point.with_magnitude(x)?, &sample, context);
+            let limit = calculus::limit(&f, point.magnitude()?)?;
+            let sample = sample.into_inner().expect("the expression was evaluated");
+            sample.with_magnitude(limit)
+        }
+
+        // `solve(x^2 = 2, x)` lists the real roots, `solve(x^2 = 2, x, 0, 10)` those in 0..10
+        Expression::FunctionCall(name, args) if name == "solve" && is_equation(args) => {
+            let (equation, variable, interval) = match args.as_slice() {
+                [equation, Expression::Name(variable)] => (equation, variable, None),
+                [equation, Expression::Name(variable), start, end] => {
+                    (equation, variable, Some((start, end)))
                 }
-                Ok(arg.sqrt())
-            }
-            ("abs", 1) => {
-                let arg = evaluate(&args[0])?;
-                Ok(arg.abs())
-            }
-            ("sin", 1) => Ok(evaluate(&args[0])?.sin()),
-            ("cos", 1) => Ok(evaluate(&args[0])?.cos()),
-            ("tan", 1) => Ok(evaluate(&args[0])?.tan()),
-            ("asin", 1) => Ok(evaluate(&args[0])?.asin()),
-            ("acos", 1) => Ok(evaluate(&args[0])?.acos()),
-            ("atan", 1) => Ok(evaluate(&args[0])?.atan()),
-            ("exp", 1) => Ok(evaluate(&args[0])?.exp()),
-            ("cbrt", 1) => {
-                let arg = evaluate(&args[0])?;
-                if arg < 0.0 {
-                    return Err("Cube root of negative number");
+                _ => unreachable!("checked by is_equation"),
+            };
+            let (start, end) = match interval {
+                Some((start, end)) => (
+                    evaluate_number(start, context)?,
+                    evaluate_number(end, context)?,
+                ),
+                None => solve::DEFAULT_INTERVAL,
+            };
+            let difference = match equation {
+                Expression::Equal(left, right) => {
+                    Expression::Subtraction(left.clone(), right.clone())
                 }
-                Ok(arg.cbrt())
-            }
-            ("ceil", 1) => Ok(evaluate(&args[0])?.ceil()),
-            ("floor", 1) => Ok(evaluate(&args[0])?.floor()),
-            ("round", 1) => Ok(evaluate(&args[0])?.round()),
-            ("trunc", 1) => Ok(evaluate(&args[0])?.trunc()),
-            ("signum", 1) => Ok(evaluate(&args[0])?.signum()),
-            ("factorial", 1) => {
-                let arg = evaluate(&args[0])?;
-                if arg < 0.0 || arg.fract() != 0.0 {
-                    return Err("Factorial of negative or non-integer number");
+                _ => equation.clone(),
+            };
+            let roots = find_roots(&difference, variable, context, |f, derivative| {
+                solve::roots(f, derivative, start, end)
+            })?;
+            Ok(Value::List(roots.into_iter().map(Value::Number).collect()))
+        }
+
+        // `root(f, 0, 2)` is a root of the function `f` between 0 and 2
+        Expression::FunctionCall(name, args) if name == "root" => {
+            let [Expression::Name(function), start, end] = args.as_slice() else {
+                return Err("root takes a function and two bounds");
+            };
+            let (body, variable) = match context.function(function) {
+                Some(function) if function.parameters.len() == 1 => {
+                    (function.body.clone(), function.parameters[0].clone())
                 }
-                let n = arg as u64;
-                Ok((1..=n).product::<u64>() as f64)
+                _ => (
+                    Expression::FunctionCall(function.clone(), vec![Expression::Name("x".into())]),
+                    "x".to_string(),
+                ),
+            };
+            let (start, end) = (
+                evaluate_number(start, context)?,
+                evaluate_number(end, context)?,
+            );
+            let root = find_roots(&body, &variable, context, |f, derivative| {
+                match solve::bracketed_root(f, derivative, start, end) {
+                    // Without a sign change at the bounds, search between them
+                    Err(solve::NO_SIGN_CHANGE) => solve::roots(f, derivative, start, end)?
+                        .first()
+                        .copied()
+                        .ok_or("No root between the bounds"),
+                    result => result,
+                }
+            })?;
+            Ok(Value::Number(root))
+        }
+
+        Expression::FunctionCall(name, args) => {
+            if let Some(function) = context.function(name) {
+                return call_user_function(function, args, context);
             }
-            _ => Err("Unknown function"),
-        },
+            let function = context.builtins().get(name).ok_or("Unknown function")?;
+            let arguments = evaluate_all(args, context)?;
+            context.limits().check_arguments(function, &arguments)?;
+            function.call(&arguments)
+        }
+
+        Expression::List(elements) => Ok(Value::List(evaluate_all(elements, context)?)),
+
+        Expression::Range(start, end) => Ok(Value::List(range(start, end, context)?)),
+
+        Expression::BooleanLiteral(value) => Ok(Value::Bool(*value)),
+
+        Expression::LessThan(left, right) => {
+            compare(left, right, context, |ordering| ordering.is_lt())
+        }
+
+        Expression::LessThanOrEqual(left, right) => {
+            compare(left, right, context, |ordering| ordering.is_le())
+        }
+
+        Expression::GreaterThan(left, right) => {
+            compare(left, right, context, |ordering| ordering.is_gt())
+        }
+
+        Expression::GreaterThanOrEqual(left, right) => {
+            compare(left, right, context, |ordering| ordering.is_ge())
+        }
+
+        Expression::Equal(left, right) => {
+            let left = evaluate_in(left, context)?;
+            Ok(Value::Bool(left.equals(&evaluate_in(right, context)?)?))
+        }
+
+        Expression::NotEqual(left, right) => {
+            let left = evaluate_in(left, context)?;
+            Ok(Value::Bool(!left.equals(&evaluate_in(right, context)?)?))
+        }
+
+        Expression::ApproximatelyEqual(left, right) => {
+            let left = evaluate_in(left, context)?;
+            let equal = left.approximately_equals(&evaluate_in(right, context)?)?;
+            Ok(Value::Bool(equal))
+        }
+
+        // `and` and `or` short-circuit, so the right side is only evaluated when needed
+        Expression::And(left, right) => {
+            if !evaluate_in(left, context)?.as_bool()? {
+                return Ok(Value::Bool(false));
+            }
+            Ok(Value::Bool(evaluate_in(right, context)?.as_bool()?))
+        }
+
+        Expression::Or(left, right) => {
+            if evaluate_in(left, context)?.as_bool()? {
+                return Ok(Value::Bool(true));
+            }
+            Ok(Value::Bool(evaluate_in(right, context)?.as_bool()?))
+        }
+
+        Expression::Not(operand) => Ok(Value::Bool(!evaluate_in(operand, context)?.as_bool()?)),
+
+        Expression::UnitDefinition(..)
+        | Expression::Assignment(..)
+        | Expression::FunctionDefinition(..) => {
+            Err("Definitions are only allowed at the top level")
+        }
+    }
+}
+
+fn call_user_function(
+    function: &Function,
+    args: &[Expression],
+    context: &Context,
+) -> Result<Value, &'static str> {
+    if args.len() != function.parameters.len() {
+        return Err("Wrong number of function arguments");
+    }
+    let mut arguments = vec![];
+    for (parameter, arg) in function.parameters.iter().zip(args) {
+        arguments.push((parameter.as_str(), evaluate_in(arg, context)?));
+    }
+    evaluate_in(&function.body, &context.with_variables(arguments))
+}
+
+/// Whether a call is one of the special forms above rather than a function call, as
+/// special forms control how their arguments are evaluated
+pub(crate) fn is_special_form(name: &str, args: &[Expression]) -> bool {
+    match name {
+        "if" | "piecewise" | "derive" | "integrate" | "limit" | "root" => true,
+        "sum" | "prod" => is_bound_iteration(args),
+        "solve" => is_equation(args),
+        _ => false,
+    }
+}
+
+/// Whether the arguments are a variable, bounds, and a body, `sum(k, 1, 3, 2)` binding
+/// `k` even where the body does not use it
+fn is_bound_iteration(args: &[Expression]) -> bool {
+    matches!(args, [Expression::Name(_), _, _, _])
+}
+
+/// Whether `solve` is given an equation and a variable rather than a matrix and a vector
+fn is_equation(args: &[Expression]) -> bool {
+    match args {
+        [Expression::Equal(..), Expression::Name(_), ..] => {
+            matches!(args.len(), 2 | 4)
+        }
+        [expression, Expression::Name(variable)]
+        | [expression, Expression::Name(variable), _, _] => {
+            expression.referenced_names().contains(&variable.as_str())
+        }
+        _ => false,
+    }
+}
+
+/// Runs a root finder on `expression` as a function of `variable`, refining with its
+/// derivative where it can be derived symbolically
+fn find_roots<T>(
+    expression: &Expression,
+    variable: &str,
+    context: &Context,
+    finder: impl FnOnce(solve::RealFunction, Option<solve::RealFunction>) -> Result<T, &'static str>,
+) -> Result<T, &'static str> {
+    let at = |expression: &Expression, x: f64| {
+        let scope = context.with_variables([(variable, Value::Number(x))]);
+        evaluate_in(expression, &scope)?.magnitude()
+    };
+    let f = |x: f64| at(expression, x);
+    match derive(expression, variable, context) {
+        Ok(derivative) => finder(&f, Some(&|x: f64| at(&derivative, x))),
+        Err(_) => finder(&f, None),
+    }
+}
+
+/// The symbolic derivative of `expression`, which recurses through every operand, so
+/// only of expressions no taller than the depth limit
+fn derive(
+    expression: &Expression,
+    variable: &str,
+    context: &Context,
+) -> Result<Expression, &'static str> {
+    if expression.height() > context.limits().max_depth {
+        return Err(limits::TOO_DEEP);
+    }
+    let mut expression = expression.clone();
+    inline(&mut expression, context, &mut vec![])?;
+    derivative::derive_with_limits(&expression, variable, context.limits())
+}
+
+/// Replaces calls of user-defined functions by their bodies with the arguments
+/// substituted, leaving recursive calls, which cannot be inlined, as they are
+fn inline(
+    expression: &mut Expression,
+    context: &Context,
+    inlining: &mut Vec<String>,
+) -> Result<(), &'static str> {
+    // Inlined bodies may repeat arguments, so the steps bound the size of the result
+    let _nesting = context.enter()?;
+    for child in expression.children_mut() {
+        inline(child, context, inlining)?;
+    }
+    let Expression::FunctionCall(name, args) = expression else {
+        return Ok(());
+    };
+    if is_special_form(name, args) || inlining.contains(name) {
+        return Ok(());
+    }
+    let Some(function) = context.function(name) else {
+        return Ok(());
+    };
+    if function.parameters.len() != args.len() {
+        return Ok(());
     }
+    let mut body = function.body.clone();
+    inlining.push(name.clone());
+    let inlined = inline(&mut body, context, inlining);
+    inlining.pop();
+    inlined?;
+    let bindings: Vec<_> = function
+        .parameters
+        .iter()
+        .map(String::as_str)
+        .zip(args.iter())
+        .collect();
+    derivative::substitute(&mut body, &bindings);
+    *expression = body;
+    Ok(())
+}
+
+/// The magnitude of `expression` with `variable` bound to `value`, keeping the value in
+/// `sample` so that numeric results can be given its dimension
+fn sample_at(
+    expression: &Expression,
+    variable: &str,
+    value: Value,
+    sample: &RefCell<Option<Value>>,
+    context: &Context,
+) -> Result<f64, &'static str> {
+    let value = evaluate_in(expression, &context.with_variables([(variable, value)]))?;
+    let magnitude = value.magnitude();
+    sample.replace(Some(value));
+    magnitude
+}
+
+/// The numbers from `start` up to and including `end`, in steps of one
+fn range(
+    start: &Expression,
+    end: &Expression,
+    context: &Context,
+) -> Result<Vec<Value>, &'static str> {
+    range_between(
+        &evaluate_in(start, context)?,
+        &evaluate_in(end, context)?,
+        context.limits(),
+    )
+}
+
+pub(crate) fn range_between(
+    start: &Value,
+    end: &Value,
+    limits: &Limits,
+) -> Result<Vec<Value>, &'static str> {
+    let (start, count) = steps(start, end)?;
+    if count > limits.max_list_length as u64 {
+        return Err(limits::LIST_TOO_LONG);
+    }
+    Ok((0..count)
+        .map(|i| Value::Number(start + i as f64))
+        .collect())
+}
+
+/// The first number of a range and how many numbers it has
+fn steps(start: &Value, end: &Value) -> Result<(f64, u64), &'static str> {
+    let (start, end) = (start.as_number()?, end.as_number()?);
+    if !start.is_finite() || !end.is_finite() {
+        return Err("Range bounds must be finite");
+    }
+    // Saturates for ranges too long to count, which the step limit stops
+    let count = ((end - start).floor() + 1.0).max(0.0) as u64;
+    Ok((start, count))
+}
+
+fn evaluate_all(expressions: &[Expression], context: &Context) -> Result<Vec<Value>, &'static str> {
+    expressions
+        .iter()
+        .map(|expression| evaluate_in(expression, context))
+        .collect()
+}
+
+/// Compares two values, where comparisons involving NaN are always false
+fn compare(
+    left: &Expression,
+    right: &Expression,
+    context: &Context,
+    predicate: impl Fn(Ordering) -> bool,
+) -> Result<Value, &'static str> {
+    let left = evaluate_in(left, context)?;
+    let ordering = left.compare(&evaluate_in(right, context)?)?;
+    Ok(Value::Bool(ordering.is_some_and(predicate)))
+}
+
+fn evaluate_number(expression: &Expression, context: &Context) -> Result<f64, &'static str> {
+    evaluate_in(expression, context)?.as_number()
 }
 
 #[cfg(test)]
@@ -224,4 +638,48 @@ mod tests {
         );
         assert_eq!(evaluate(&expr).unwrap(), 512.0);
     }
+
+    #[test]
+    fn test_unit_conversion() {
+        // 1.5 TB in GB = 1500 GB
+        let expr = Expression::Conversion(
+            Box::new(Expression::Multiplication(
+                Box::new(Expression::NumericLiteral(1.5)),
+                Box::new(Expression::Name("TB".into())),
+            )),
+            Box::new(Expression::Name("GB".into())),
+        );
+        assert_eq!(evaluate_value(&expr).unwrap().to_string(), "1500 GB");
+        assert!(evaluate(&expr).is_err());
+    }
+
+    #[test]
+    fn test_unknown_name() {
+        let expr = Expression::Name("foo".into());
+        assert!(evaluate_value(&expr).is_err());
+    }
+
+    #[test]
+    fn test_logical_operators_short_circuit() {
+        // The division by zero on the right is never evaluated
+        let division_by_zero = || {
+            Box::new(Expression::Equal(
+                Box::new(Expression::Division(
+                    Box::new(Expression::NumericLiteral(1.0)),
+                    Box::new(Expression::NumericLiteral(0.0)),
+                )),
+                Box::new(Expression::NumericLiteral(1.0)),
+            ))
+        };
+        let expr = Expression::And(
+            Box::new(Expression::BooleanLiteral(false)),
+            division_by_zero(),
+        );
+        assert_eq!(evaluate_value(&expr).unwrap(), Value::Bool(false));
+        let expr = Expression::Or(
+            Box::new(Expression::BooleanLiteral(true)),
+            division_by_zero(),
+        );
+        assert_eq!(evaluate_value(&expr).unwrap(), Value::Bool(true));
+    }
 }
@@ -0,0 +1,451 @@
+//! The registry of built-in functions that calls are dispatched through
+//!
+//! Hosts can extend or restrict the functions available to an expression through
+//! [`crate::context::Context::builtins_mut`]:
+//!
+//! ```
+//! use culator::context::Context;
+//! use culator::functions::Builtin;
+//!
+//! let mut context = Context::new();
+//! let builtins = context.builtins_mut();
+//! builtins.retain(|function| function.pure);
+//! builtins.register(Builtin::unary("double", |x| 2.0 * x).doc("Twice the number"));
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, OnceLock};
+
+use crate::value::Value;
+use crate::{aggregates, matrix, number_theory, special};
+
+pub type Implementation = Arc<dyn Fn(&[Value]) -> Result<Value, &'static str> + Send + Sync>;
+
+/// A requirement on one numeric argument, checked before the function is called
+#[derive(Clone, Copy)]
+pub struct DomainCheck {
+    /// Zero-based index of the checked argument
+    pub argument: usize,
+    pub predicate: fn(f64) -> bool,
+    pub message: &'static str,
+}
+
+/// A built-in function and its metadata
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub min_arguments: usize,
+    /// `None` for functions taking any number of arguments
+    pub max_arguments: Option<usize>,
+    pub doc: String,
+    /// Whether the result depends on nothing but the arguments
+    pub pure: bool,
+    pub domain: Vec<DomainCheck>,
+    /// Whether the arguments are integers, bounded by [`Limits::max_integer`](crate::limits::Limits)
+    pub integer_arguments: bool,
+    implementation: Implementation,
+}
+
+impl Builtin {
+    pub fn new(
+        name: &str,
+        arity: impl RangeBounds<usize>,
+        implementation: impl Fn(&[Value]) -> Result<Value, &'static str> + Send + Sync + 'static,
+    ) -> Builtin {
+        let min_arguments = match arity.start_bound() {
+            Bound::Included(min) => *min,
+            Bound::Excluded(min) => min + 1,
+            Bound::Unbounded => 0,
+        };
+        let max_arguments = match arity.end_bound() {
+            Bound::Included(max) => Some(*max),
+            Bound::Excluded(max) => Some(max.saturating_sub(1)),
+            Bound::Unbounded => None,
+        };
+        Builtin {
+            name: name.to_string(),
+            aliases: vec![],
+            min_arguments,
+            max_arguments,
+            doc: String::new(),
+            pure: true,
+            domain: vec![],
+            integer_arguments: false,
+            implementation: Arc::new(implementation),
+        }
+    }
+
+    /// A function of dimensionless numbers
+    pub fn numeric(
+        name: &str,
+        arity: impl RangeBounds<usize>,
+        implementation: impl Fn(&[f64]) -> Result<f64, &'static str> + Send + Sync + 'static,
+    ) -> Builtin {
+        Builtin::new(name, arity, move |arguments| {
+            // Few arguments fit on the stack, as calls are frequent in compiled expressions
+            let mut buffer = [0.0; 4];
+            let allocated;
+            let numbers = if arguments.len() <= buffer.len() {
+                for (number, argument) in buffer.iter_mut().zip(arguments) {
+                    *number = argument.as_number()?;
+                }
+                &buffer[..arguments.len()]
+            } else {
+                allocated = arguments
+                    .iter()
+                    .map(Value::as_number)
+                    .collect::<Result<Vec<_>, _>>()?;
+                &allocated
+            };
+            implementation(numbers).map(Value::Number)
+        })
+    }
+
+    /// A function of a single dimensionless number that cannot fail
+    pub fn unary(name: &str, implementation: fn(f64) -> f64) -> Builtin {
+        Builtin::new(name, 1..=1, move |arguments| {
+            Ok(Value::Number(implementation(arguments[0].as_number()?)))
+        })
+    }
+
+    /// A function of two dimensionless numbers that cannot fail
+    pub fn binary(name: &str, implementation: fn(f64, f64) -> f64) -> Builtin {
+        Builtin::new(name, 2..=2, move |arguments| {
+            let (left, right) = (arguments[0].as_number()?, arguments[1].as_number()?);
+            Ok(Value::Number(implementation(left, right)))
+        })
+    }
+
+    pub fn alias(mut self, alias: &str) -> Builtin {
+        self.aliases.push(alias.to_string());
+        self
+    }
+
+    pub fn doc(mut self, doc: &str) -> Builtin {
+        self.doc = doc.to_string();
+        self
+    }
+
+    /// Marks the function as depending on more than its arguments, e.g. on the time
+    pub fn impure(mut self) -> Builtin {
+        self.pure = false;
+        self
+    }
+
+    /// Marks the arguments as integers, whose size the limits of a context bound
+    pub fn integers(mut self) -> Builtin {
+        self.integer_arguments = true;
+        self
+    }
+
+    /// Requires `predicate` to hold for the numeric argument at `argument`
+    pub fn domain(
+        mut self,
+        argument: usize,
+        predicate: fn(f64) -> bool,
+        message: &'static str,
+    ) -> Builtin {
+        self.domain.push(DomainCheck {
+            argument,
+            predicate,
+            message,
+        });
+        self
+    }
+
+    pub fn accepts_arguments(&self, count: usize) -> bool {
+        count >= self.min_arguments && self.max_arguments.is_none_or(|max| count <= max)
+    }
+
+    pub fn call(&self, arguments: &[Value]) -> Result<Value, &'static str> {
+        if !self.accepts_arguments(arguments.len()) {
+            return Err("Wrong number of function arguments");
+        }
+        for check in &self.domain {
+            if let Some(argument) = arguments.get(check.argument) {
+                if !(check.predicate)(argument.as_number()?) {
+                    return Err(check.message);
+                }
+            }
+        }
+        (self.implementation)(arguments)
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .field("aliases", &self.aliases)
+            .field("min_arguments", &self.min_arguments)
+            .field("max_arguments", &self.max_arguments)
+            .field("pure", &self.pure)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Built-in functions by name, with aliases resolving to the same function
+///
+/// Functions are shared, with copies of the registry and with compiled expressions.
+#[derive(Debug, Clone)]
+pub struct FunctionRegistry {
+    functions: BTreeMap<String, Arc<Builtin>>,
+    aliases: HashMap<String, String>,
+}
+
+impl Default for FunctionRegistry {
+    /// All built-in functions
+    fn default() -> FunctionRegistry {
+        FunctionRegistry::clone(FunctionRegistry::standard())
+    }
+}
+
+impl FunctionRegistry {
+    /// A registry without any functions
+    pub fn empty() -> FunctionRegistry {
+        FunctionRegistry {
+            functions: BTreeMap::new(),
+            aliases: HashMap::new(),
+        }
+    }
+
+    /// The shared registry of all built-in functions
+    pub fn standard() -> &'static Arc<FunctionRegistry> {
+        static STANDARD: OnceLock<Arc<FunctionRegistry>> = OnceLock::new();
+        STANDARD.get_or_init(|| {
+            let mut registry = FunctionRegistry::empty();
+            register_elementary(&mut registry);
+            special::register(&mut registry);
+            aggregates::register(&mut registry);
+            matrix::register(&mut registry);
+            number_theory::register(&mut registry);
+            Arc::new(registry)
+        })
+    }
+
+    /// Adds a function, replacing any function of the same name or alias
+    pub fn register(&mut self, function: Builtin) {
+        for name in std::iter::once(&function.name).chain(&function.aliases) {
+            self.remove(name);
+        }
+        for alias in &function.aliases {
+            self.aliases.insert(alias.clone(), function.name.clone());
+        }
+        self.functions
+            .insert(function.name.clone(), Arc::new(function));
+    }
+
+    /// Removes a function by its name or one of its aliases
+    pub fn remove(&mut self, name: &str) -> Option<Builtin> {
+        let name = self.aliases.get(name).cloned().unwrap_or(name.to_string());
+        let function = self.functions.remove(&name)?;
+        self.aliases.retain(|_, target| *target != name);
+        Some(Arc::unwrap_or_clone(function))
+    }
+
+    /// Keeps only the functions for which `keep` returns true
+    pub fn retain(&mut self, mut keep: impl FnMut(&Builtin) -> bool) {
+        self.functions.retain(|_, function| keep(function));
+        let functions = &self.functions;
+        self.aliases
+            .retain(|_, target| functions.contains_key(target));
+    }
+
+    /// Looks up a function by its name or one of its aliases
+    pub fn get(&self, name: &str) -> Option<&Builtin> {
+        self.get_shared(name).map(Arc::as_ref)
+    }
+
+    /// Looks up a function to keep, e.g. in a compiled expression, without copying it
+    pub(crate) fn get_shared(&self, name: &str) -> Option<&Arc<Builtin>> {
+        let name = self.aliases.get(name).map_or(name, String::as_str);
+        self.functions.get(name)
+    }
+
+    /// All functions, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = &Builtin> {
+        self.functions.values().map(Arc::as_ref)
+    }
+
+    /// Every name and alias a function can be called by, in alphabetical order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self
+            .functions
+            .keys()
+            .chain(self.aliases.keys())
+            .map(String::as_str)
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn call(&self, name: &str, arguments: &[Value]) -> Result<Value, &'static str> {
+        self.get(name).ok_or("Unknown function")?.call(arguments)
+    }
+}
+
+fn register_elementary(registry: &mut FunctionRegistry) {
+    let positive = |x: f64| x > 0.0;
+    let functions = [
+        Builtin::numeric("log", 1..=2, |arguments| match *arguments {
+            [value] if value > 0.0 => Ok(value.ln()),
+            [_] => Err("Logarithm of non-positive number"),
+            [base, value] if base > 0.0 && base != 1.0 && value > 0.0 => Ok(value.log(base)),
+            _ => Err("Invalid logarithm base or value"),
+        })
+        .alias("ln")
+        .doc("Natural logarithm, or logarithm to the base given first"),
+        Builtin::unary("log2", f64::log2)
+            .domain(0, positive, "Logarithm of non-positive number")
+            .doc("Binary logarithm"),
+        Builtin::unary("log10", f64::log10)
+            .domain(0, positive, "Logarithm of non-positive number")
+            .doc("Decimal logarithm"),
+        Builtin::unary("log1p", f64::ln_1p)
+            .domain(0, |x| x > -1.0, "Logarithm of non-positive number")
+            .doc("ln(1 + x), accurate for small x"),
+        Builtin::unary("exp", f64::exp).doc("e to the power of x"),
+        Builtin::unary("expm1", f64::exp_m1).doc("exp(x) - 1, accurate for small x"),
+        Builtin::binary("pow", f64::powf).doc("The base raised to the exponent"),
+        Builtin::unary("sqrt", f64::sqrt)
+            .domain(0, |x| x >= 0.0, "Square root of negative number")
+            .doc("Square root"),
+        Builtin::unary("cbrt", f64::cbrt)
+            .domain(0, |x| x >= 0.0, "Cube root of negative number")
+            .doc("Cube root"),
+        Builtin::binary("hypot", f64::hypot).doc("Length of the hypotenuse, sqrt(x^2 + y^2)"),
+        Builtin::unary("abs", f64::abs).doc("Absolute value"),
+        Builtin::unary("sin", f64::sin).doc("Sine of an angle in radians"),
+        Builtin::unary("cos", f64::cos).doc("Cosine of an angle in radians"),
+        Builtin::unary("tan", f64::tan).doc("Tangent of an angle in radians"),
+        Builtin::unary("sec", |x| 1.0 / x.cos())
+            .domain(0, |x| x.cos() != 0.0, "Secant of odd multiple of pi/2")
+            .doc("Secant, 1 / cos(x)"),
+        Builtin::unary("csc", |x| 1.0 / x.sin())
+            .domain(0, |x| x.sin() != 0.0, "Cosecant of multiple of pi")
+            .doc("Cosecant, 1 / sin(x)"),
+        Builtin::unary("cot", |x| x.cos() / x.sin())
+            .domain(0, |x| x.sin() != 0.0, "Cotangent of multiple of pi")
+            .doc("Cotangent, cos(x) / sin(x)"),
+        Builtin::unary("asin", f64::asin).doc("Inverse sine in radians"),
+        Builtin::unary("acos", f64::acos).doc("Inverse cosine in radians"),
+        Builtin::unary("atan", f64::atan).doc("Inverse tangent in radians"),
+        Builtin::binary("atan2", f64::atan2).doc("Angle of the point (x, y), given as atan2(y, x)"),
+        Builtin::unary("sinh", f64::sinh).doc("Hyperbolic sine"),
+        Builtin::unary("cosh", f64::cosh).doc("Hyperbolic cosine"),
+        Builtin::unary("tanh", f64::tanh).doc("Hyperbolic tangent"),
+        Builtin::unary("asinh", f64::asinh).doc("Inverse hyperbolic sine"),
+        Builtin::unary("acosh", f64::acosh)
+            .domain(
+                0,
+                |x| x >= 1.0,
+                "Inverse hyperbolic cosine of number below 1",
+            )
+            .doc("Inverse hyperbolic cosine"),
+        Builtin::unary("atanh", f64::atanh)
+            .domain(
+                0,
+                |x| x.abs() < 1.0,
+                "Inverse hyperbolic tangent outside of (-1, 1)",
+            )
+            .doc("Inverse hyperbolic tangent"),
+        Builtin::unary("ceil", f64::ceil).doc("Smallest integer not below x"),
+        Builtin::unary("floor", f64::floor).doc("Largest integer not above x"),
+        Builtin::numeric("round", 1..=2, |arguments| match *arguments {
+            [value] => Ok(value.round()),
+            [value, digits] => Ok(round(value, digits)),
+            _ => unreachable!(),
+        })
+        .domain(
+            1,
+            |digits| digits.fract() == 0.0,
+            "Number of digits must be an integer",
+        )
+        .doc("Rounds to the nearest integer, or to a number of decimal digits"),
+        Builtin::unary("trunc", f64::trunc).doc("Integer part of x"),
+        Builtin::unary("signum", f64::signum).doc("The sign of x, 1 or -1"),
+        Builtin::numeric("clamp", 3..=3, |arguments| {
+            let [value, min, max] = *arguments else {
+                unreachable!();
+            };
+            if min > max {
+                return Err("Lower bound of clamp exceeds upper bound");
+            }
+            Ok(value.clamp(min, max))
+        })
+        .doc("The value limited to the range from min to max, clamp(x, min, max)"),
+    ];
+    for function in functions {
+        registry.register(function);
+    }
+}
+
+/// Rounds to a number of decimal digits, where negative digits round to tens,
+/// hundreds and so on
+fn round(value: f64, digits: f64) -> f64 {
+    let scale = 10f64.powf(digits.abs());
+    if digits < 0.0 && scale.is_finite() {
+        (value / scale).round() * scale
+    } else if digits < 0.0 {
+        // Finite numbers are far closer to zero than to a power of ten this large
+        match value.is_finite() {
+            true => 0f64.copysign(value),
+            false => value,
+        }
+    } else if (value * scale).is_finite() {
+        (value * scale).round() / scale
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_by_alias() {
+        let registry = FunctionRegistry::default();
+        assert_eq!(registry.get("ln").unwrap().name, "log");
+        assert_eq!(
+            registry.call("ln", &[Value::Number(1.0)]),
+            Ok(Value::Number(0.0))
+        );
+        assert_eq!(registry.call("nope", &[]), Err("Unknown function"));
+        assert!(registry.names().contains(&"choose"));
+    }
+
+    #[test]
+    fn test_arity_and_domain_checks() {
+        let registry = FunctionRegistry::default();
+        let sqrt = registry.get("sqrt").unwrap();
+        assert!(sqrt.accepts_arguments(1));
+        assert!(!sqrt.accepts_arguments(2));
+        assert_eq!(sqrt.call(&[]), Err("Wrong number of function arguments"));
+        assert_eq!(
+            sqrt.call(&[Value::Number(-1.0)]),
+            Err("Square root of negative number")
+        );
+        assert_eq!(registry.get("sum").unwrap().max_arguments, None);
+    }
+
+    #[test]
+    fn test_extend_and_restrict() {
+        let mut registry = FunctionRegistry::empty();
+        registry.register(Builtin::unary("double", |x| 2.0 * x).alias("twice"));
+        registry.register(Builtin::new("now", 0..=0, |_| Ok(Value::Number(0.0))).impure());
+        assert_eq!(registry.names(), ["double", "now", "twice"]);
+        assert_eq!(
+            registry.call("twice", &[Value::Number(4.0)]),
+            Ok(Value::Number(8.0))
+        );
+
+        registry.retain(|function| function.pure);
+        assert!(registry.get("now").is_none());
+        registry.remove("twice");
+        assert!(registry.names().is_empty());
+    }
+}
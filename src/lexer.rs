@@ -4,16 +4,31 @@ use itertools::Itertools;
 pub enum Token {
     OpeningParenthesis,
     ClosingParenthesis,
+    OpeningBracket,
+    ClosingBracket,
     Comma,
     NumericLiteral(String),
     Name(String),
     Symbol(char),
+    /// Operators spanning multiple characters, e.g. `<=` or `~=`
+    Operator(String),
+    /// The label before the expression, e.g. `Monthly rent` in `Monthly rent: 1200`
+    Label(String),
 }
 
+#[allow(clippy::result_unit_err)]
 pub fn lex(input: impl Into<String>) -> Result<Vec<Token>, ()> {
     let input: String = input.into();
 
     let mut tokens: Vec<Token> = vec![];
+    let (label, input) = split_label(&input);
+    if let Some(label) = label {
+        if label.is_empty() {
+            return Err(());
+        }
+        tokens.push(Token::Label(label.to_string()));
+    }
+    let input = input.lines().map(strip_comment).join("\n");
 
     let mut iterator = input.chars().multipeek();
     let mut current = iterator.next();
@@ -22,10 +37,14 @@ pub fn lex(input: impl Into<String>) -> Result<Vec<Token>, ()> {
         match char {
             number if number.is_numeric() => {
                 let mut number_buffer = String::from(number);
-                while let Some(char) = iterator.peek() {
+                while let Some(&char) = iterator.peek() {
                     // TODO reconsider supporting 0x000 and similar syntaxes
-                    if char.is_numeric() || *char == '.' {
-                        number_buffer.push(*char);
+                    // `..` after a number is a range, as in `1..10`
+                    if char == '.' && iterator.peek() == Some(&'.') {
+                        break;
+                    }
+                    if char.is_numeric() || char == '.' {
+                        number_buffer.push(char);
                         iterator.next();
                     } else {
                         break;
@@ -36,7 +55,7 @@ pub fn lex(input: impl Into<String>) -> Result<Vec<Token>, ()> {
             name if name.is_alphabetic() => {
                 let mut name_buffer = String::from(name);
                 while let Some(char) = iterator.peek() {
-                    if char.is_alphanumeric() {
+                    if char.is_alphanumeric() || *char == '_' {
                         name_buffer.push(*char);
                         iterator.next();
                     } else {
@@ -48,8 +67,20 @@ pub fn lex(input: impl Into<String>) -> Result<Vec<Token>, ()> {
             whitespace if whitespace.is_whitespace() => {}
             '(' => tokens.push(Token::OpeningParenthesis),
             ')' => tokens.push(Token::ClosingParenthesis),
+            '[' => tokens.push(Token::OpeningBracket),
+            ']' => tokens.push(Token::ClosingBracket),
             ',' => tokens.push(Token::Comma),
-            '+' | '-' | '*' | '/' | '^' => tokens.push(Token::Symbol(char.to_owned())),
+            '.' if iterator.peek() == Some(&'.') => {
+                iterator.next();
+                tokens.push(Token::Operator("..".into()));
+            }
+            '<' | '>' | '=' | '!' | '~' if iterator.peek() == Some(&'=') => {
+                iterator.next();
+                tokens.push(Token::Operator(format!("{}=", char)));
+            }
+            '+' | '-' | '*' | '/' | '^' | '=' | '<' | '>' | '!' => {
+                tokens.push(Token::Symbol(char.to_owned()))
+            }
             _ => return Err(()),
         }
         current = iterator.next();
@@ -58,6 +89,33 @@ pub fn lex(input: impl Into<String>) -> Result<Vec<Token>, ()> {
     Ok(tokens)
 }
 
+/// The line without a trailing `#` or `//` comment
+pub fn strip_comment(line: &str) -> &str {
+    &line[..comment_start(line).unwrap_or(line.len())]
+}
+
+/// Where the comment of a line starts, if any. A `#` followed by a digit, as in
+/// `Item #3`, is part of the text rather than a comment.
+fn comment_start(line: &str) -> Option<usize> {
+    let hash = line
+        .match_indices('#')
+        .map(|(index, _)| index)
+        .find(|&index| !line[index + 1..].starts_with(|char: char| char.is_ascii_digit()));
+    [hash, line.find("//")].into_iter().flatten().min()
+}
+
+/// Splits off the label before the first colon of the first line, e.g.
+/// `Monthly rent: 1200`, trimmed. A colon inside a comment does not start a label.
+pub fn split_label(input: &str) -> (Option<&str>, &str) {
+    let first_line = input.lines().next().unwrap_or_default();
+    match first_line.find(':') {
+        Some(colon) if comment_start(first_line).is_none_or(|start| colon < start) => {
+            (Some(input[..colon].trim()), &input[colon + 1..])
+        }
+        _ => (None, input),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Token::*;
@@ -87,4 +145,87 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn test_comparison_operators() {
+        let result = lex("1 < 2 <= 3 > 4 >= 5 == 6 != 7 ~= 8 = 9");
+        assert_eq!(
+            result,
+            Ok(vec![
+                NumericLiteral("1".into()),
+                Symbol('<'),
+                NumericLiteral("2".into()),
+                Operator("<=".into()),
+                NumericLiteral("3".into()),
+                Symbol('>'),
+                NumericLiteral("4".into()),
+                Operator(">=".into()),
+                NumericLiteral("5".into()),
+                Operator("==".into()),
+                NumericLiteral("6".into()),
+                Operator("!=".into()),
+                NumericLiteral("7".into()),
+                Operator("~=".into()),
+                NumericLiteral("8".into()),
+                Symbol('='),
+                NumericLiteral("9".into()),
+            ])
+        );
+        assert_eq!(lex("1 ~ 2"), Err(()));
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(
+            lex("1..10 1.5..2"),
+            Ok(vec![
+                NumericLiteral("1".into()),
+                Operator("..".into()),
+                NumericLiteral("10".into()),
+                NumericLiteral("1.5".into()),
+                Operator("..".into()),
+                NumericLiteral("2".into()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_comments_and_labels() {
+        assert_eq!(
+            lex("2 * 3 # six\n// nothing\n+ 1 // one"),
+            Ok(vec![
+                NumericLiteral("2".into()),
+                Symbol('*'),
+                NumericLiteral("3".into()),
+                Symbol('+'),
+                NumericLiteral("1".into()),
+            ])
+        );
+        assert_eq!(lex("# only a comment"), Ok(vec![]));
+        assert_eq!(
+            lex("Monthly rent ($): 1200 # before tax"),
+            Ok(vec![
+                Label("Monthly rent ($)".into()),
+                NumericLiteral("1200".into()),
+            ])
+        );
+        assert_eq!(lex("6 / 2 # ratio: halved"), lex("6 / 2"));
+        assert_eq!(lex(": 1"), Err(()));
+        assert_eq!(lex("a: b: 1"), Err(()));
+        assert_eq!(
+            lex("Item #3: 5 # each"),
+            Ok(vec![Label("Item #3".into()), NumericLiteral("5".into())])
+        );
+        assert_eq!(
+            lex("2 +\n3 # five: total"),
+            Ok(vec![
+                NumericLiteral("2".into()),
+                Symbol('+'),
+                NumericLiteral("3".into()),
+            ])
+        );
+        // A colon on a later line is not a label
+        assert_eq!(lex("2 +\nrent: 3"), Err(()));
+        assert_eq!(split_label("2 +\nrent: 3"), (None, "2 +\nrent: 3"));
+    }
 }
@@ -1,6 +1,28 @@
+pub mod aggregates;
+pub mod batch;
+pub mod calculus;
+pub mod compile;
+pub mod context;
+pub mod csv;
+pub mod definitions;
+pub mod derivative;
+pub mod document;
 pub mod evaluator;
+pub mod functions;
 pub mod lexer;
+pub mod limits;
+pub mod markdown;
+pub mod matrix;
+pub mod number_theory;
 pub mod parser;
+pub mod render;
+pub mod simplify;
+pub mod solve;
+pub mod special;
+#[cfg(test)]
+mod strategies;
+pub mod units;
+pub mod value;
 
 #[cfg(test)]
 mod tests {
@@ -16,7 +38,7 @@ mod tests {
             ("2 ^ 3 ^ 2", 512.0),
             ("8 / 2 / 2", 2.0),
             ("-5 + 3 * 2", 1.0),
-            // ("2 * -3", -6.0),
+            ("2 * -3", -6.0),
             ("2 * (23 - 2.5 *2) ^2 /2 *3 + log(231)", 977.4424177105218),
             ("abs(-3) + 4 * 2", 11.0),
         ];
@@ -35,4 +57,303 @@ mod tests {
             );
         }
     }
+    #[test]
+    fn test_elementary_functions() {
+        let test_cases = [
+            ("sinh(1)", 1.1752011936438014),
+            ("cosh(1)", 1.5430806348152437),
+            ("tanh(1)", 0.7615941559557649),
+            ("asinh(sinh(2))", 2.0),
+            ("acosh(cosh(2))", 2.0),
+            ("atanh(tanh(0.5))", 0.5),
+            ("atan2(1, -1)", 2.356194490192345),
+            ("sec(0)", 1.0),
+            ("csc(1)", 1.1883951057781212),
+            ("cot(1)", 0.6420926159343306),
+            ("log2(1024)", 10.0),
+            ("ln(exp(3))", 3.0),
+            ("expm1(0.0000000001)", 1.00000000005e-10),
+            ("log1p(0.0000000001)", 9.9999999995e-11),
+            ("hypot(3, 4)", 5.0),
+            ("min(3, 1, 2) + max(3, 1, 2)", 4.0),
+            ("clamp(12, 0, 10)", 10.0),
+            ("clamp(-1, 0, 10)", 0.0),
+            ("round(1.23456, 3)", 1.235),
+            ("round(1234.5, -2)", 1200.0),
+            ("round(5, -400)", 0.0),
+            ("round(-10^300, -400)", 0.0),
+        ];
+
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            let result = evaluator::evaluate(&parsed).expect("Evaluation failed");
+            assert!(
+                (result - expected).abs() <= 1e-12 * expected.abs().max(1e-10),
+                "{} => {} (expected {})",
+                expression,
+                result,
+                expected
+            );
+        }
+
+        let errors = [
+            ("acosh(0.5)", "Inverse hyperbolic cosine of number below 1"),
+            ("atanh(1)", "Inverse hyperbolic tangent outside of (-1, 1)"),
+            ("csc(0)", "Cosecant of multiple of pi"),
+            ("cot(0)", "Cotangent of multiple of pi"),
+            ("log2(0)", "Logarithm of non-positive number"),
+            ("log1p(-1)", "Logarithm of non-positive number"),
+            ("clamp(1, 2, 0)", "Lower bound of clamp exceeds upper bound"),
+            ("round(1, 0.5)", "Number of digits must be an integer"),
+        ];
+        for (expression, message) in errors {
+            let tokens = lexer::lex(expression).expect("Lexing failed");
+            let parsed = parser::parse(&tokens).expect("Parse failed");
+            assert_eq!(evaluator::evaluate(&parsed), Err(message), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_unit_expressions() {
+        let test_cases = [
+            ("1.5 TB in GiB", "1396.9838619232178 GiB"),
+            ("500 Mbit/s * 2 h in GB", "450 GB"),
+            ("4 KiB * 1024 in MiB", "4 MiB"),
+            ("4 KiB * 1024", "4096 KiB"),
+            ("1 KB in B", "1000 B"),
+            ("100 Mbps * 1 min in MB", "750 MB"),
+            ("1 GiB / 1 MiB", "1024"),
+            ("2 h + 30 min in min", "150 min"),
+            ("3 km / 2 h", "1.5 km/h"),
+        ];
+
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            let result = evaluator::evaluate_value(&parsed).expect("Evaluation failed");
+            assert_eq!(result.to_string(), *expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_incompatible_units() {
+        for expression in ["1 GB + 1 s", "1 GB in s", "1 GB in 5", "2 ^ (1 s)"] {
+            let tokens = lexer::lex(expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            assert!(
+                evaluator::evaluate_value(&parsed).is_err(),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        let test_cases = [
+            ("2^10 > 1000", true),
+            ("2^10 < 1000", false),
+            ("3 <= 3 and 3 >= 3", true),
+            ("sqrt(2)^2 == 2", false),
+            ("sqrt(2)^2 ~= 2", true),
+            ("sqrt(2)^2 != 2", true),
+            ("1 km == 1000 m", true),
+            ("1 GiB > 1 GB", true),
+            ("not 1 > 2", true),
+            ("true or false and false", true),
+            ("not (true or false)", false),
+            ("1 < 2 == true", true),
+            ("2 * -3 < -5", true),
+        ];
+
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            let result = evaluator::evaluate_value(&parsed).expect("Evaluation failed");
+            assert_eq!(result, value::Value::Bool(*expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_invalid_boolean_expressions() {
+        for expression in [
+            "true + 1",
+            "1 and true",
+            "1 m < 1 s",
+            "true < false",
+            "-true",
+        ] {
+            let tokens = lexer::lex(expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            assert!(
+                evaluator::evaluate_value(&parsed).is_err(),
+                "{}",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn test_list_expressions() {
+        let test_cases = [
+            ("[1, 2, 3]", "[1, 2, 3]"),
+            ("[1, 2, 3] * 2", "[2, 4, 6]"),
+            ("10 - [1, 2]", "[9, 8]"),
+            ("[1, 2] + [10, 20]", "[11, 22]"),
+            ("-[1, 2] ^ 2", "[-1, -4]"),
+            ("[1 GB, 2 GB] in MB", "[1000 MB, 2000 MB]"),
+            ("sum([1, 2, 3])", "6"),
+            ("sum(1, 2, 3)", "6"),
+            ("mean([1, 2, 3, 4])", "2.5"),
+            ("median(3, 1, 2)", "2"),
+            ("max([1, 5], 3)", "5"),
+            ("min(2 h, 90 min)", "90 min"),
+            ("count([])", "0"),
+            ("sum([1 GB, 500 MB]) in MB", "1500 MB"),
+            ("[1, 2] == [1, 2]", "true"),
+            ("det([[1, 2], [3, 4]])", "-2"),
+            ("matmul([[1, 2], [3, 4]], [1, 1])", "[3, 7]"),
+            ("transpose([[1, 2, 3]])", "[[1], [2], [3]]"),
+            ("solve([[2, 0], [0, 4]], [2, 2])", "[1, 0.5]"),
+            ("[[1, 2], [3, 4]] * 2", "[[2, 4], [6, 8]]"),
+        ];
+
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            let result = evaluator::evaluate_value(&parsed).expect("Evaluation failed");
+            assert_eq!(result.to_string(), *expected, "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_invalid_list_expressions() {
+        for expression in [
+            "[1, 2] + [1, 2, 3]",
+            "[1, 2] < 3",
+            "sqrt([4])",
+            "[1, 2) + 3",
+        ] {
+            let result = lexer::lex(expression)
+                .map_err(|_| "Lexing failed")
+                .and_then(|tokens| parser::parse(&tokens))
+                .and_then(|parsed| evaluator::evaluate_value(&parsed));
+            assert!(result.is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_summation_and_ranges() {
+        let test_cases = [
+            ("1..5", "[1, 2, 3, 4, 5]"),
+            ("1..2 + 1", "[1, 2, 3]"),
+            ("5..1", "[]"),
+            ("sum(1..10)", "55"),
+            ("prod(1..5)", "120"),
+            ("mean(1..4)", "2.5"),
+            ("sum(k, 1, 100, k^2)", "338350"),
+            ("prod(k, 1, 10, k)", "3628800"),
+            ("sum(i, 1, 3, sum(j, 1, i, j))", "10"),
+            ("sum(k, 1, 0, k)", "0"),
+            ("sum(k, 1, 3, 2)", "6"),
+            ("sum(1..0)", "0"),
+            ("sum([])", "0"),
+            ("prod([])", "1"),
+            ("sum(n, 1, 3, n h) in min", "360 min"),
+        ];
+
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(tokens.as_slice()).expect("Parse failed");
+            let result = evaluator::evaluate_value(&parsed).expect("Evaluation failed");
+            assert_eq!(result.to_string(), *expected, "{}", expression);
+        }
+
+        // The bound variable is not visible outside of the summation
+        let tokens = lexer::lex("sum(k, 1, 3, k) + k").unwrap();
+        let parsed = parser::parse(&tokens).unwrap();
+        assert!(evaluator::evaluate_value(&parsed).is_err());
+    }
+
+    #[test]
+    fn test_factorials_and_gamma() {
+        let test_cases = [
+            ("5!", "120"),
+            ("0!", "1"),
+            ("25!", "15511210043330986000000000"),
+            ("factorial(20)", "2432902008176640000"),
+            ("7!!", "105"),
+            ("(3!)!", "720"),
+            ("2 3!", "12"),
+            ("3! - 1", "5"),
+            ("-3!", "-6"),
+            ("2^3!", "64"),
+            ("3!^2", "36"),
+            ("n = 3", "3"),
+            ("(2n)!", "720"),
+            ("gamma(5)", "24"),
+            ("gamma(0.5)^2 ~= 3.141592653589793", "true"),
+            ("lgamma(1000) ~= 5905.220423209181", "true"),
+        ];
+
+        let mut context = context::Context::new();
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(&tokens).expect("Parse failed");
+            let result = context.evaluate(&parsed).expect("Evaluation failed");
+            assert_eq!(result.unwrap().to_string(), *expected, "{}", expression);
+        }
+
+        for expression in ["171!", "(-1)!", "1.5!", "gamma(0)", "gamma(-2)", "1000!!"] {
+            let tokens = lexer::lex(expression).expect("Lexing failed");
+            let parsed = parser::parse(&tokens).expect("Parse failed");
+            assert!(evaluator::evaluate(&parsed).is_err(), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn test_number_theory_functions() {
+        let test_cases = [
+            ("choose(5, 2)", "10"),
+            ("nCr(50, 25)", "126410606437752"),
+            ("nPr(5, 2)", "20"),
+            ("gcd(12, 18, 27)", "3"),
+            ("lcm(4, 6, 10)", "60"),
+            ("mod(-7, 3)", "2"),
+            ("mod(7, -3)", "-2"),
+            ("isprime(97)", "true"),
+            ("isprime(2^31 - 1)", "true"),
+            ("nextprime(100)", "101"),
+            ("factor(360)", "[2, 2, 2, 3, 3, 5]"),
+            ("totient(36)", "12"),
+            ("powmod(4, 13, 497)", "445"),
+            ("powmod(3, 10^15, 10^9 + 7) < 10^9 + 7", "true"),
+        ];
+
+        for (expression, expected) in test_cases.iter() {
+            let tokens = lexer::lex(*expression).expect("Lexing failed");
+            let parsed = parser::parse(&tokens).expect("Parse failed");
+            let result = evaluator::evaluate_value(&parsed).expect("Evaluation failed");
+            assert_eq!(result.to_string(), *expected, "{}", expression);
+        }
+
+        for expression in [
+            "gcd(1.5, 3)",
+            "mod(1, 0)",
+            "factor(0)",
+            "choose(2^60, 3)",
+            "nCr(100, 50)",
+            "lcm(321, 28059810762433)",
+            "isprime(1 m)",
+        ] {
+            let tokens = lexer::lex(expression).expect("Lexing failed");
+            let parsed = parser::parse(&tokens).expect("Parse failed");
+            assert!(
+                evaluator::evaluate_value(&parsed).is_err(),
+                "{}",
+                expression
+            );
+        }
+    }
 }
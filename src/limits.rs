@@ -0,0 +1,256 @@
+//! Limits on the resources an evaluation may use, for evaluating untrusted input
+//!
+//! ```
+//! use culator::context::Context;
+//! use culator::limits::Limits;
+//!
+//! let mut context = Context::new();
+//! context.set_limits(Limits {
+//!     max_steps: 10_000,
+//!     ..Limits::default()
+//! });
+//! assert_eq!(
+//!     context.evaluate_str("sum(k, 1, 1000000, k)"),
+//!     Err("Evaluation took too many steps")
+//! );
+//! ```
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::functions::Builtin;
+use crate::value::Value;
+
+pub const INPUT_TOO_LONG: &str = "Input is too long";
+pub const TOO_DEEP: &str = "Expression is nested too deeply";
+pub const TOO_MANY_STEPS: &str = "Evaluation took too many steps";
+pub const INTEGER_TOO_LARGE: &str = "Integer argument is too large";
+pub const LIST_TOO_LONG: &str = "List is too long";
+
+/// Whether an error is an evaluation running out of its budget, which numeric methods
+/// pass on rather than take for a point where a function is undefined
+pub(crate) fn is_exhausted(error: &str) -> bool {
+    error == TOO_DEEP || error == TOO_MANY_STEPS
+}
+
+/// The default limits allow any reasonable input, while keeping evaluations within a
+/// second or two and the stack of a debug build's 2 MiB thread from overflowing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limits {
+    /// Characters in an input
+    pub max_input_length: usize,
+    /// Nesting of parentheses, operators and function calls when parsing and evaluating,
+    /// where a chain of `+`, `-`, `*` and `/` like `1 + 2 + 3` nests once however long
+    /// it is, and the height of expressions to derive, simplify or render, which recurse
+    /// once per operator
+    pub max_depth: usize,
+    /// Subexpressions evaluated for a single input, bounding the time taken
+    pub max_steps: u64,
+    /// Magnitude of integer arguments, e.g. to `factor` or `isprime`
+    pub max_integer: f64,
+    /// Elements of a list made by a range, e.g. `1..n`
+    pub max_list_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_input_length: 100_000,
+            max_depth: 100,
+            max_steps: 10_000_000,
+            max_integer: 2f64.powi(53),
+            max_list_length: 1_000_000,
+        }
+    }
+}
+
+impl Limits {
+    /// Checks the integer arguments of a call to `function` against `max_integer`
+    pub(crate) fn check_arguments(
+        &self,
+        function: &Builtin,
+        arguments: &[Value],
+    ) -> Result<(), &'static str> {
+        let too_large = |argument: &Value| matches!(argument, Value::Number(number) if number.abs() > self.max_integer);
+        if function.integer_arguments && arguments.iter().any(too_large) {
+            return Err(INTEGER_TOO_LARGE);
+        }
+        Ok(())
+    }
+}
+
+/// The depth and steps of an evaluation in progress, shared by the scopes of a context
+#[derive(Debug, Default)]
+pub(crate) struct Budget {
+    depth: AtomicUsize,
+    steps: AtomicU64,
+}
+
+/// Leaves a level of nesting when dropped
+pub(crate) struct Nesting<'a>(&'a Budget);
+
+impl Drop for Nesting<'_> {
+    fn drop(&mut self) {
+        self.0.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Budget {
+    /// Counts a step one level deeper, starting the count afresh at the top level
+    pub(crate) fn enter(&self, limits: &Limits) -> Result<Nesting<'_>, &'static str> {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed);
+        let nesting = Nesting(self);
+        if depth == 0 {
+            self.steps.store(0, Ordering::Relaxed);
+        }
+        if depth >= limits.max_depth {
+            return Err(TOO_DEEP);
+        }
+        if self.steps.fetch_add(1, Ordering::Relaxed) >= limits.max_steps {
+            return Err(TOO_MANY_STEPS);
+        }
+        Ok(nesting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_in;
+    use crate::context::Context;
+    use crate::derivative::derive;
+    use crate::lexer;
+    use crate::parser::{self, Expression};
+    use crate::simplify::simplify;
+
+    fn limited(limits: Limits) -> Context {
+        let mut context = Context::new();
+        context.set_limits(limits);
+        context
+    }
+
+    #[test]
+    fn test_nesting() {
+        let mut context = Context::new();
+        let nested = "-(".repeat(1000) + "1" + &")".repeat(1000);
+        assert_eq!(context.evaluate_str(&nested), Err(TOO_DEEP));
+        let parenthesized = "(".repeat(1000) + "1" + &")".repeat(1000);
+        assert_eq!(context.evaluate_str(&parenthesized), Err(TOO_DEEP));
+
+        // Chains of operators nest no deeper however long they are
+        let chain = vec!["1"; 1000].join(" + ");
+        assert_eq!(
+            context.evaluate_str(&chain),
+            Ok(Some(Value::Number(1000.0)))
+        );
+        let chain = vec!["x * 2 - x"; 1000].join(" + ");
+        let expression = parser::parse(&lexer::lex(&chain).unwrap()).unwrap();
+        let compiled = compile_in(&expression, &["x"], &context).unwrap();
+        assert_eq!(compiled.evaluate(&[3.0]), Ok(3000.0));
+        let derivative = format!("derive({}, x, 1)", vec!["x"; 1000].join(" + "));
+        assert_eq!(context.evaluate_str(&derivative), Err(TOO_DEEP));
+
+        // Unbounded recursion fails, leaving the context usable
+        context.evaluate_str("f(n) = f(n + 1)").unwrap();
+        assert_eq!(context.evaluate_str("f(1)"), Err(TOO_DEEP));
+        assert_eq!(context.evaluate_str("1 + 1"), Ok(Some(Value::Number(2.0))));
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let mut context = limited(Limits {
+            max_input_length: 20,
+            max_steps: 100,
+            max_integer: 1000.0,
+            max_list_length: 1000,
+            ..Limits::default()
+        });
+        assert_eq!(
+            context.evaluate_str("1 + 1 + 1 + 1 + 1 + 1"),
+            Err(INPUT_TOO_LONG)
+        );
+        assert_eq!(
+            context.evaluate_str("sum(k, 1, 50, k)"),
+            Ok(Some(Value::Number(1275.0)))
+        );
+        assert_eq!(
+            context.evaluate_str("sum(k, 1, 500, k)"),
+            Err(TOO_MANY_STEPS)
+        );
+        // Numeric methods stop rather than skip the points left without steps
+        assert_eq!(
+            context.evaluate_str("solve(x^2 = 2, x)"),
+            Err(TOO_MANY_STEPS)
+        );
+        assert_eq!(context.evaluate_str("count(1..5000)"), Err(LIST_TOO_LONG));
+        assert_eq!(context.evaluate_str("factor(1001)"), Err(INTEGER_TOO_LARGE));
+        assert_eq!(
+            context.evaluate_str("isprime(997)"),
+            Ok(Some(Value::Bool(true)))
+        );
+
+        // Compiled expressions check the limits they were compiled with
+        let formula =
+            Expression::FunctionCall("totient".into(), vec![Expression::Name("n".into())]);
+        let compiled = compile_in(&formula, &["n"], &context).unwrap();
+        assert_eq!(compiled.evaluate(&[10.0]), Ok(4.0));
+        assert_eq!(compiled.evaluate(&[1e6]), Err(INTEGER_TOO_LARGE));
+        let totients = compiled.evaluate_batch(&[&[10.0, 1e6]]).unwrap();
+        assert_eq!(totients[0], 4.0);
+        assert!(totients[1].is_nan());
+
+        // Bound iterations do not build their range as a list
+        let mut context = limited(Limits {
+            max_list_length: 10,
+            ..Limits::default()
+        });
+        assert_eq!(
+            context.evaluate_str("sum(k, 1, 100, k)"),
+            Ok(Some(Value::Number(5050.0)))
+        );
+    }
+
+    #[test]
+    fn test_hostile_input() {
+        let mut context = Context::new();
+        let huge_literal = "9".repeat(400);
+        let cases = [
+            ("1.2.3", Err("Invalid number")),
+            ("()", Err("Empty input")),
+            ("(10^300)!!", Err("Double factorial is too large")),
+            ("identity(10^9)", Err("Matrix is too large")),
+            ("1..10^300", Err(LIST_TOO_LONG)),
+            ("factor(2^60)", Err(INTEGER_TOO_LARGE)),
+            (huge_literal.as_str(), Err("Invalid number")),
+            ("m^127 * m", Err("Exponent too large")),
+            ("(1 m)^100 * (1 m)^100", Err("Exponent too large")),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(context.evaluate_str(input), expected, "{}", input);
+        }
+
+        // Chains parse however long they are, but symbolic methods recurse once per
+        // operator, so they fail on chains taller than the limit rather than overflow
+        let chain = vec!["x"; 20_000].join(" + ");
+        let expression = context.parse(&chain).unwrap();
+        assert!(expression == expression.clone());
+        assert_eq!(expression.to_latex(), Err(TOO_DEEP));
+        assert_eq!(expression.to_mathml(), Err(TOO_DEEP));
+        assert_eq!(simplify(&expression), Err(TOO_DEEP));
+        assert_eq!(derive(&expression, "x"), Err(TOO_DEEP));
+        let derivative = format!("derive({}, x)", chain);
+        assert_eq!(context.evaluate_str(&derivative), Err(TOO_DEEP));
+
+        // Collecting the factors of a balanced product makes a chain of all of them
+        let mut product = "x".to_string();
+        for level in 0..10 {
+            product = format!(
+                "({}) * ({})",
+                product,
+                product.replace('x', &format!("x{}", level))
+            );
+        }
+        let expression = context.parse(&product).unwrap();
+        assert!(expression.height() < Limits::default().max_depth);
+        assert_eq!(simplify(&expression), Err(TOO_DEEP));
+    }
+}
@@ -0,0 +1,375 @@
+//! Command line interface, evaluating a single expression, a formula over the rows of
+//! a CSV file or the calculations in Markdown files
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process::ExitCode;
+
+use culator::context::Context;
+use culator::csv::{self, Reader};
+use culator::markdown;
+use culator::parser::Expression;
+
+const USAGE: &str = "\
+Usage: culator [--definitions FILE] EXPRESSION
+       culator [--definitions FILE] --csv FILE --expr FORMULA
+       culator markdown [--definitions FILE] [--check] FILE...
+
+Evaluates EXPRESSION and prints its value, e.g. culator '1.5 TB in GiB'.
+
+With --csv, evaluates FORMULA for every row of FILE, or of standard input if FILE is
+-, with the column headers as variables, and prints the rows with the result in an
+extra column. Spaces in headers become underscores, so a column `unit price` is
+`unit_price`. The result column is named `result` unless FORMULA has a label or is
+an assignment, as in `Total, with tax: price * qty * (1 + tax)` or
+`total = price * qty * (1 + tax)`. Rows that cannot be evaluated are left empty.
+
+With markdown, evaluates the lines of ```culator code blocks and inline code such
+as `= 2 + 3 =` in each FILE, and updates the results in place. The results of earlier
+runs are replaced. Standard input is written to standard output if FILE is -.
+
+Options:
+  --definitions FILE  Loads unit definitions from FILE
+  --csv FILE          Reads rows from the CSV file FILE
+  --expr FORMULA      The formula to evaluate for each row
+  --check             Fails instead of updating Markdown files with outdated results
+  -h, --help          Prints this help";
+
+/// Rows read before evaluating the formula over them
+const CHUNK_SIZE: usize = 1024;
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    definitions: Option<String>,
+    csv: Option<String>,
+    expression: Option<String>,
+    /// Whether to evaluate Markdown files, given in `files`
+    markdown: bool,
+    files: Vec<String>,
+    check: bool,
+    help: bool,
+}
+
+fn parse_arguments(arguments: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut arguments = arguments.into_iter().peekable();
+    if arguments
+        .next_if(|argument| argument == "markdown")
+        .is_some()
+    {
+        options.markdown = true;
+    }
+    while let Some(argument) = arguments.next() {
+        let mut value = |option: &str| {
+            arguments
+                .next()
+                .ok_or_else(|| format!("Missing value for {}", option))
+        };
+        match argument.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--definitions" => options.definitions = Some(value("--definitions")?),
+            "--csv" => options.csv = Some(value("--csv")?),
+            "--expr" => options.expression = Some(value("--expr")?),
+            "--check" if options.markdown => options.check = true,
+            option if option.starts_with("--") => {
+                return Err(format!("Unknown option {}", option));
+            }
+            _ if options.markdown => options.files.push(argument),
+            _ if options.expression.is_some() => {
+                return Err("Expected a single expression".to_string());
+            }
+            _ => options.expression = Some(argument),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("culator: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), String> {
+    let options = parse_arguments(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let mut context = Context::new();
+    if let Some(path) = &options.definitions {
+        let source = read(path)?;
+        context
+            .load_definitions(&source)
+            .map_err(|error| format!("{}: {}", path, error))?;
+    }
+    if options.markdown {
+        return evaluate_markdown_files(&options, &context);
+    }
+
+    let Some(input) = &options.expression else {
+        return Err(format!("Missing expression\n\n{}", USAGE));
+    };
+
+    match &options.csv {
+        Some(path) => {
+            let expression = context.parse(input)?;
+            let input: Box<dyn BufRead> = if path == "-" {
+                Box::new(io::stdin().lock())
+            } else {
+                let file = File::open(path).map_err(|error| format!("{}: {}", path, error))?;
+                Box::new(BufReader::new(file))
+            };
+            let output = BufWriter::new(io::stdout().lock());
+            evaluate_csv(input, output, &expression, &context)
+        }
+        None => {
+            if let Some(value) = context.evaluate_str(input)? {
+                println!("{}", value);
+            }
+            Ok(())
+        }
+    }?;
+
+    for warning in context.take_warnings() {
+        eprintln!("culator: warning: {}", warning);
+    }
+    Ok(())
+}
+
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))
+}
+
+/// Updates the results in each Markdown file, evaluated in its own copy of `context`
+fn evaluate_markdown_files(options: &Options, context: &Context) -> Result<(), String> {
+    if options.files.is_empty() {
+        return Err(format!("Missing Markdown file\n\n{}", USAGE));
+    }
+    let mut outdated = vec![];
+    for path in &options.files {
+        let source = if path == "-" {
+            io::read_to_string(io::stdin()).map_err(|error| error.to_string())?
+        } else {
+            read(path)?
+        };
+        let context = &mut context.clone();
+        let evaluated = markdown::evaluate_markdown_in(&source, context);
+        for warning in context.take_warnings() {
+            eprintln!("culator: warning: {}: {}", path, warning);
+        }
+        if path == "-" {
+            print!("{}", evaluated);
+        } else if evaluated != source {
+            if options.check {
+                outdated.push(path.as_str());
+            } else {
+                std::fs::write(path, evaluated).map_err(|error| format!("{}: {}", path, error))?;
+            }
+        }
+    }
+    match outdated.as_slice() {
+        [] => Ok(()),
+        paths => Err(format!("Outdated results in {}", paths.join(", "))),
+    }
+}
+
+/// Appends a column with the formula evaluated for each row, a chunk of rows at a time
+fn evaluate_csv(
+    input: impl BufRead,
+    mut output: impl Write,
+    formula: &Expression,
+    context: &Context,
+) -> Result<(), String> {
+    let (label, formula) = match formula {
+        Expression::Labeled(label, formula) => (Some(label.as_str()), &**formula),
+        formula => (None, formula),
+    };
+    let (column, formula) = match formula {
+        Expression::Assignment(name, formula) => (label.unwrap_or(name), &**formula),
+        Expression::UnitDefinition(..) | Expression::FunctionDefinition(..) => {
+            return Err("Expected a formula".to_string());
+        }
+        formula => (label.unwrap_or("result"), formula),
+    };
+
+    let mut records = Reader::new(input);
+    let Some(header) = records
+        .next()
+        .transpose()
+        .map_err(|error| error.to_string())?
+    else {
+        return Ok(());
+    };
+    let names: Vec<String> = header
+        .fields
+        .iter()
+        .map(|name| name.trim().replace(' ', "_"))
+        .collect();
+    // Only the columns the formula refers to are parsed as numbers
+    let referenced = formula.referenced_names();
+    let parameters: Vec<(usize, &str)> = names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| referenced.contains(&name.as_str()))
+        .map(|(index, name)| (index, name.as_str()))
+        .collect();
+    let compiled = culator::compile::compile_in(
+        formula,
+        &parameters.iter().map(|(_, name)| *name).collect::<Vec<_>>(),
+        context,
+    )?;
+    let write_error = |error: io::Error| error.to_string();
+    writeln!(output, "{},{}", header.raw, csv::escape(column)).map_err(write_error)?;
+
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+    let mut records = records.peekable();
+    while records.peek().is_some() {
+        chunk.clear();
+        for record in records.by_ref().take(CHUNK_SIZE) {
+            chunk.push(record.map_err(|error| error.to_string())?);
+        }
+        let columns: Vec<Vec<f64>> = parameters
+            .iter()
+            .map(|(index, _)| {
+                chunk
+                    .iter()
+                    .map(|record| match record.fields.get(*index) {
+                        Some(cell) => cell.trim().parse().unwrap_or(f64::NAN),
+                        None => f64::NAN,
+                    })
+                    .collect()
+            })
+            .collect();
+        let columns: Vec<&[f64]> = columns.iter().map(Vec::as_slice).collect();
+        let results = compiled.evaluate_batch(&columns)?;
+        for (record, result) in chunk.iter().zip(results) {
+            if result.is_nan() {
+                writeln!(output, "{},", record.raw)
+            } else {
+                writeln!(output, "{},{}", record.raw, result)
+            }
+            .map_err(write_error)?;
+        }
+    }
+    output.flush().map_err(write_error)?;
+    for warning in compiled.take_warnings() {
+        context.warn(warning);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate_csv(input: &str, formula: &str) -> Result<String, String> {
+        let mut output = vec![];
+        let context = Context::new();
+        super::evaluate_csv(
+            input.as_bytes(),
+            &mut output,
+            &context.parse(formula)?,
+            &context,
+        )?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_parse_arguments() {
+        let arguments = |arguments: &[&str]| {
+            parse_arguments(arguments.iter().map(|argument| argument.to_string()))
+        };
+        assert_eq!(
+            arguments(&["--csv", "data.csv", "--expr", "price * qty"]),
+            Ok(Options {
+                csv: Some("data.csv".to_string()),
+                expression: Some("price * qty".to_string()),
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            arguments(&["2 + 3"]).unwrap().expression,
+            Some("2 + 3".to_string())
+        );
+        assert_eq!(
+            arguments(&["--csv"]),
+            Err("Missing value for --csv".to_string())
+        );
+        assert_eq!(
+            arguments(&["--sheet", "data.csv"]),
+            Err("Unknown option --sheet".to_string())
+        );
+        assert_eq!(
+            arguments(&["1", "2"]),
+            Err("Expected a single expression".to_string())
+        );
+        assert_eq!(
+            arguments(&["markdown", "--check", "README.md", "-"]),
+            Ok(Options {
+                markdown: true,
+                files: vec!["README.md".to_string(), "-".to_string()],
+                check: true,
+                ..Options::default()
+            })
+        );
+        assert_eq!(
+            arguments(&["--check", "1"]),
+            Err("Unknown option --check".to_string())
+        );
+        assert_eq!(
+            arguments(&["1", "markdown"]),
+            Err("Expected a single expression".to_string())
+        );
+    }
+
+    #[test]
+    fn test_evaluate_csv() {
+        let input = "item,price,qty,tax\n\
+                     \"Widget, large\",2.5,4,0.2\r\n\
+                     Gadget,10,,0.2\n\
+                     Gizmo,1,3\n";
+        assert_eq!(
+            evaluate_csv(input, "price * qty * (1 + tax)").unwrap(),
+            "item,price,qty,tax,result\n\
+             \"Widget, large\",2.5,4,0.2,12\n\
+             Gadget,10,,0.2,\n\
+             Gizmo,1,3,\n"
+        );
+        assert_eq!(
+            evaluate_csv("unit price,qty\n2,3\n", "total = unit_price * qty / 0").unwrap(),
+            "unit price,qty,total\n2,3,\n"
+        );
+        assert_eq!(
+            evaluate_csv("qty\n2\n", "Price, in cents: qty * 150 # list price").unwrap(),
+            "qty,\"Price, in cents\"\n2,300\n"
+        );
+        // More rows than a chunk
+        let input: String = std::iter::once("x\n".to_string())
+            .chain((0..3000).map(|row| format!("{}\n", row)))
+            .collect();
+        let output = evaluate_csv(&input, "x * 1000").unwrap();
+        assert_eq!(output.lines().count(), 3001);
+        assert_eq!(output.lines().last(), Some("2999,2999000"));
+
+        assert_eq!(evaluate_csv("", "x").unwrap(), "");
+        assert_eq!(
+            evaluate_csv("x\n1\n", "x + y"),
+            Err("Unknown name".to_string())
+        );
+        assert_eq!(
+            evaluate_csv("x\n1\n", "f(x) = x"),
+            Err("Expected a formula".to_string())
+        );
+        let nested = "-(".repeat(1000) + "x" + &")".repeat(1000);
+        assert_eq!(
+            evaluate_csv("x\n1\n", &nested),
+            Err("Expression is nested too deeply".to_string())
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! Evaluation of calculations embedded in Markdown files, such as runbooks
+//!
+//! Fenced code blocks marked `culator` are evaluated line by line as a
+//! [notepad document](crate::document), with each result written after its line as a
+//! `# => result` comment. Inline code of the form `= expression =` gets the result
+//! written inside it, as in `= 2 + 3 = 5`. All calculations in a file share one
+//! context, and results from earlier runs are replaced, so evaluating the output
+//! again leaves it unchanged.
+//!
+//! ````
+//! use culator::markdown::evaluate_markdown;
+//!
+//! let source = "```culator\nrate = 100 Mbit/s\n2 GB / rate in s\n```\nAbout `= 2 GB / rate in min =`.\n";
+//! let evaluated = evaluate_markdown(source);
+//! assert_eq!(
+//!     evaluated,
+//!     "```culator\nrate = 100 Mbit/s # => 100 Mbit/s\n2 GB / rate in s  # => 160 s\n```\nAbout `= 2 GB / rate in min = 2.6666666666666665 min`.\n"
+//! );
+//! assert_eq!(evaluate_markdown(&evaluated), evaluated);
+//! ````
+
+use crate::context::Context;
+use crate::document::{self, Line};
+
+/// Starts the comment holding the result of a line in a code block
+pub const RESULT_MARKER: &str = "# =>";
+
+/// The info string of the code blocks to evaluate
+const LANGUAGE: &str = "culator";
+
+/// Evaluates the calculations in `source` in a fresh context, returning the Markdown
+/// with their results
+pub fn evaluate_markdown(source: &str) -> String {
+    evaluate_markdown_in(source, &mut Context::new())
+}
+
+/// Evaluates the calculations in `source`, keeping variables and definitions in
+/// `context`
+pub fn evaluate_markdown_in(source: &str, context: &mut Context) -> String {
+    let mut output = String::with_capacity(source.len());
+    // The fence of the code block the line is in, and the lines of a `culator` block
+    let mut fence: Option<&str> = None;
+    let mut block: Option<Vec<(&str, &str)>> = None;
+    for line in source.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let ending = &line[content.len()..];
+        match fence {
+            None => match opening_fence(content) {
+                Some((opening, language)) => {
+                    fence = Some(opening);
+                    if language == LANGUAGE {
+                        block = Some(vec![]);
+                    }
+                    output.push_str(line);
+                }
+                None => {
+                    output.push_str(&evaluate_inline(content, context));
+                    output.push_str(ending);
+                }
+            },
+            Some(opening) if is_closing_fence(content, opening) => {
+                if let Some(lines) = block.take() {
+                    write_block(&mut output, &lines, context);
+                }
+                fence = None;
+                output.push_str(line);
+            }
+            Some(_) => match &mut block {
+                Some(lines) => lines.push((content, ending)),
+                None => output.push_str(line),
+            },
+        }
+    }
+    // A block left open runs to the end of the file
+    if let Some(lines) = block {
+        write_block(&mut output, &lines, context);
+    }
+    output
+}
+
+/// Writes the lines of a block with their results, aligned after the longest line
+/// with one
+fn write_block(output: &mut String, lines: &[(&str, &str)], context: &mut Context) {
+    let code: Vec<&str> = lines.iter().map(|(line, _)| strip_result(line)).collect();
+    let evaluated = document::evaluate_document_in(&code.join("\n"), context);
+    let mut results: Vec<Option<String>> = evaluated.iter().map(format_result).collect();
+    // Trailing blank lines have no results of their own
+    results.resize(code.len(), None);
+    let width = code
+        .iter()
+        .zip(&results)
+        .filter(|(_, result)| result.is_some())
+        .map(|(code, _)| code.chars().count())
+        .max()
+        .unwrap_or(0);
+    for ((code, result), (line, ending)) in code.iter().zip(results).zip(lines) {
+        match result {
+            Some(result) => {
+                output.push_str(&format!("{:width$} {} {}", code, RESULT_MARKER, result))
+            }
+            // The result of an earlier run is stale once the line has none
+            None if line.contains(RESULT_MARKER) => output.push_str(code),
+            None => output.push_str(line),
+        }
+        output.push_str(ending);
+    }
+}
+
+/// The fence and first word of the info string if the line opens a code block
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    let marker = line
+        .chars()
+        .next()
+        .filter(|char| *char == '`' || *char == '~')?;
+    let length = line.len() - line.trim_start_matches(marker).len();
+    if length < 3 {
+        return None;
+    }
+    let (fence, info) = line.split_at(length);
+    let language = info.split_whitespace().next().unwrap_or("");
+    Some((fence, language))
+}
+
+fn is_closing_fence(line: &str, opening: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(opening) && line.chars().all(|char| opening.starts_with(char))
+}
+
+/// The line without the result of an earlier run or trailing whitespace
+fn strip_result(line: &str) -> &str {
+    match line.rfind(RESULT_MARKER) {
+        Some(start) => &line[..start],
+        None => line,
+    }
+    .trim_end()
+}
+
+fn format_result(line: &Line) -> Option<String> {
+    let result = match &line.result {
+        Ok(Some(value)) => value.to_string(),
+        Ok(None) => return None,
+        Err(error) => format!("error: {}", error),
+    };
+    Some(match line.warnings.as_slice() {
+        [] => result,
+        warnings => format!("{} ({})", result, warnings.join("; ")),
+    })
+}
+
+/// Writes results into the inline code of a line that starts with `=`
+fn evaluate_inline(line: &str, context: &mut Context) -> String {
+    let mut output = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('`') {
+        let Some(length) = rest[start + 1..].find('`') else {
+            break;
+        };
+        let code = &rest[start + 1..start + 1 + length];
+        output.push_str(&rest[..=start]);
+        match inline_expression(code) {
+            Some(expression) => {
+                let result = match context.evaluate_str(expression) {
+                    Ok(Some(value)) => value.to_string(),
+                    Ok(None) => String::new(),
+                    Err(error) => format!("error: {}", error),
+                };
+                output.push_str(format!("= {} = {}", expression, result).trim_end());
+            }
+            None => output.push_str(code),
+        }
+        output.push('`');
+        rest = &rest[start + length + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// The expression of inline code such as `= 2 + 3 =` or `= 2 + 3 = 5`
+fn inline_expression(code: &str) -> Option<&str> {
+    let code = code.strip_prefix('=')?;
+    // The result follows the last `=` that is not part of an operator such as `==`
+    let bytes = code.as_bytes();
+    let end = (0..bytes.len()).rev().find(|&index| {
+        bytes[index] == b'='
+            && !(index > 0 && b"=<>!~".contains(&bytes[index - 1]))
+            && bytes.get(index + 1) != Some(&b'=')
+    })?;
+    let expression = code[..end].trim();
+    (!expression.is_empty()).then_some(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Evaluates the Markdown, checking that evaluating the result again changes nothing
+    fn evaluate(source: &str) -> String {
+        let evaluated = evaluate_markdown(source);
+        assert_eq!(evaluate_markdown(&evaluated), evaluated, "{}", source);
+        evaluated
+    }
+
+    #[test]
+    fn test_blocks() {
+        let source = "# Disk usage\n\
+                      \n\
+                      ```culator\n\
+                      disks = 12  \n\
+                      Size: disks * 4 TB in TiB # raw\n\
+                      \n\
+                      1 / 0\n\
+                      ```\n\
+                      \n\
+                      ```sh\n\
+                      echo 1 + 2\n\
+                      ```\n\
+                      ~~~~ culator extra\n\
+                      disks * 2\n\
+                      ~~~~\n";
+        assert_eq!(
+            evaluate(source),
+            "# Disk usage\n\
+             \n\
+             ```culator\n\
+             disks = 12                      # => 12\n\
+             Size: disks * 4 TB in TiB # raw # => 43.655745685100555 TiB\n\
+             \n\
+             1 / 0                           # => error: Division by zero\n\
+             ```\n\
+             \n\
+             ```sh\n\
+             echo 1 + 2\n\
+             ```\n\
+             ~~~~ culator extra\n\
+             disks * 2 # => 24\n\
+             ~~~~\n"
+        );
+        // Lines change and results are updated
+        let edited = evaluate(source).replace("disks = 12", "disks = 10");
+        assert!(evaluate(&edited).contains("disks * 2 # => 20\n"));
+    }
+
+    #[test]
+    fn test_inline() {
+        let source = "Set `x = 3` to `= 2^10 =`, then `= x = 4 =` gives `= x == 4 = false` \
+                      and `= 1 / 0 =`. Still `= =` and `=2`.\r\n";
+        assert_eq!(
+            evaluate(source),
+            "Set `x = 3` to `= 2^10 = 1024`, then `= x = 4 = 4` gives `= x == 4 = true` \
+             and `= 1 / 0 = error: Division by zero`. Still `= =` and `=2`.\r\n"
+        );
+        // Inline code in other code blocks is left alone
+        assert_eq!(evaluate("```\n`= 1 + 1 =`\n```"), "```\n`= 1 + 1 =`\n```");
+    }
+
+    #[test]
+    fn test_unclosed_block() {
+        assert_eq!(evaluate("```culator\n2 * 3"), "```culator\n2 * 3 # => 6");
+    }
+
+    #[test]
+    fn test_stale_results() {
+        assert_eq!(
+            evaluate("```culator\nNotes # => 5\nx = 2 # => 1\n# => 3\n```\n"),
+            "```culator\nNotes\nx = 2 # => 2\n\n```\n"
+        );
+    }
+}
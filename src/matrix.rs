@@ -0,0 +1,413 @@
+//! Matrix functions over lists of equally long rows, e.g. `det([[1, 2], [3, 4]])`
+//!
+//! Arithmetic operators stay element-wise on matrices, `matmul` is the matrix product.
+//! Where a function accepts a vector, a flat list such as `[1, 2]` is treated as a
+//! column and the result is a flat list again.
+
+use crate::functions::{Builtin, FunctionRegistry};
+use crate::value::Value;
+
+/// Pivots smaller than this, relative to the largest entry, make a matrix singular
+const SINGULARITY_TOLERANCE: f64 = 1e-12;
+
+/// Entries of the largest matrix a function makes, keeping allocations bounded
+const MAX_ENTRIES: f64 = 1e6;
+
+const MATRIX_TOO_LARGE: &str = "Matrix is too large";
+
+const RAGGED_MATRIX: &str = "Matrix rows differ in length";
+
+pub fn register(registry: &mut FunctionRegistry) {
+    let functions = [
+        Builtin::new("matmul", 2..=2, |arguments| {
+            let (right, is_vector) = Matrix::from_value_or_vector(&arguments[1])?;
+            let product = Matrix::from_value(&arguments[0])?.multiply(&right)?;
+            Ok(product.into_value(is_vector))
+        })
+        .doc("Matrix product, matmul(A, B)"),
+        Builtin::new("transpose", 1..=1, |arguments| {
+            Ok(Matrix::from_value(&arguments[0])?
+                .transpose()
+                .into_value(false))
+        })
+        .doc("Rows and columns swapped"),
+        Builtin::new("det", 1..=1, |arguments| {
+            Matrix::from_value(&arguments[0])?
+                .determinant()
+                .map(Value::Number)
+        })
+        .doc("Determinant of a square matrix"),
+        Builtin::new("inv", 1..=1, |arguments| {
+            Ok(Matrix::from_value(&arguments[0])?
+                .inverse()?
+                .into_value(false))
+        })
+        .alias("inverse")
+        .doc("Inverse of a square matrix"),
+        Builtin::new("identity", 1..=1, |arguments| {
+            let size = arguments[0].magnitude()?;
+            if size < 1.0 || size.fract() != 0.0 {
+                return Err("Matrix size must be a positive integer");
+            }
+            if size * size > MAX_ENTRIES {
+                return Err(MATRIX_TOO_LARGE);
+            }
+            Ok(Matrix::identity(size as usize).into_value(false))
+        })
+        .doc("Identity matrix of the given size"),
+        Builtin::new("solve", 2..=2, |arguments| {
+            let (constants, is_vector) = Matrix::from_value_or_vector(&arguments[1])?;
+            let solution = Matrix::from_value(&arguments[0])?.solve(&constants)?;
+            Ok(solution.into_value(is_vector))
+        })
+        .doc("Solution x of A x = b, solve(A, b)"),
+    ];
+    for function in functions {
+        registry.register(function);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    columns: usize,
+    /// Entries in row-major order
+    entries: Vec<f64>,
+}
+
+impl Matrix {
+    /// Reads a list of equally long lists of numbers
+    pub fn from_value(value: &Value) -> Result<Matrix, &'static str> {
+        let Value::List(rows) = value else {
+            return Err("Expected a matrix");
+        };
+        let mut entries = vec![];
+        let mut columns = None;
+        for row in rows {
+            let Value::List(row) = row else {
+                // Numbers beside rows make a ragged matrix rather than a vector
+                return Err(if rows.iter().any(|row| matches!(row, Value::List(_))) {
+                    RAGGED_MATRIX
+                } else {
+                    "Expected a matrix"
+                });
+            };
+            if *columns.get_or_insert(row.len()) != row.len() {
+                return Err(RAGGED_MATRIX);
+            }
+            for entry in row {
+                match entry {
+                    Value::Number(number) => entries.push(*number),
+                    _ => return Err("Matrix entries must be numbers"),
+                }
+            }
+        }
+        match columns {
+            Some(columns) if columns > 0 => Ok(Matrix {
+                rows: rows.len(),
+                columns,
+                entries,
+            }),
+            _ => Err("Matrix must not be empty"),
+        }
+    }
+
+    /// Reads a matrix, or a flat list as a column vector
+    fn from_value_or_vector(value: &Value) -> Result<(Matrix, bool), &'static str> {
+        match value {
+            Value::List(elements) if !elements.iter().any(|e| matches!(e, Value::List(_))) => {
+                let rows = elements
+                    .iter()
+                    .cloned()
+                    .map(|e| Value::List(vec![e]))
+                    .collect();
+                Ok((Matrix::from_value(&Value::List(rows))?, true))
+            }
+            _ => Ok((Matrix::from_value(value)?, false)),
+        }
+    }
+
+    /// Lists of rows, or a flat list for a column vector when `as_vector` is set
+    pub fn into_value(self, as_vector: bool) -> Value {
+        let numbers = self.entries.into_iter().map(Value::Number);
+        if as_vector && self.columns == 1 {
+            return Value::List(numbers.collect());
+        }
+        let numbers: Vec<Value> = numbers.collect();
+        Value::List(
+            numbers
+                .chunks(self.columns)
+                .map(|row| Value::List(row.to_vec()))
+                .collect(),
+        )
+    }
+
+    pub fn identity(size: usize) -> Matrix {
+        let mut matrix = Matrix::zero(size, size);
+        for i in 0..size {
+            *matrix.at(i, i) = 1.0;
+        }
+        matrix
+    }
+
+    fn zero(rows: usize, columns: usize) -> Matrix {
+        Matrix {
+            rows,
+            columns,
+            entries: vec![0.0; rows * columns],
+        }
+    }
+
+    fn get(&self, row: usize, column: usize) -> f64 {
+        self.entries[row * self.columns + column]
+    }
+
+    fn at(&mut self, row: usize, column: usize) -> &mut f64 {
+        &mut self.entries[row * self.columns + column]
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        let mut transposed = Matrix::zero(self.columns, self.rows);
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                *transposed.at(column, row) = self.get(row, column);
+            }
+        }
+        transposed
+    }
+
+    pub fn multiply(&self, other: &Matrix) -> Result<Matrix, &'static str> {
+        if self.columns != other.rows {
+            return Err("Matrix columns must match the rows of the other matrix");
+        }
+        if (self.rows * other.columns) as f64 > MAX_ENTRIES {
+            return Err(MATRIX_TOO_LARGE);
+        }
+        let mut product = Matrix::zero(self.rows, other.columns);
+        for row in 0..self.rows {
+            for column in 0..other.columns {
+                *product.at(row, column) = (0..self.columns)
+                    .map(|k| self.get(row, k) * other.get(k, column))
+                    .sum();
+            }
+        }
+        Ok(product)
+    }
+
+    pub fn determinant(&self) -> Result<f64, &'static str> {
+        if self.rows != self.columns {
+            return Err("Matrix must be square");
+        }
+        let mut matrix = self.clone();
+        let mut determinant = 1.0;
+        for pivot in 0..matrix.rows {
+            let Some(pivot_row) = matrix.pivot_row(pivot) else {
+                return Ok(0.0);
+            };
+            if pivot_row != pivot {
+                matrix.swap_rows(pivot, pivot_row);
+                determinant = -determinant;
+            }
+            determinant *= matrix.get(pivot, pivot);
+            matrix.eliminate_below(pivot);
+        }
+        Ok(determinant)
+    }
+
+    pub fn inverse(&self) -> Result<Matrix, &'static str> {
+        self.solve(&Matrix::identity(self.rows))
+    }
+
+    /// Solves `self * x = constants` for `x` by Gauss-Jordan elimination
+    pub fn solve(&self, constants: &Matrix) -> Result<Matrix, &'static str> {
+        if self.rows != self.columns {
+            return Err("Matrix must be square");
+        }
+        if constants.rows != self.rows {
+            return Err("Right-hand side must have as many rows as the matrix");
+        }
+
+        let mut matrix = self.clone();
+        let mut solution = constants.clone();
+        for pivot in 0..matrix.rows {
+            let pivot_row = matrix.pivot_row(pivot).ok_or("Matrix is singular")?;
+            matrix.swap_rows(pivot, pivot_row);
+            solution.swap_rows(pivot, pivot_row);
+
+            let pivot_value = matrix.get(pivot, pivot);
+            for row in 0..matrix.rows {
+                if row == pivot {
+                    continue;
+                }
+                let factor = matrix.get(row, pivot) / pivot_value;
+                matrix.subtract_row(row, pivot, factor);
+                solution.subtract_row(row, pivot, factor);
+            }
+        }
+        for row in 0..solution.rows {
+            let pivot_value = matrix.get(row, row);
+            for column in 0..solution.columns {
+                *solution.at(row, column) /= pivot_value;
+            }
+        }
+        Ok(solution)
+    }
+
+    /// The row at or below `pivot` with the largest entry in the pivot column,
+    /// `None` when that entry is negligible
+    fn pivot_row(&self, pivot: usize) -> Option<usize> {
+        let scale = self
+            .entries
+            .iter()
+            .fold(0.0f64, |max, entry| max.max(entry.abs()));
+        let best = (pivot..self.rows).max_by(|a, b| {
+            self.get(*a, pivot)
+                .abs()
+                .total_cmp(&self.get(*b, pivot).abs())
+        })?;
+        let magnitude = self.get(best, pivot).abs();
+        (magnitude > SINGULARITY_TOLERANCE * scale && magnitude.is_finite()).then_some(best)
+    }
+
+    fn swap_rows(&mut self, a: usize, b: usize) {
+        for column in 0..self.columns {
+            self.entries
+                .swap(a * self.columns + column, b * self.columns + column);
+        }
+    }
+
+    /// Subtracts `factor` times the `source` row from the `target` row
+    fn subtract_row(&mut self, target: usize, source: usize, factor: f64) {
+        for column in 0..self.columns {
+            let value = self.get(source, column);
+            *self.at(target, column) -= factor * value;
+        }
+    }
+
+    fn eliminate_below(&mut self, pivot: usize) {
+        let pivot_value = self.get(pivot, pivot);
+        for row in pivot + 1..self.rows {
+            let factor = self.get(row, pivot) / pivot_value;
+            self.subtract_row(row, pivot, factor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(rows: &[&[f64]]) -> Value {
+        Value::List(
+            rows.iter()
+                .map(|row| Value::List(row.iter().map(|n| Value::Number(*n)).collect()))
+                .collect(),
+        )
+    }
+
+    fn vector(elements: &[f64]) -> Value {
+        Value::List(elements.iter().map(|n| Value::Number(*n)).collect())
+    }
+
+    fn call(name: &str, arguments: Vec<Value>) -> Result<Value, &'static str> {
+        let mut registry = FunctionRegistry::empty();
+        register(&mut registry);
+        registry.call(name, &arguments)
+    }
+
+    #[test]
+    fn test_matrix_functions() {
+        let a = || matrix(&[&[1.0, 2.0], &[3.0, 4.0]]);
+        assert_eq!(call("det", vec![a()]).unwrap(), Value::Number(-2.0));
+        assert_eq!(
+            call("transpose", vec![a()]).unwrap(),
+            matrix(&[&[1.0, 3.0], &[2.0, 4.0]])
+        );
+        assert_eq!(
+            call("matmul", vec![a(), a()]).unwrap(),
+            matrix(&[&[7.0, 10.0], &[15.0, 22.0]])
+        );
+        assert_eq!(
+            call("matmul", vec![a(), vector(&[1.0, 1.0])]).unwrap(),
+            vector(&[3.0, 7.0])
+        );
+        let inverse = Matrix::from_value(&call("inv", vec![a()]).unwrap()).unwrap();
+        for (entry, expected) in inverse.entries.iter().zip([-2.0, 1.0, 1.5, -0.5]) {
+            assert!((entry - expected).abs() < 1e-12);
+        }
+        assert_eq!(
+            call("identity", vec![Value::Number(2.0)]).unwrap(),
+            matrix(&[&[1.0, 0.0], &[0.0, 1.0]])
+        );
+    }
+
+    #[test]
+    fn test_solve_linear_system() {
+        // 2x + y - z = 8, -3x - y + 2z = -11, -2x + y + 2z = -3
+        let a = matrix(&[&[2.0, 1.0, -1.0], &[-3.0, -1.0, 2.0], &[-2.0, 1.0, 2.0]]);
+        let b = vector(&[8.0, -11.0, -3.0]);
+        let Value::List(solution) = call("solve", vec![a, b]).unwrap() else {
+            panic!("Expected a vector");
+        };
+        for (value, expected) in solution.iter().zip([2.0, 3.0, -1.0]) {
+            assert!((value.magnitude().unwrap() - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_shape_errors() {
+        let square = || matrix(&[&[1.0, 2.0], &[3.0, 4.0]]);
+        let wide = || matrix(&[&[1.0, 2.0, 3.0]]);
+        let singular = matrix(&[&[1.0, 2.0], &[2.0, 4.0]]);
+        let cases = [
+            ("det", vec![wide()], "Matrix must be square"),
+            (
+                "matmul",
+                vec![square(), wide()],
+                "Matrix columns must match the rows of the other matrix",
+            ),
+            ("inv", vec![singular], "Matrix is singular"),
+            (
+                "solve",
+                vec![square(), vector(&[1.0, 2.0, 3.0])],
+                "Right-hand side must have as many rows as the matrix",
+            ),
+            (
+                "det",
+                vec![Value::List(vec![vector(&[1.0]), vector(&[1.0, 2.0])])],
+                RAGGED_MATRIX,
+            ),
+            (
+                "matmul",
+                vec![
+                    square(),
+                    Value::List(vec![Value::Number(1.0), vector(&[2.0])]),
+                ],
+                RAGGED_MATRIX,
+            ),
+            (
+                "inverse",
+                vec![Value::List(vec![vector(&[1.0, 2.0]), Value::Number(3.0)])],
+                RAGGED_MATRIX,
+            ),
+            ("det", vec![vector(&[1.0, 2.0])], "Expected a matrix"),
+            (
+                "identity",
+                vec![Value::Number(1.5)],
+                "Matrix size must be a positive integer",
+            ),
+            ("identity", vec![Value::Number(1e9)], "Matrix is too large"),
+            (
+                "matmul",
+                vec![
+                    Value::List(vec![vector(&[1.0]); 2000]),
+                    matrix(&[&[1.0; 2000]]),
+                ],
+                "Matrix is too large",
+            ),
+        ];
+        for (name, arguments, message) in cases {
+            assert_eq!(call(name, arguments), Err(message), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Combinatorics and number theory, e.g. `choose(50, 25)` or `factor(360)`
+//!
+//! Arguments must be integers that a number holds exactly, i.e. up to 2^53 in
+//! magnitude. Intermediate results are computed in 128-bit integers, and a final
+//! result too large to hold exactly is an error rather than rounded.
+
+use crate::functions::{Builtin, FunctionRegistry};
+use crate::value::Value;
+
+/// The largest integer up to which every integer is exactly representable as an `f64`
+pub const MAX_EXACT_INTEGER: i128 = 1 << 53;
+
+const RESULT_TOO_LARGE: &str = "Result is too large";
+
+/// Factors below this are found by trial division
+const SMALL_FACTORS: i128 = 1000;
+
+pub fn register(registry: &mut FunctionRegistry) {
+    let functions = [
+        Builtin::new("choose", 2..=2, |arguments| {
+            choose(integer(&arguments[0])?, integer(&arguments[1])?).and_then(number)
+        })
+        .alias("nCr")
+        .alias("binomial")
+        .integers()
+        .doc("Number of ways to choose k of n items, choose(n, k)"),
+        Builtin::new("nPr", 2..=2, |arguments| {
+            permutations(integer(&arguments[0])?, integer(&arguments[1])?).and_then(number)
+        })
+        .alias("permutations")
+        .integers()
+        .doc("Number of ordered arrangements of k of n items, nPr(n, k)"),
+        Builtin::new("gcd", 1.., |arguments| {
+            let integers = integers(arguments)?;
+            number(integers[1..].iter().fold(integers[0], |a, b| gcd(a, *b)))
+        })
+        .integers()
+        .doc("Greatest common divisor"),
+        Builtin::new("lcm", 1.., |arguments| {
+            let integers = integers(arguments)?;
+            integers[1..]
+                .iter()
+                .try_fold(integers[0].abs(), |a, b| lcm(a, *b))
+                .and_then(number)
+        })
+        .integers()
+        .doc("Least common multiple"),
+        Builtin::numeric("mod", 2..=2, |numbers| modulo(numbers[0], numbers[1]))
+            .doc("Remainder of a floored division, with the sign of the divisor"),
+        Builtin::new("isprime", 1..=1, |arguments| {
+            Ok(Value::Bool(is_prime(integer(&arguments[0])?)))
+        })
+        .integers()
+        .doc("Whether the integer is prime"),
+        Builtin::new("nextprime", 1..=1, |arguments| {
+            next_prime(integer(&arguments[0])?).and_then(number)
+        })
+        .integers()
+        .doc("Smallest prime greater than the integer"),
+        Builtin::new("factor", 1..=1, |arguments| {
+            let factors = factor(integer(&arguments[0])?)?;
+            let factors = factors.into_iter().map(number).collect::<Result<_, _>>()?;
+            Ok(Value::List(factors))
+        })
+        .integers()
+        .doc("Prime factors in ascending order, repeated by multiplicity"),
+        Builtin::new("totient", 1..=1, |arguments| {
+            totient(integer(&arguments[0])?).and_then(number)
+        })
+        .integers()
+        .doc("Number of integers from 1 to n coprime to n"),
+        Builtin::new("powmod", 3..=3, |arguments| {
+            let [base, exponent, modulus] = [0, 1, 2].map(|i| integer(&arguments[i]));
+            powmod(base?, exponent?, modulus?).and_then(number)
+        })
+        .integers()
+        .doc("Modular exponentiation, powmod(base, exponent, modulus)"),
+    ];
+    for function in functions {
+        registry.register(function);
+    }
+}
+
+fn integers(arguments: &[Value]) -> Result<Vec<i128>, &'static str> {
+    arguments.iter().map(integer).collect()
+}
+
+fn integer(value: &Value) -> Result<i128, &'static str> {
+    let Value::Number(number) = value else {
+        return Err("Expected an integer");
+    };
+    if number.fract() != 0.0 || !number.is_finite() {
+        return Err("Expected an integer");
+    }
+    if number.abs() > MAX_EXACT_INTEGER as f64 {
+        return Err("Integer is too large to be exact");
+    }
+    Ok(*number as i128)
+}
+
+fn number(integer: i128) -> Result<Value, &'static str> {
+    // Compared before the conversion, which would round 2^53 + 1 down to 2^53
+    if integer.abs() > MAX_EXACT_INTEGER {
+        return Err(RESULT_TOO_LARGE);
+    }
+    Ok(Value::Number(integer as f64))
+}
+
+/// The remainder of a floored division, which has the sign of the divisor,
+/// so `mod(-7, 3)` is 2
+pub fn modulo(dividend: f64, divisor: f64) -> Result<f64, &'static str> {
+    if divisor == 0.0 {
+        return Err("Modulo by zero");
+    }
+    let remainder = dividend % divisor;
+    if remainder != 0.0 && (remainder < 0.0) != (divisor < 0.0) {
+        Ok(remainder + divisor)
+    } else {
+        Ok(remainder)
+    }
+}
+
+/// The number of ways to choose k of n items, zero when k is out of range
+pub fn choose(n: i128, k: i128) -> Result<i128, &'static str> {
+    if n < 0 {
+        return Err("Combinations of a negative number of items");
+    }
+    if k < 0 || k > n {
+        return Ok(0);
+    }
+    let k = k.min(n - k);
+    // Each partial product is itself a binomial coefficient, so the division is exact
+    (0..k).try_fold(1i128, |result, i| {
+        result
+            .checked_mul(n - i)
+            .map(|product| product / (i + 1))
+            .ok_or(RESULT_TOO_LARGE)
+    })
+}
+
+/// The number of ordered arrangements of k of n items
+pub fn permutations(n: i128, k: i128) -> Result<i128, &'static str> {
+    if n < 0 {
+        return Err("Permutations of a negative number of items");
+    }
+    if k < 0 || k > n {
+        return Ok(0);
+    }
+    (n - k + 1..=n).try_fold(1i128, |result, factor| {
+        result.checked_mul(factor).ok_or(RESULT_TOO_LARGE)
+    })
+}
+
+pub fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+pub fn lcm(a: i128, b: i128) -> Result<i128, &'static str> {
+    if a == 0 || b == 0 {
+        return Ok(0);
+    }
+    (a.abs() / gcd(a, b))
+        .checked_mul(b.abs())
+        .ok_or(RESULT_TOO_LARGE)
+}
+
+/// Deterministic Miller-Rabin, exact for every integer below 2^64
+pub fn is_prime(n: i128) -> bool {
+    const WITNESSES: [i128; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+    if n < 2 {
+        return false;
+    }
+    for witness in WITNESSES {
+        if n % witness == 0 {
+            return n == witness;
+        }
+    }
+
+    let mut odd_part = n - 1;
+    let mut twos = 0;
+    while odd_part % 2 == 0 {
+        odd_part /= 2;
+        twos += 1;
+    }
+    WITNESSES.iter().all(|&witness| {
+        let mut x = power_modulo(witness, odd_part, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..twos {
+            x = x * x % n;
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// The smallest prime greater than n
+pub fn next_prime(n: i128) -> Result<i128, &'static str> {
+    let mut candidate = n.max(1) + 1;
+    while !is_prime(candidate) {
+        candidate += 1;
+    }
+    if candidate > MAX_EXACT_INTEGER {
+        return Err(RESULT_TOO_LARGE);
+    }
+    Ok(candidate)
+}
+
+/// Prime factors in ascending order, repeated by multiplicity, e.g. 12 is [2, 2, 3]
+pub fn factor(n: i128) -> Result<Vec<i128>, &'static str> {
+    if n < 1 {
+        return Err("Factorization of non-positive integer");
+    }
+    let mut factors = vec![];
+    let mut remaining = n;
+    for divisor in 2..SMALL_FACTORS {
+        while remaining % divisor == 0 {
+            factors.push(divisor);
+            remaining /= divisor;
+        }
+    }
+    // What remains has only large prime factors, which are split apart rather than
+    // found by trial division up to its square root
+    let mut pending = vec![remaining];
+    while let Some(composite) = pending.pop() {
+        if composite == 1 {
+            continue;
+        }
+        if is_prime(composite) {
+            factors.push(composite);
+            continue;
+        }
+        let divisor = find_divisor(composite);
+        pending.extend([divisor, composite / divisor]);
+    }
+    factors.sort_unstable();
+    Ok(factors)
+}
+
+/// A nontrivial divisor of the composite n, which has no factors below `SMALL_FACTORS`,
+/// by Pollard's rho method in around n^(1/4) steps
+fn find_divisor(n: i128) -> i128 {
+    (1..)
+        .find_map(|c| {
+            let next = |x: i128| (x * x + c) % n;
+            let (mut x, mut y, mut divisor) = (2, 2, 1);
+            while divisor == 1 {
+                x = next(x);
+                y = next(next(y));
+                divisor = gcd(x - y, n);
+            }
+            // The sequences met modulo n itself, so try another one
+            (divisor != n).then_some(divisor)
+        })
+        .expect("a composite has a divisor")
+}
+
+/// The number of integers from 1 to n that are coprime to n
+pub fn totient(n: i128) -> Result<i128, &'static str> {
+    if n < 1 {
+        return Err("Totient of non-positive integer");
+    }
+    let mut primes = factor(n)?;
+    primes.dedup();
+    Ok(primes
+        .into_iter()
+        .fold(n, |result, prime| result / prime * (prime - 1)))
+}
+
+/// base^exponent mod modulus, with a result between 0 and modulus - 1
+pub fn powmod(base: i128, exponent: i128, modulus: i128) -> Result<i128, &'static str> {
+    if modulus < 1 {
+        return Err("Modulus must be a positive integer");
+    }
+    if exponent < 0 {
+        return Err("Negative exponent in modular exponentiation");
+    }
+    Ok(power_modulo(base.rem_euclid(modulus), exponent, modulus))
+}
+
+/// Square-and-multiply for 0 <= base < modulus, where modulus < 2^64 keeps every
+/// product within 128 bits
+fn power_modulo(base: i128, mut exponent: i128, modulus: i128) -> i128 {
+    let mut result = 1 % modulus;
+    let mut base = base % modulus;
+    while exponent > 0 {
+        if exponent % 2 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exponent /= 2;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combinatorics() {
+        assert_eq!(choose(5, 2), Ok(10));
+        assert_eq!(choose(5, 6), Ok(0));
+        assert_eq!(choose(60, 30), Ok(118264581564861424));
+        assert_eq!(choose(200, 100), Err(RESULT_TOO_LARGE));
+        // Results that fit into 128 bits but not exactly into a number are not rounded
+        assert_eq!(choose(100, 50).and_then(number), Err(RESULT_TOO_LARGE));
+        assert_eq!(lcm(321, 28_059_810_762_433), Ok(9_007_199_254_740_993));
+        assert_eq!(number(9_007_199_254_740_993), Err(RESULT_TOO_LARGE));
+        assert_eq!(permutations(5, 2), Ok(20));
+        assert_eq!(permutations(20, 20), Ok(2432902008176640000));
+        assert!(choose(-1, 0).is_err());
+    }
+
+    #[test]
+    fn test_divisibility() {
+        assert_eq!(gcd(-12, 18), 6);
+        assert_eq!(gcd(0, 7), 7);
+        assert_eq!(lcm(4, -6), Ok(12));
+        assert_eq!(modulo(-7.0, 3.0), Ok(2.0));
+        assert_eq!(modulo(7.0, -3.0), Ok(-2.0));
+        assert_eq!(modulo(5.5, 2.0), Ok(1.5));
+        assert!(modulo(1.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_primes() {
+        let primes: Vec<i128> = (0..30).filter(|n| is_prime(*n)).collect();
+        assert_eq!(primes, [2, 3, 5, 7, 11, 13, 17, 19, 23, 29]);
+        // Strong pseudoprime to several small bases
+        assert!(!is_prime(3_215_031_751));
+        assert!(is_prime(9_007_199_254_740_881));
+        assert_eq!(next_prime(13), Ok(17));
+        assert_eq!(next_prime(-5), Ok(2));
+    }
+
+    #[test]
+    fn test_factorization() {
+        assert_eq!(factor(360), Ok(vec![2, 2, 2, 3, 3, 5]));
+        assert_eq!(factor(1), Ok(vec![]));
+        assert_eq!(
+            factor(9_007_199_254_740_881),
+            Ok(vec![9_007_199_254_740_881])
+        );
+        assert_eq!(factor(600_851_475_143), Ok(vec![71, 839, 1471, 6857]));
+        // Factors too large for trial division in reasonable time
+        assert_eq!(
+            factor(9_007_195_909_437_503),
+            Ok(vec![94_906_247, 94_906_249])
+        );
+        assert_eq!(
+            factor(99_999_820_000_081 * 1009),
+            Ok(vec![1009, 9_999_991, 9_999_991])
+        );
+        assert_eq!(totient(36), Ok(12));
+        assert_eq!(totient(1), Ok(1));
+        assert!(factor(0).is_err());
+    }
+
+    #[test]
+    fn test_powmod() {
+        assert_eq!(powmod(4, 13, 497), Ok(445));
+        assert_eq!(powmod(-2, 3, 5), Ok(2));
+        assert_eq!(powmod(2, 0, 1), Ok(0));
+        // Operands near 2^53 would lose precision as numbers
+        assert_eq!(
+            powmod(9_007_199_254_740_000, 2, 9_007_199_254_740_881),
+            Ok(776_161)
+        );
+        assert!(powmod(2, -1, 5).is_err());
+    }
+}
@@ -1,9 +1,21 @@
+use std::{fmt, mem};
+
+use itertools::Itertools;
+
 use crate::lexer::Token;
+use crate::limits::{self, Limits};
 
 // 35% of 230
 // Percentage(Box<Expression>, Box<Expression>),
 
-#[derive(Debug, PartialEq)]
+/// A parsed expression tree
+///
+/// Chains of `+`, `-`, `*` and `/` are as long as the input allows, as parsing them
+/// counts no nesting, and nest one level deeper down their left operands for every
+/// operator. Whatever walks every node of a tree, such as cloning, comparing, printing,
+/// dropping and evaluating, therefore follows these chains in a loop rather than by
+/// recursion, so that a long chain cannot overflow the stack.
+#[derive(Debug)]
 pub enum Expression {
     NumericLiteral(f64),
     Minus(Box<Expression>),
@@ -13,8 +25,478 @@ pub enum Expression {
     Division(Box<Expression>, Box<Expression>),
     Exponentiation(Box<Expression>, Box<Expression>),
     FunctionCall(String, Vec<Expression>),
+    /// A unit or other named value, e.g. `GiB`
+    Name(String),
+    /// Expresses the left side in the unit on the right, e.g. `1.5 TB in GiB`
+    Conversion(Box<Expression>, Box<Expression>),
+    /// Defines a new unit, e.g. `unit furlong = 201.168 m`
+    UnitDefinition(String, Box<Expression>),
+    BooleanLiteral(bool),
+    LessThan(Box<Expression>, Box<Expression>),
+    LessThanOrEqual(Box<Expression>, Box<Expression>),
+    GreaterThan(Box<Expression>, Box<Expression>),
+    GreaterThanOrEqual(Box<Expression>, Box<Expression>),
+    Equal(Box<Expression>, Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    /// Equality within a relative tolerance, e.g. `sqrt(2)^2 ~= 2`
+    ApproximatelyEqual(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    /// Assigns a variable, e.g. `rate = 0.2`
+    Assignment(String, Box<Expression>),
+    /// Defines a function of named parameters, e.g. `f(x, y) = x^2 + y`
+    FunctionDefinition(String, Vec<String>, Box<Expression>),
+    /// A list literal, e.g. `[1, 2, 3]`
+    List(Vec<Expression>),
+    /// Consecutive numbers from start to end inclusive, e.g. `1..10`
+    Range(Box<Expression>, Box<Expression>),
+    /// Postfix factorial, e.g. `5!`
+    Factorial(Box<Expression>),
+    /// Postfix double factorial, e.g. `7!! = 7 * 5 * 3 * 1`
+    DoubleFactorial(Box<Expression>),
+    /// An expression with a label that does not affect its value, e.g. `rent: 1200`
+    Labeled(String, Box<Expression>),
+}
+
+impl Expression {
+    /// The direct subexpressions of this expression
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::NumericLiteral(_) | Expression::Name(_) | Expression::BooleanLiteral(_) => {
+                vec![]
+            }
+            Expression::Minus(operand)
+            | Expression::Not(operand)
+            | Expression::Factorial(operand)
+            | Expression::DoubleFactorial(operand)
+            | Expression::Labeled(_, operand) => vec![operand],
+            Expression::Subtraction(left, right)
+            | Expression::Addition(left, right)
+            | Expression::Multiplication(left, right)
+            | Expression::Division(left, right)
+            | Expression::Exponentiation(left, right)
+            | Expression::Conversion(left, right)
+            | Expression::LessThan(left, right)
+            | Expression::LessThanOrEqual(left, right)
+            | Expression::GreaterThan(left, right)
+            | Expression::GreaterThanOrEqual(left, right)
+            | Expression::Equal(left, right)
+            | Expression::NotEqual(left, right)
+            | Expression::ApproximatelyEqual(left, right)
+            | Expression::And(left, right)
+            | Expression::Or(left, right) => vec![left, right],
+            Expression::FunctionCall(_, args) | Expression::List(args) => args.iter().collect(),
+            Expression::UnitDefinition(_, definition)
+            | Expression::Assignment(_, definition)
+            | Expression::FunctionDefinition(_, _, definition) => vec![definition],
+            Expression::Range(start, end) => vec![start, end],
+        }
+    }
+
+    /// The direct subexpressions of this expression, to change or take
+    pub(crate) fn children_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Expression::NumericLiteral(_) | Expression::Name(_) | Expression::BooleanLiteral(_) => {
+                vec![]
+            }
+            Expression::Minus(operand)
+            | Expression::Not(operand)
+            | Expression::Factorial(operand)
+            | Expression::DoubleFactorial(operand)
+            | Expression::Labeled(_, operand) => vec![operand],
+            Expression::Subtraction(left, right)
+            | Expression::Addition(left, right)
+            | Expression::Multiplication(left, right)
+            | Expression::Division(left, right)
+            | Expression::Exponentiation(left, right)
+            | Expression::Conversion(left, right)
+            | Expression::LessThan(left, right)
+            | Expression::LessThanOrEqual(left, right)
+            | Expression::GreaterThan(left, right)
+            | Expression::GreaterThanOrEqual(left, right)
+            | Expression::Equal(left, right)
+            | Expression::NotEqual(left, right)
+            | Expression::ApproximatelyEqual(left, right)
+            | Expression::And(left, right)
+            | Expression::Or(left, right) => vec![left, right],
+            Expression::FunctionCall(_, args) | Expression::List(args) => args.iter_mut().collect(),
+            Expression::UnitDefinition(_, definition)
+            | Expression::Assignment(_, definition)
+            | Expression::FunctionDefinition(_, _, definition) => vec![definition],
+            Expression::Range(start, end) => vec![start, end],
+        }
+    }
+
+    /// The number of levels of the tree, counted in a loop
+    pub(crate) fn height(&self) -> usize {
+        let mut height = 0;
+        let mut pending = vec![(self, 1)];
+        while let Some((expression, level)) = pending.pop() {
+            height = height.max(level);
+            pending.extend(
+                expression
+                    .children()
+                    .into_iter()
+                    .map(|child| (child, level + 1)),
+            );
+        }
+        height
+    }
+
+    /// Names referenced anywhere in the expression
+    pub fn referenced_names(&self) -> Vec<&str> {
+        let mut names = vec![];
+        let mut pending = vec![self];
+        while let Some(expression) = pending.pop() {
+            if let Expression::Name(name) = expression {
+                names.push(name.as_str());
+            }
+            pending.extend(expression.children());
+        }
+        names
+    }
+}
+
+/// Clones chains in a loop, see [`Expression`]
+impl Clone for Expression {
+    fn clone(&self) -> Expression {
+        let mut operations: Vec<(BinaryOperator, &Expression)> = vec![];
+        let mut leftmost = self;
+        loop {
+            let (operator, left, right): (BinaryOperator, _, _) = match leftmost {
+                Expression::Addition(left, right) => (Expression::Addition, left, right),
+                Expression::Subtraction(left, right) => (Expression::Subtraction, left, right),
+                Expression::Multiplication(left, right) => {
+                    (Expression::Multiplication, left, right)
+                }
+                Expression::Division(left, right) => (Expression::Division, left, right),
+                _ => break,
+            };
+            operations.push((operator, right));
+            leftmost = left;
+        }
+        let mut result = match leftmost {
+            Expression::NumericLiteral(value) => Expression::NumericLiteral(*value),
+            Expression::Name(name) => Expression::Name(name.clone()),
+            Expression::BooleanLiteral(value) => Expression::BooleanLiteral(*value),
+            Expression::Minus(operand) => Expression::Minus(operand.clone()),
+            Expression::Not(operand) => Expression::Not(operand.clone()),
+            Expression::Factorial(operand) => Expression::Factorial(operand.clone()),
+            Expression::DoubleFactorial(operand) => Expression::DoubleFactorial(operand.clone()),
+            Expression::Labeled(label, operand) => {
+                Expression::Labeled(label.clone(), operand.clone())
+            }
+            Expression::Exponentiation(left, right) => {
+                Expression::Exponentiation(left.clone(), right.clone())
+            }
+            Expression::Conversion(left, right) => {
+                Expression::Conversion(left.clone(), right.clone())
+            }
+            Expression::LessThan(left, right) => Expression::LessThan(left.clone(), right.clone()),
+            Expression::LessThanOrEqual(left, right) => {
+                Expression::LessThanOrEqual(left.clone(), right.clone())
+            }
+            Expression::GreaterThan(left, right) => {
+                Expression::GreaterThan(left.clone(), right.clone())
+            }
+            Expression::GreaterThanOrEqual(left, right) => {
+                Expression::GreaterThanOrEqual(left.clone(), right.clone())
+            }
+            Expression::Equal(left, right) => Expression::Equal(left.clone(), right.clone()),
+            Expression::NotEqual(left, right) => Expression::NotEqual(left.clone(), right.clone()),
+            Expression::ApproximatelyEqual(left, right) => {
+                Expression::ApproximatelyEqual(left.clone(), right.clone())
+            }
+            Expression::And(left, right) => Expression::And(left.clone(), right.clone()),
+            Expression::Or(left, right) => Expression::Or(left.clone(), right.clone()),
+            Expression::Range(start, end) => Expression::Range(start.clone(), end.clone()),
+            Expression::FunctionCall(name, args) => {
+                Expression::FunctionCall(name.clone(), args.clone())
+            }
+            Expression::List(elements) => Expression::List(elements.clone()),
+            Expression::UnitDefinition(name, definition) => {
+                Expression::UnitDefinition(name.clone(), definition.clone())
+            }
+            Expression::Assignment(name, value) => {
+                Expression::Assignment(name.clone(), value.clone())
+            }
+            Expression::FunctionDefinition(name, parameters, body) => {
+                Expression::FunctionDefinition(name.clone(), parameters.clone(), body.clone())
+            }
+            Expression::Addition(..)
+            | Expression::Subtraction(..)
+            | Expression::Multiplication(..)
+            | Expression::Division(..) => unreachable!("walked down above"),
+        };
+        for (operator, right) in operations.into_iter().rev() {
+            result = operator(Box::new(result), Box::new(right.clone()));
+        }
+        result
+    }
+}
+
+/// Compares chains in a loop, see [`Expression`]
+impl PartialEq for Expression {
+    fn eq(&self, other: &Expression) -> bool {
+        let (mut left, mut right) = (self, other);
+        while let (
+            Expression::Addition(left_rest, left_last),
+            Expression::Addition(right_rest, right_last),
+        )
+        | (
+            Expression::Subtraction(left_rest, left_last),
+            Expression::Subtraction(right_rest, right_last),
+        )
+        | (
+            Expression::Multiplication(left_rest, left_last),
+            Expression::Multiplication(right_rest, right_last),
+        )
+        | (
+            Expression::Division(left_rest, left_last),
+            Expression::Division(right_rest, right_last),
+        ) = (left, right)
+        {
+            if left_last != right_last {
+                return false;
+            }
+            left = left_rest;
+            right = right_rest;
+        }
+        let same_node = match (left, right) {
+            (Expression::NumericLiteral(a), Expression::NumericLiteral(b)) => a == b,
+            (Expression::BooleanLiteral(a), Expression::BooleanLiteral(b)) => a == b,
+            (Expression::Name(a), Expression::Name(b))
+            | (Expression::FunctionCall(a, _), Expression::FunctionCall(b, _))
+            | (Expression::UnitDefinition(a, _), Expression::UnitDefinition(b, _))
+            | (Expression::Assignment(a, _), Expression::Assignment(b, _))
+            | (Expression::Labeled(a, _), Expression::Labeled(b, _)) => a == b,
+            (
+                Expression::FunctionDefinition(a, a_parameters, _),
+                Expression::FunctionDefinition(b, b_parameters, _),
+            ) => a == b && a_parameters == b_parameters,
+            _ => mem::discriminant(left) == mem::discriminant(right),
+        };
+        let (left_children, right_children) = (left.children(), right.children());
+        same_node
+            && left_children.len() == right_children.len()
+            && left_children
+                .into_iter()
+                .zip(right_children)
+                .all(|(left, right)| left == right)
+    }
+}
+
+/// Drops the tree in a loop rather than recursively, see [`Expression`]
+impl Drop for Expression {
+    fn drop(&mut self) {
+        // Subexpressions with subexpressions of their own are moved out, leaving leaves
+        let take_branches = |expression: &mut Expression, pending: &mut Vec<Expression>| {
+            for child in expression.children_mut() {
+                if !child.children().is_empty() {
+                    pending.push(mem::replace(child, Expression::BooleanLiteral(false)));
+                }
+            }
+        };
+        let mut pending = vec![];
+        take_branches(self, &mut pending);
+        while let Some(mut expression) = pending.pop() {
+            take_branches(&mut expression, &mut pending);
+        }
+    }
+}
+
+/// How tightly each kind of expression binds, following the order in which [`parse`]
+/// splits at operators
+pub(crate) mod precedence {
+    pub const DEFINITION: u8 = 0;
+    pub const OR: u8 = 1;
+    pub const AND: u8 = 2;
+    pub const NOT: u8 = 3;
+    pub const COMPARISON: u8 = 4;
+    pub const CONVERSION: u8 = 5;
+    pub const RANGE: u8 = 6;
+    pub const ADDITIVE: u8 = 7;
+    pub const MULTIPLICATIVE: u8 = 8;
+    /// Implicit multiplication, e.g. `2 x`, binds tighter than `*` and `/`
+    pub const JUXTAPOSITION: u8 = 9;
+    pub const POWER: u8 = 10;
+    pub const POSTFIX: u8 = 11;
+    pub const ATOM: u8 = 12;
+}
+
+/// Names the parser treats as keywords unless they stand alone
+pub(crate) const KEYWORDS: [&str; 4] = ["and", "or", "not", "in"];
+
+impl Expression {
+    pub(crate) fn precedence(&self) -> u8 {
+        match self {
+            Expression::UnitDefinition(..)
+            | Expression::Assignment(..)
+            | Expression::FunctionDefinition(..)
+            | Expression::Labeled(..) => precedence::DEFINITION,
+            Expression::Or(..) => precedence::OR,
+            Expression::And(..) => precedence::AND,
+            Expression::Not(_) => precedence::NOT,
+            Expression::LessThan(..)
+            | Expression::LessThanOrEqual(..)
+            | Expression::GreaterThan(..)
+            | Expression::GreaterThanOrEqual(..)
+            | Expression::Equal(..)
+            | Expression::NotEqual(..)
+            | Expression::ApproximatelyEqual(..) => precedence::COMPARISON,
+            Expression::Conversion(..) => precedence::CONVERSION,
+            Expression::Range(..) => precedence::RANGE,
+            Expression::Addition(..) | Expression::Subtraction(..) | Expression::Minus(_) => {
+                precedence::ADDITIVE
+            }
+            Expression::Multiplication(left, right) if is_juxtaposition(left, right) => {
+                precedence::JUXTAPOSITION
+            }
+            Expression::Multiplication(..) | Expression::Division(..) => precedence::MULTIPLICATIVE,
+            Expression::Exponentiation(..) => precedence::POWER,
+            Expression::Factorial(_) | Expression::DoubleFactorial(_) => precedence::POSTFIX,
+            Expression::NumericLiteral(value) if value.is_sign_negative() => precedence::ADDITIVE,
+            Expression::Name(name) if KEYWORDS.contains(&name.as_str()) => precedence::DEFINITION,
+            Expression::NumericLiteral(_)
+            | Expression::Name(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::FunctionCall(..)
+            | Expression::List(_) => precedence::ATOM,
+        }
+    }
+}
+
+/// Whether a product is written without `*`, as in `2 x` or `500 Mbit`
+pub(crate) fn is_juxtaposition(left: &Expression, right: &Expression) -> bool {
+    matches!(
+        (left, right),
+        (Expression::NumericLiteral(value), Expression::Name(name))
+            if !value.is_sign_negative() && !KEYWORDS.contains(&name.as_str())
+    )
+}
+
+/// Writes an operand, in parentheses when it binds looser than `minimum`
+fn write_operand(f: &mut fmt::Formatter<'_>, operand: &Expression, minimum: u8) -> fmt::Result {
+    if operand.precedence() < minimum {
+        write!(f, "({})", operand)
+    } else {
+        write!(f, "{}", operand)
+    }
+}
+
+/// Writes a left-associative binary operation
+fn write_binary(
+    f: &mut fmt::Formatter<'_>,
+    left: &Expression,
+    operator: &str,
+    right: &Expression,
+    precedence: u8,
+) -> fmt::Result {
+    write_operand(f, left, precedence)?;
+    write!(f, "{}", operator)?;
+    write_operand(f, right, precedence + 1)
+}
+
+/// Writes a chain of `+`, `-`, `*` and `/` like [`write_binary`], in a loop down its left
+/// operands of the same precedence
+fn write_chain(f: &mut fmt::Formatter<'_>, expression: &Expression) -> fmt::Result {
+    let precedence = expression.precedence();
+    let mut operations = vec![];
+    let mut leftmost = expression;
+    while leftmost.precedence() == precedence {
+        let (operator, left, right) = match leftmost {
+            Expression::Addition(left, right) => (" + ", left, right),
+            Expression::Subtraction(left, right) => (" - ", left, right),
+            Expression::Multiplication(left, right) => (" * ", left, right),
+            Expression::Division(left, right) => (" / ", left, right),
+            _ => break,
+        };
+        operations.push((operator, right));
+        leftmost = left;
+    }
+    write_operand(f, leftmost, precedence)?;
+    for (operator, right) in operations.into_iter().rev() {
+        write!(f, "{}", operator)?;
+        write_operand(f, right, precedence + 1)?;
+    }
+    Ok(())
+}
+
+/// Writes the expression with as few parentheses as needed to parse back to the same tree
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use precedence::*;
+
+        match self {
+            Expression::NumericLiteral(value) => write!(f, "{}", value),
+            Expression::Name(name) => write!(f, "{}", name),
+            Expression::BooleanLiteral(value) => write!(f, "{}", value),
+            Expression::Minus(operand) => {
+                write!(f, "-")?;
+                write_operand(f, operand, MULTIPLICATIVE)
+            }
+            Expression::Not(operand) => {
+                write!(f, "not ")?;
+                write_operand(f, operand, COMPARISON)
+            }
+            Expression::Factorial(operand) => {
+                write_operand(f, operand, ATOM)?;
+                write!(f, "!")
+            }
+            Expression::DoubleFactorial(operand) => {
+                write_operand(f, operand, ATOM)?;
+                write!(f, "!!")
+            }
+            Expression::Multiplication(left, right) if is_juxtaposition(left, right) => {
+                write!(f, "{} {}", left, right)
+            }
+            Expression::Addition(..)
+            | Expression::Subtraction(..)
+            | Expression::Multiplication(..)
+            | Expression::Division(..) => write_chain(f, self),
+            // Right-associative, so `2^3^2` is `2^(3^2)`
+            Expression::Exponentiation(base, exponent) => {
+                write_operand(f, base, POWER + 1)?;
+                write!(f, "^")?;
+                write_operand(f, exponent, POWER)
+            }
+            Expression::Conversion(value, unit) => write_binary(f, value, " in ", unit, CONVERSION),
+            Expression::Range(start, end) => write_binary(f, start, "..", end, RANGE),
+            Expression::LessThan(left, right) => write_binary(f, left, " < ", right, COMPARISON),
+            Expression::LessThanOrEqual(left, right) => {
+                write_binary(f, left, " <= ", right, COMPARISON)
+            }
+            Expression::GreaterThan(left, right) => write_binary(f, left, " > ", right, COMPARISON),
+            Expression::GreaterThanOrEqual(left, right) => {
+                write_binary(f, left, " >= ", right, COMPARISON)
+            }
+            Expression::Equal(left, right) => write_binary(f, left, " == ", right, COMPARISON),
+            Expression::NotEqual(left, right) => write_binary(f, left, " != ", right, COMPARISON),
+            Expression::ApproximatelyEqual(left, right) => {
+                write_binary(f, left, " ~= ", right, COMPARISON)
+            }
+            Expression::And(left, right) => write_binary(f, left, " and ", right, AND),
+            Expression::Or(left, right) => write_binary(f, left, " or ", right, OR),
+            Expression::FunctionCall(name, args) => {
+                write!(f, "{}({})", name, args.iter().join(", "))
+            }
+            Expression::List(elements) => write!(f, "[{}]", elements.iter().join(", ")),
+            Expression::UnitDefinition(name, definition) => {
+                write!(f, "unit {} = {}", name, definition)
+            }
+            Expression::Assignment(name, value) => write!(f, "{} = {}", name, value),
+            Expression::FunctionDefinition(name, parameters, body) => {
+                write!(f, "{}({}) = {}", name, parameters.join(", "), body)
+            }
+            Expression::Labeled(label, expression) => write!(f, "{}: {}", label, expression),
+        }
+    }
 }
 
+/// Builds the expression for a binary operator from its operands
+type BinaryOperator = fn(Box<Expression>, Box<Expression>) -> Expression;
+
 #[derive(Debug, PartialEq)]
 enum TopLevelAtomic {
     Single {
@@ -24,30 +506,160 @@ enum TopLevelAtomic {
         start_inclusive: usize,
         end_inclusive: usize,
     },
+    BracketGroup {
+        start_inclusive: usize,
+        end_inclusive: usize,
+    },
 }
 
 pub fn parse(tokens: &[Token]) -> Result<Expression, &'static str> {
-    if tokens.is_empty() {
-        return Err("Empty input");
+    parse_with_limits(tokens, &Limits::default())
+}
+
+/// Parses tokens, failing when they nest deeper than the limits allow
+pub fn parse_with_limits(tokens: &[Token], limits: &Limits) -> Result<Expression, &'static str> {
+    parse_nested(tokens, 0, limits)
+}
+
+fn parse_nested(
+    tokens: &[Token],
+    mut depth: usize,
+    limits: &Limits,
+) -> Result<Expression, &'static str> {
+    if let [Token::Label(label), expression @ ..] = tokens {
+        let expression = parse_nested(expression, depth, limits)?;
+        return Ok(Expression::Labeled(label.clone(), Box::new(expression)));
     }
 
+    // Definitions are statements, so `=` anywhere else compares, as in `if(f(x) = 0, ...)`
+    let statement = depth == 0;
     let mut tokens = tokens;
 
-    while start_and_ends_with_parenthesis(tokens) {
+    // Redundant parentheses count towards the depth, as each is checked in full
+    loop {
+        if depth >= limits.max_depth {
+            return Err(limits::TOO_DEEP);
+        }
+        if !start_and_ends_with_parenthesis(tokens) {
+            break;
+        }
         tokens = &tokens[1..tokens.len() - 1];
+        depth += 1;
+    }
+    let parse = |tokens: &[Token]| parse_nested(tokens, depth + 1, limits);
+
+    if tokens.is_empty() {
+        return Err("Empty input");
     }
 
     if tokens.len() == 1 {
         match &tokens[0] {
             Token::NumericLiteral(number) => {
-                return Ok(Expression::NumericLiteral(number.parse().unwrap()))
+                // Literals of hundreds of digits overflow to infinity, which has no literal
+                let number: f64 = number.parse().map_err(|_| "Invalid number")?;
+                if !number.is_finite() {
+                    return Err("Invalid number");
+                }
+                return Ok(Expression::NumericLiteral(number));
+            }
+            Token::Name(name) => {
+                return Ok(match name.as_str() {
+                    "true" => Expression::BooleanLiteral(true),
+                    "false" => Expression::BooleanLiteral(false),
+                    _ => Expression::Name(name.clone()),
+                })
             }
             _ => return Err("Single token, but not a numeric literal."),
         }
     }
 
+    // Unit definitions: `unit name = definition`
+    if let [Token::Name(keyword), Token::Name(name), Token::Symbol('='), definition @ ..] = tokens {
+        if keyword == "unit" {
+            let definition = parse(definition)?;
+            return Ok(Expression::UnitDefinition(
+                name.clone(),
+                Box::new(definition),
+            ));
+        }
+    }
+
+    // Variable assignments: `name = value`
+    if let [Token::Name(name), Token::Symbol('='), value @ ..] = tokens {
+        if statement {
+            let value = parse(value)?;
+            return Ok(Expression::Assignment(name.clone(), Box::new(value)));
+        }
+    }
+
     let top_level_atoms = group_top_level_items(tokens)?;
 
+    // Function definitions: `name(parameter, ...) = body`
+    if let [TopLevelAtomic::Single { index: name_index }, TopLevelAtomic::ParenthesisGroup {
+        start_inclusive,
+        end_inclusive,
+    }, TopLevelAtomic::Single {
+        index: equals_index,
+    }, ..] = top_level_atoms.as_slice()
+    {
+        if let (true, Token::Name(name), Token::Symbol('=')) =
+            (statement, &tokens[*name_index], &tokens[*equals_index])
+        {
+            let parameters: Result<Vec<String>, &'static str> =
+                split_parameters(&tokens[*start_inclusive + 1..*end_inclusive])?
+                    .into_iter()
+                    .map(|parameter| match parameter {
+                        [Token::Name(parameter)] => Ok(parameter.clone()),
+                        _ => Err("Function parameters must be names"),
+                    })
+                    .collect();
+            let body = parse(&tokens[*equals_index + 1..])?;
+            return Ok(Expression::FunctionDefinition(
+                name.clone(),
+                parameters?,
+                Box::new(body),
+            ));
+        }
+    }
+
+    // List literals: `[element, ...]`
+    if let [TopLevelAtomic::BracketGroup {
+        start_inclusive,
+        end_inclusive,
+    }] = top_level_atoms.as_slice()
+    {
+        let elements: Result<Vec<Expression>, &'static str> =
+            split_parameters(&tokens[*start_inclusive + 1..*end_inclusive])?
+                .into_iter()
+                .map(parse)
+                .collect();
+        return Ok(Expression::List(elements?));
+    }
+
+    // Lowest precedence: logical `or`, then `and` (left to right)
+    for keyword in ["or", "and"] {
+        for (i, atom) in top_level_atoms.iter().enumerate().rev() {
+            if let TopLevelAtomic::Single { index } = atom {
+                if tokens[*index] == Token::Name(keyword.into()) {
+                    let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
+                    let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
+                    let left = Box::new(parse(&left_tokens)?);
+                    let right = Box::new(parse(&right_tokens)?);
+                    return Ok(match keyword {
+                        "or" => Expression::Or(left, right),
+                        _ => Expression::And(left, right),
+                    });
+                }
+            }
+        }
+    }
+
+    // Logical `not` applies to everything after it
+    if tokens[0] == Token::Name("not".into()) {
+        let operand = parse(&tokens[1..])?;
+        return Ok(Expression::Not(Box::new(operand)));
+    }
+
     // Check for function calls: Name followed by parentheses
     if let (
         2,
@@ -64,44 +676,79 @@ pub fn parse(tokens: &[Token]) -> Result<Expression, &'static str> {
         let function_name_token = &tokens[*index];
         if let Token::Name(function_name) = function_name_token {
             let function_parameters_tokens = &tokens[*start_inclusive + 1..*end_inclusive];
-            if function_parameters_tokens.is_empty() {
-                return Ok(Expression::FunctionCall(function_name.clone(), vec![]));
-            }
-
-            let function_level_atoms = group_top_level_items(function_parameters_tokens)?;
-            let parameters: Result<Vec<Expression>, &'static str> = function_level_atoms
-                .split(|a| match a {
-                    &TopLevelAtomic::Single { index } => {
-                        function_parameters_tokens[index] == Token::Comma
-                    }
-                    _ => false,
-                })
-                .map(|function_parameter_atoms| {
-                    let start = function_parameter_atoms[0].start_inclusive();
-                    let end = function_parameter_atoms[function_parameter_atoms.len() - 1]
-                        .end_inclusive();
-                    let tokens_slice = &function_parameters_tokens[start..=end];
-                    parse(tokens_slice)
-                })
-                .collect();
+            let parameters: Result<Vec<Expression>, &'static str> =
+                split_parameters(function_parameters_tokens)?
+                    .into_iter()
+                    .map(parse)
+                    .collect();
 
             return Ok(Expression::FunctionCall(function_name.clone(), parameters?));
         }
     }
 
     // Check for binary operations with proper precedence and associativity
-    
-    // Lowest precedence: + and - (left to right)
+
+    // Comparisons (left to right), where an equation `lhs = rhs` compares like `==`
     for (i, atom) in top_level_atoms.iter().enumerate().rev() {
+        if let TopLevelAtomic::Single { index } = atom {
+            let comparison: BinaryOperator = match &tokens[*index] {
+                Token::Symbol('=') => Expression::Equal,
+                Token::Symbol('<') => Expression::LessThan,
+                Token::Symbol('>') => Expression::GreaterThan,
+                Token::Operator(operator) => match operator.as_str() {
+                    "<=" => Expression::LessThanOrEqual,
+                    ">=" => Expression::GreaterThanOrEqual,
+                    "==" => Expression::Equal,
+                    "!=" => Expression::NotEqual,
+                    "~=" => Expression::ApproximatelyEqual,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
+            let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
+            let left = parse(&left_tokens)?;
+            let right = parse(&right_tokens)?;
+            return Ok(comparison(Box::new(left), Box::new(right)));
+        }
+    }
+
+    // Unit conversion with `in`, unless `in` is used as the inch unit
+    for (i, atom) in top_level_atoms.iter().enumerate().rev() {
+        if let TopLevelAtomic::Single { index } = atom {
+            if tokens[*index] == Token::Name("in".into())
+                && i != 0
+                && i != top_level_atoms.len() - 1
+            {
+                let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
+                let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
+                let left = parse(&left_tokens)?;
+                let right = parse(&right_tokens)?;
+                return Ok(Expression::Conversion(Box::new(left), Box::new(right)));
+            }
+        }
+    }
+
+    // Ranges: `start..end`
+    for (i, atom) in top_level_atoms.iter().enumerate().rev() {
+        if let TopLevelAtomic::Single { index } = atom {
+            if tokens[*index] == Token::Operator("..".into()) {
+                let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
+                let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
+                let left = parse(&left_tokens)?;
+                let right = parse(&right_tokens)?;
+                return Ok(Expression::Range(Box::new(left), Box::new(right)));
+            }
+        }
+    }
+
+    // + and - (left to right)
+    let mut additive: Vec<(usize, BinaryOperator)> = Vec::new();
+    let mut starts_with_minus = false;
+    for (i, atom) in top_level_atoms.iter().enumerate() {
         if let TopLevelAtomic::Single { index } = atom {
             match &tokens[*index] {
-                Token::Symbol('+') => {
-                    let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
-                    let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
-                    let left = parse(&left_tokens)?;
-                    let right = parse(&right_tokens)?;
-                    return Ok(Expression::Addition(Box::new(left), Box::new(right)));
-                }
+                Token::Symbol('+') => additive.push((i, Expression::Addition)),
                 Token::Symbol('-') => {
                     // Improved unary minus detection: at the start, or after an operator or parenthesis
                     let is_unary = if i == 0 {
@@ -110,70 +757,94 @@ pub fn parse(tokens: &[Token]) -> Result<Expression, &'static str> {
                         // Look at previous atom's end token
                         let prev_atom = &top_level_atoms[i - 1];
                         let prev_idx = prev_atom.end_inclusive();
-                        match &tokens[prev_idx] {
-                            Token::Symbol(_) | Token::OpeningParenthesis | Token::Comma => true,
-                            _ => false,
-                        }
+                        matches!(
+                            &tokens[prev_idx],
+                            Token::Symbol('+' | '-' | '*' | '/' | '^' | '=' | '<' | '>')
+                                | Token::Operator(_)
+                                | Token::OpeningParenthesis
+                                | Token::Comma
+                        )
                     };
-                    if is_unary {
-                        let right_atoms = &top_level_atoms[i + 1..];
-                        let right_tokens = extract_tokens_from_atoms(right_atoms, tokens);
-                        let right = parse(&right_tokens)?;
-                        return Ok(Expression::Minus(Box::new(right)));
-                    } else {
-                        let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
-                        let right_tokens =
-                            extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
-                        let left = parse(&left_tokens)?;
-                        let right = parse(&right_tokens)?;
-                        return Ok(Expression::Subtraction(Box::new(left), Box::new(right)));
+                    // Unary minus in an operand, e.g. `2 * -3`, binds tighter than the
+                    // operator before it, so keep looking for a lower precedence split
+                    match is_unary {
+                        true => starts_with_minus |= i == 0,
+                        false => additive.push((i, Expression::Subtraction)),
                     }
                 }
                 _ => {}
             }
         }
     }
+    if !additive.is_empty() {
+        return parse_chain(&top_level_atoms, tokens, &additive, parse);
+    }
+    if starts_with_minus {
+        let right_tokens = extract_tokens_from_atoms(&top_level_atoms[1..], tokens);
+        let right = parse(&right_tokens)?;
+        return Ok(Expression::Minus(Box::new(right)));
+    }
 
-    // Medium precedence: * and / (left to right) 
-    for (i, atom) in top_level_atoms.iter().enumerate().rev() {
-        if let TopLevelAtomic::Single { index } = atom {
-            match &tokens[*index] {
-                Token::Symbol('*') | Token::Symbol('/') => {
-                    let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
-                    // Instead of extracting tokens only from the next atom, always use the full right slice for recursion
-                    let right_start = top_level_atoms.get(i + 1).map(|a| a.start_inclusive()).unwrap_or(tokens.len());
-                    let right_tokens = if right_start < tokens.len() {
-                        tokens[right_start..].to_vec()
-                    } else {
-                        vec![]
-                    };
-                    let left = parse(&left_tokens)?;
-                    let right = parse(&right_tokens)?;
-                    let result = match &tokens[*index] {
-                        Token::Symbol('*') => Expression::Multiplication(Box::new(left), Box::new(right)),
-                        Token::Symbol('/') => Expression::Division(Box::new(left), Box::new(right)),
-                        _ => unreachable!(),
-                    };
-                    return Ok(result);
-                }
-                _ => {}
-            }
+    // Medium precedence: * and / (left to right)
+    let multiplicative: Vec<(usize, BinaryOperator)> = top_level_atoms
+        .iter()
+        .enumerate()
+        .filter_map(|(i, atom)| match atom {
+            TopLevelAtomic::Single { index } => match &tokens[*index] {
+                Token::Symbol('*') => Some((i, Expression::Multiplication as BinaryOperator)),
+                Token::Symbol('/') => Some((i, Expression::Division as BinaryOperator)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    if !multiplicative.is_empty() {
+        return parse_chain(&top_level_atoms, tokens, &multiplicative, parse);
+    }
+
+    // Implicit multiplication of adjacent operands, e.g. `500 Mbit` or `2 (3 + 4)`
+    for i in (1..top_level_atoms.len()).rev() {
+        let previous = &top_level_atoms[i - 1];
+        let current = &top_level_atoms[i];
+        let is_function_call = matches!(
+            (previous, current),
+            (TopLevelAtomic::Single { index }, TopLevelAtomic::ParenthesisGroup { .. })
+                if matches!(tokens[*index], Token::Name(_))
+        );
+        let previous_ends_operand = is_operand(previous, tokens) || is_factorial(previous, tokens);
+        if previous_ends_operand && is_operand(current, tokens) && !is_function_call {
+            let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
+            let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i..], tokens);
+            let left = parse(&left_tokens)?;
+            let right = parse(&right_tokens)?;
+            return Ok(Expression::Multiplication(Box::new(left), Box::new(right)));
         }
     }
 
     // Highest precedence: ^ (right to left - find leftmost operator for right associativity)
     for (i, atom) in top_level_atoms.iter().enumerate() {
         if let TopLevelAtomic::Single { index } = atom {
-            match &tokens[*index] {
-                Token::Symbol('^') => {
-                    let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
-                    let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
-                    let left = parse(&left_tokens)?;
-                    let right = parse(&right_tokens)?;
-                    return Ok(Expression::Exponentiation(Box::new(left), Box::new(right)));
+            if let Token::Symbol('^') = &tokens[*index] {
+                let left_tokens = extract_tokens_from_atoms(&top_level_atoms[..i], tokens);
+                let right_tokens = extract_tokens_from_atoms(&top_level_atoms[i + 1..], tokens);
+                let left = parse(&left_tokens)?;
+                let right = parse(&right_tokens)?;
+                return Ok(Expression::Exponentiation(Box::new(left), Box::new(right)));
+            }
+        }
+    }
+
+    // Postfix factorials bind tightest, so `2^3!` is `2^(3!)` and `-3!` is `-(3!)`
+    if let [operand @ .., last] = top_level_atoms.as_slice() {
+        if is_factorial(last, tokens) {
+            if let [operand @ .., second_last] = operand {
+                if is_factorial(second_last, tokens) {
+                    let operand = parse(&extract_tokens_from_atoms(operand, tokens))?;
+                    return Ok(Expression::DoubleFactorial(Box::new(operand)));
                 }
-                _ => {}
             }
+            let operand = parse(&extract_tokens_from_atoms(operand, tokens))?;
+            return Ok(Expression::Factorial(Box::new(operand)));
         }
     }
 
@@ -186,6 +857,9 @@ impl TopLevelAtomic {
             TopLevelAtomic::Single { index } => *index,
             TopLevelAtomic::ParenthesisGroup {
                 start_inclusive, ..
+            }
+            | TopLevelAtomic::BracketGroup {
+                start_inclusive, ..
             } => *start_inclusive,
         }
     }
@@ -193,54 +867,82 @@ impl TopLevelAtomic {
     fn end_inclusive(&self) -> usize {
         match self {
             TopLevelAtomic::Single { index } => *index,
-            TopLevelAtomic::ParenthesisGroup { end_inclusive, .. } => *end_inclusive,
+            TopLevelAtomic::ParenthesisGroup { end_inclusive, .. }
+            | TopLevelAtomic::BracketGroup { end_inclusive, .. } => *end_inclusive,
         }
     }
 }
 
 fn group_top_level_items(tokens: &[Token]) -> Result<Vec<TopLevelAtomic>, &'static str> {
     let mut top_level_atoms: Vec<TopLevelAtomic> = vec![];
-    let mut parenthesis_level = 0;
+    // Parentheses and brackets that are not closed yet, innermost last
+    let mut open_groups: Vec<&Token> = vec![];
 
-    let mut parenthesis_start = 0;
+    let mut group_start = 0;
 
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..tokens.len() {
-        let token = &tokens[i];
+    for (i, token) in tokens.iter().enumerate() {
         match token {
-            Token::OpeningParenthesis => {
-                if parenthesis_level == 0 {
-                    parenthesis_start = i;
+            Token::OpeningParenthesis | Token::OpeningBracket => {
+                if open_groups.is_empty() {
+                    group_start = i;
                 }
-                parenthesis_level += 1;
+                open_groups.push(token);
             }
-            Token::ClosingParenthesis => {
-                if parenthesis_level == 0 {
-                    return Err("Unexpected closing parenthesis.");
+            Token::ClosingParenthesis | Token::ClosingBracket => {
+                let opening = match token {
+                    Token::ClosingParenthesis => Token::OpeningParenthesis,
+                    _ => Token::OpeningBracket,
+                };
+                match open_groups.pop() {
+                    None => return Err("Unexpected closing parenthesis."),
+                    Some(open) if *open != opening => return Err("Mismatched parenthesis"),
+                    Some(_) => {}
                 }
-                parenthesis_level -= 1;
-                if parenthesis_level == 0 {
-                    let new_group = TopLevelAtomic::ParenthesisGroup {
-                        start_inclusive: parenthesis_start,
-                        end_inclusive: i,
+                if open_groups.is_empty() {
+                    let new_group = match token {
+                        Token::ClosingParenthesis => TopLevelAtomic::ParenthesisGroup {
+                            start_inclusive: group_start,
+                            end_inclusive: i,
+                        },
+                        _ => TopLevelAtomic::BracketGroup {
+                            start_inclusive: group_start,
+                            end_inclusive: i,
+                        },
                     };
                     top_level_atoms.push(new_group)
                 }
             }
             _ => {
-                if parenthesis_level == 0 {
+                if open_groups.is_empty() {
                     top_level_atoms.push(TopLevelAtomic::Single { index: i });
                 }
             }
         }
     }
-    if parenthesis_level != 0 {
+    if !open_groups.is_empty() {
         return Err("Mismatched parenthesis");
     }
 
     Ok(top_level_atoms)
 }
 
+/// Parses the operands between left-associative operators of one precedence in turn,
+/// so that a chain like `1 + 2 + 3` nests no deeper however long it is
+fn parse_chain(
+    atoms: &[TopLevelAtomic],
+    tokens: &[Token],
+    operators: &[(usize, BinaryOperator)],
+    parse: impl Fn(&[Token]) -> Result<Expression, &'static str>,
+) -> Result<Expression, &'static str> {
+    let mut result = parse(&extract_tokens_from_atoms(&atoms[..operators[0].0], tokens))?;
+    for (k, &(i, operator)) in operators.iter().enumerate() {
+        let end = operators.get(k + 1).map_or(atoms.len(), |&(next, _)| next);
+        let right = parse(&extract_tokens_from_atoms(&atoms[i + 1..end], tokens))?;
+        result = operator(Box::new(result), Box::new(right));
+    }
+    Ok(result)
+}
+
 fn extract_tokens_from_atoms(atoms: &[TopLevelAtomic], tokens: &[Token]) -> Vec<Token> {
     if atoms.is_empty() {
         return vec![];
@@ -251,9 +953,151 @@ fn extract_tokens_from_atoms(atoms: &[TopLevelAtomic], tokens: &[Token]) -> Vec<
     tokens[start..=end].to_vec()
 }
 
+/// Splits the tokens between a function call's parentheses at top level commas
+fn split_parameters(tokens: &[Token]) -> Result<Vec<&[Token]>, &'static str> {
+    if tokens.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let atoms = group_top_level_items(tokens)?;
+    atoms
+        .split(|atom| match atom {
+            &TopLevelAtomic::Single { index } => tokens[index] == Token::Comma,
+            _ => false,
+        })
+        .map(|parameter_atoms| {
+            let (Some(first), Some(last)) = (parameter_atoms.first(), parameter_atoms.last())
+            else {
+                return Err("Empty function parameter");
+            };
+            Ok(&tokens[first.start_inclusive()..=last.end_inclusive()])
+        })
+        .collect()
+}
+
+fn is_operand(atom: &TopLevelAtomic, tokens: &[Token]) -> bool {
+    match atom {
+        TopLevelAtomic::Single { index } => {
+            matches!(tokens[*index], Token::NumericLiteral(_) | Token::Name(_))
+        }
+        TopLevelAtomic::ParenthesisGroup { .. } | TopLevelAtomic::BracketGroup { .. } => true,
+    }
+}
+
+fn is_factorial(atom: &TopLevelAtomic, tokens: &[Token]) -> bool {
+    matches!(atom, TopLevelAtomic::Single { index } if tokens[*index] == Token::Symbol('!'))
+}
+
 fn start_and_ends_with_parenthesis(tokens: &[Token]) -> bool {
     let Some(last) = tokens.last() else {
         return false;
     };
-    tokens[0] == Token::OpeningParenthesis && *last == Token::ClosingParenthesis
+    if tokens[0] != Token::OpeningParenthesis || *last != Token::ClosingParenthesis {
+        return false;
+    }
+    // `(1 + 2) * (3)` starts and ends with parentheses, but they are not a single group
+    matches!(
+        group_top_level_items(tokens).as_deref(),
+        Ok([TopLevelAtomic::ParenthesisGroup { .. }])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{lexer, strategies};
+
+    fn parse_str(input: &str) -> Expression {
+        parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_display_minimal_parentheses() {
+        let cases = [
+            ("(1 + 2) + 3", "1 + 2 + 3"),
+            ("1 + (2 + 3)", "1 + (2 + 3)"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("(2 * 3) / 4", "2 * 3 / 4"),
+            ("2 / (3 * 4)", "2 / (3 * 4)"),
+            ("2 ^ 3 ^ 2", "2^3^2"),
+            ("(2 ^ 3) ^ 2", "(2^3)^2"),
+            ("-(2 * 3)", "-2 * 3"),
+            ("(-2) * 3", "(-2) * 3"),
+            ("-(2 + 3)", "-(2 + 3)"),
+            ("2 * -3", "2 * (-3)"),
+            ("-x^2", "-x^2"),
+            ("(-x)^2", "(-x)^2"),
+            ("500 Mbit/s * 2 h in GB", "500 Mbit / s * 2 h in GB"),
+            ("a / 2 x", "a / 2 x"),
+            ("(2 x)^2", "(2 x)^2"),
+            ("(2n)!", "(2 n)!"),
+            ("(3!)!", "(3!)!"),
+            ("7!!", "7!!"),
+            ("not (a and b) or c < 1", "not (a and b) or c < 1"),
+            ("not a < b", "not a < b"),
+            ("a or (b or c)", "a or (b or c)"),
+            (
+                "f(x, y) = x^2 + max([1, 2], y)",
+                "f(x, y) = x^2 + max([1, 2], y)",
+            ),
+            ("unit furlong = 201.168 m", "unit furlong = 201.168 m"),
+            ("1..n + 1", "1..n + 1"),
+            ("2 in", "2 * (in)"),
+            ("1 ft in in", "1 ft in (in)"),
+            (
+                "Monthly rent: (1200) # due on the 1st",
+                "Monthly rent: 1200",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse_str(input).to_string(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let inputs = [
+            "2 * (23 - 2.5 *2) ^2 /2 *3 + log(231)",
+            "if(x > 0 and not y, sum(k, 1, 10, k^2), -1)",
+            "piecewise(x <= 1, 0.1 x, x <= 4, 1 + 0.2 (x - 1), 2)",
+            "1.5 TB in GiB",
+            "x = 3 km / 2 h",
+            "sqrt(2)^2 ~= 2 == true",
+            "a - -b - (c - d)",
+            "2^-x^y",
+            "[[1, 2], [3, 4]]",
+            "0.1 + 100000000000000000000 * 0.000001",
+            "Area of the room: x = 3 m * 4 m",
+        ];
+        for input in inputs {
+            let expression = parse_str(input);
+            assert_eq!(parse_str(&expression.to_string()), expression, "{}", input);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_round_trip(expression in strategies::expression()) {
+            let displayed = expression.to_string();
+            let tokens = lexer::lex(&displayed).map_err(|_| "Invalid character");
+            prop_assert_eq!(tokens.and_then(|tokens| parse(&tokens)), Ok(expression), "{}", displayed);
+        }
+
+        #[test]
+        fn test_literal_round_trip(digits in 1usize..400) {
+            // Literals too large for a number are rejected rather than printed as `inf`
+            let parse_literal = |literal: &str| parse(&lexer::lex(literal).unwrap());
+            match parse_literal(&"9".repeat(digits)) {
+                Ok(expression) => {
+                    prop_assert_eq!(parse_literal(&expression.to_string()), Ok(expression));
+                }
+                Err(error) => {
+                    prop_assert!(digits >= 309);
+                    prop_assert_eq!(error, "Invalid number");
+                }
+            }
+        }
+    }
 }
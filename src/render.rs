@@ -0,0 +1,601 @@
+//! Typeset rendering of expressions as LaTeX and MathML, e.g. for documentation
+//!
+//! Expressions are first laid out as a tree of `Node`s, which both notations then
+//! write out, so they always agree on structure and parentheses. Trees taller than the
+//! default [`Limits::max_depth`] fail to render rather than overflow the stack.
+
+use crate::limits::{self, Limits};
+use crate::parser::{is_juxtaposition, precedence, Expression};
+
+const MATHML_NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+
+/// Functions LaTeX has a command for, e.g. `\sin`
+const LATEX_FUNCTIONS: [&str; 20] = [
+    "sin", "cos", "tan", "sec", "csc", "cot", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh",
+    "ln", "log", "exp", "min", "max", "gcd", "det", "deg",
+];
+
+const GREEK_LETTERS: [(&str, &str); 14] = [
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("theta", "θ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("phi", "φ"),
+    ("omega", "ω"),
+];
+
+impl Expression {
+    /// The expression as LaTeX math, e.g. `\frac{1}{2} \cdot x^{2}` for `1/2 * x^2`
+    pub fn to_latex(&self) -> Result<String, &'static str> {
+        Ok(checked_layout(self)?.to_latex())
+    }
+
+    /// The expression as a MathML `<math>` element
+    pub fn to_mathml(&self) -> Result<String, &'static str> {
+        Ok(format!(
+            "<math xmlns=\"{}\">{}</math>",
+            MATHML_NAMESPACE,
+            checked_layout(self)?.to_mathml()
+        ))
+    }
+}
+
+/// Lays out an expression no taller than the default depth limit
+fn checked_layout(expression: &Expression) -> Result<Node, &'static str> {
+    if expression.height() > Limits::default().max_depth {
+        return Err(limits::TOO_DEEP);
+    }
+    Ok(layout(expression))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Operator {
+    latex: &'static str,
+    mathml: &'static str,
+}
+
+const fn operator(latex: &'static str, mathml: &'static str) -> Operator {
+    Operator { latex, mathml }
+}
+
+const PLUS: Operator = operator("+", "+");
+const MINUS: Operator = operator("-", "\u{2212}");
+const TIMES: Operator = operator("\\cdot", "\u{22c5}");
+/// Implicit multiplication, e.g. `2 km`
+const INVISIBLE_TIMES: Operator = operator("\\,", "\u{2062}");
+const COMMA: Operator = operator(",", ",");
+const EQUALS: Operator = operator("=", "=");
+
+/// Typeset structure shared by both notations
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Number(f64),
+    Identifier(String),
+    /// A function name, set upright
+    Function(String),
+    Text(String),
+    Operator(Operator),
+    Row(Vec<Node>),
+    Fenced(Operator, Box<Node>, Operator),
+    Fraction(Box<Node>, Box<Node>),
+    Power(Box<Node>, Box<Node>),
+    Subscript(Box<Node>, Box<Node>),
+    Sqrt(Box<Node>),
+    Root(Box<Node>, Box<Node>),
+    /// A large operator such as a sum, with its lower and upper limits
+    BigOperator(Operator, Box<Node>, Box<Node>),
+    /// Values with their conditions, `None` for the fallback value
+    Cases(Vec<(Node, Option<Node>)>),
+    Matrix(Vec<Vec<Node>>),
+}
+
+fn row(nodes: impl IntoIterator<Item = Node>) -> Node {
+    Node::Row(nodes.into_iter().collect())
+}
+
+fn parenthesized(node: Node) -> Node {
+    Node::Fenced(operator("(", "("), Box::new(node), operator(")", ")"))
+}
+
+/// The nodes separated by commas
+fn comma_separated(nodes: impl IntoIterator<Item = Node>) -> Node {
+    let mut separated = vec![];
+    for node in nodes {
+        if !separated.is_empty() {
+            separated.push(Node::Operator(COMMA));
+        }
+        separated.push(node);
+    }
+    Node::Row(separated)
+}
+
+/// Like [`Expression::precedence`], except that fractions and other two-dimensional
+/// layouts need no parentheses around them as operands of `*` or `-`
+fn visual_precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Division(..) => precedence::POWER,
+        Expression::Exponentiation(..) => precedence::POWER,
+        Expression::FunctionCall(name, args) if name == "sqrt" || name == "cbrt" => {
+            if args.len() == 1 {
+                precedence::ATOM
+            } else {
+                expression.precedence()
+            }
+        }
+        _ => expression.precedence(),
+    }
+}
+
+/// Lays out an operand, in parentheses when it binds looser than `minimum`
+fn operand(expression: &Expression, minimum: u8) -> Node {
+    if visual_precedence(expression) < minimum {
+        parenthesized(layout(expression))
+    } else {
+        layout(expression)
+    }
+}
+
+fn binary(left: &Expression, operator: Operator, right: &Expression, precedence: u8) -> Node {
+    row([
+        operand(left, precedence),
+        Node::Operator(operator),
+        operand(right, precedence + 1),
+    ])
+}
+
+fn layout(expression: &Expression) -> Node {
+    use precedence::*;
+
+    match expression {
+        Expression::NumericLiteral(value) if value.is_sign_negative() => {
+            row([Node::Operator(MINUS), Node::Number(-value)])
+        }
+        Expression::NumericLiteral(value) => Node::Number(*value),
+        Expression::Name(name) => Node::Identifier(name.clone()),
+        Expression::BooleanLiteral(value) => Node::Text(value.to_string()),
+        Expression::Minus(value) => row([Node::Operator(MINUS), operand(value, MULTIPLICATIVE)]),
+        Expression::Not(value) => row([
+            Node::Operator(operator("\\lnot", "\u{ac}")),
+            operand(value, COMPARISON),
+        ]),
+        Expression::Factorial(value) => {
+            row([operand(value, ATOM), Node::Operator(operator("!", "!"))])
+        }
+        Expression::DoubleFactorial(value) => {
+            row([operand(value, ATOM), Node::Operator(operator("!!", "!!"))])
+        }
+        Expression::Addition(left, right) => binary(left, PLUS, right, ADDITIVE),
+        Expression::Subtraction(left, right) => binary(left, MINUS, right, ADDITIVE),
+        Expression::Multiplication(left, right) if is_juxtaposition(left, right) => {
+            row([layout(left), Node::Operator(INVISIBLE_TIMES), layout(right)])
+        }
+        Expression::Multiplication(left, right) => binary(left, TIMES, right, MULTIPLICATIVE),
+        Expression::Division(numerator, denominator) => {
+            Node::Fraction(Box::new(layout(numerator)), Box::new(layout(denominator)))
+        }
+        Expression::Exponentiation(base, exponent) => Node::Power(
+            Box::new(operand(base, POWER + 1)),
+            Box::new(layout(exponent)),
+        ),
+        Expression::Conversion(value, unit) => {
+            binary(value, operator("\\to", "\u{2192}"), unit, CONVERSION)
+        }
+        Expression::Range(start, end) => binary(start, operator("\\ldots", "\u{2026}"), end, RANGE),
+        Expression::LessThan(left, right) => binary(left, operator("<", "<"), right, COMPARISON),
+        Expression::LessThanOrEqual(left, right) => {
+            binary(left, operator("\\le", "\u{2264}"), right, COMPARISON)
+        }
+        Expression::GreaterThan(left, right) => binary(left, operator(">", ">"), right, COMPARISON),
+        Expression::GreaterThanOrEqual(left, right) => {
+            binary(left, operator("\\ge", "\u{2265}"), right, COMPARISON)
+        }
+        Expression::Equal(left, right) => binary(left, EQUALS, right, COMPARISON),
+        Expression::NotEqual(left, right) => {
+            binary(left, operator("\\ne", "\u{2260}"), right, COMPARISON)
+        }
+        Expression::ApproximatelyEqual(left, right) => {
+            binary(left, operator("\\approx", "\u{2248}"), right, COMPARISON)
+        }
+        Expression::And(left, right) => binary(left, operator("\\land", "\u{2227}"), right, AND),
+        Expression::Or(left, right) => binary(left, operator("\\lor", "\u{2228}"), right, OR),
+        Expression::FunctionCall(name, args) => layout_call(name, args),
+        Expression::List(elements) => layout_list(elements),
+        Expression::UnitDefinition(name, definition) => row([
+            Node::Text("unit".into()),
+            Node::Identifier(name.clone()),
+            Node::Operator(EQUALS),
+            layout(definition),
+        ]),
+        Expression::Assignment(name, value) => row([
+            Node::Identifier(name.clone()),
+            Node::Operator(EQUALS),
+            layout(value),
+        ]),
+        Expression::FunctionDefinition(name, parameters, body) => row([
+            Node::Function(name.clone()),
+            parenthesized(comma_separated(
+                parameters.iter().cloned().map(Node::Identifier),
+            )),
+            Node::Operator(EQUALS),
+            layout(body),
+        ]),
+        Expression::Labeled(label, expression) => {
+            row([Node::Text(format!("{}:", label)), layout(expression)])
+        }
+    }
+}
+
+fn layout_call(name: &str, args: &[Expression]) -> Node {
+    let fenced =
+        |open, close, argument: &Expression| Node::Fenced(open, Box::new(layout(argument)), close);
+    match (name, args) {
+        ("sqrt", [argument]) => Node::Sqrt(Box::new(layout(argument))),
+        ("cbrt", [argument]) => Node::Root(Box::new(layout(argument)), Box::new(Node::Number(3.0))),
+        ("exp", [argument]) => Node::Power(
+            Box::new(Node::Identifier("e".into())),
+            Box::new(layout(argument)),
+        ),
+        ("abs", [argument]) => fenced(operator("|", "|"), operator("|", "|"), argument),
+        ("floor", [argument]) => fenced(
+            operator("\\lfloor", "\u{230a}"),
+            operator("\\rfloor", "\u{230b}"),
+            argument,
+        ),
+        ("ceil", [argument]) => fenced(
+            operator("\\lceil", "\u{2308}"),
+            operator("\\rceil", "\u{2309}"),
+            argument,
+        ),
+        // The natural logarithm, unless a base is given
+        ("log" | "ln", [argument]) => application(Node::Function("ln".into()), argument),
+        ("log", [base, argument]) => application(
+            Node::Subscript(
+                Box::new(Node::Function("log".into())),
+                Box::new(layout(base)),
+            ),
+            argument,
+        ),
+        ("log2" | "log10", [argument]) => application(
+            Node::Subscript(
+                Box::new(Node::Function("log".into())),
+                Box::new(Node::Number(if name == "log2" { 2.0 } else { 10.0 })),
+            ),
+            argument,
+        ),
+        ("sum" | "prod", [Expression::Name(variable), start, end, body]) => {
+            let symbol = match name {
+                "sum" => operator("\\sum", "\u{2211}"),
+                _ => operator("\\prod", "\u{220f}"),
+            };
+            let lower = row([
+                Node::Identifier(variable.clone()),
+                Node::Operator(EQUALS),
+                layout(start),
+            ]);
+            row([
+                Node::BigOperator(symbol, Box::new(lower), Box::new(layout(end))),
+                operand(body, precedence::MULTIPLICATIVE),
+            ])
+        }
+        ("if", [condition, then, otherwise]) => Node::Cases(vec![
+            (layout(then), Some(layout(condition))),
+            (layout(otherwise), None),
+        ]),
+        ("piecewise", _) => Node::Cases(
+            args.chunks(2)
+                .map(|piece| match piece {
+                    [condition, value] => (layout(value), Some(layout(condition))),
+                    [otherwise] => (layout(otherwise), None),
+                    _ => unreachable!(),
+                })
+                .collect(),
+        ),
+        _ => {
+            let name = match name {
+                "asin" | "acos" | "atan" => format!("arc{}", &name[1..]),
+                _ => name.to_string(),
+            };
+            row([
+                Node::Function(name),
+                parenthesized(comma_separated(args.iter().map(layout))),
+            ])
+        }
+    }
+}
+
+/// A function applied to a single argument in parentheses
+fn application(function: Node, argument: &Expression) -> Node {
+    row([function, parenthesized(layout(argument))])
+}
+
+/// Lists of equally long lists are matrices, other lists are written in brackets
+fn layout_list(elements: &[Expression]) -> Node {
+    let rows: Option<Vec<&Vec<Expression>>> = elements
+        .iter()
+        .map(|element| match element {
+            Expression::List(row) if !row.is_empty() => Some(row),
+            _ => None,
+        })
+        .collect();
+    match rows {
+        Some(rows) if !rows.is_empty() && rows.iter().all(|row| row.len() == rows[0].len()) => {
+            Node::Matrix(
+                rows.into_iter()
+                    .map(|row| row.iter().map(layout).collect())
+                    .collect(),
+            )
+        }
+        _ => Node::Fenced(
+            operator("[", "["),
+            Box::new(comma_separated(elements.iter().map(layout))),
+            operator("]", "]"),
+        ),
+    }
+}
+
+impl Node {
+    fn to_latex(&self) -> String {
+        match self {
+            Node::Number(value) => value.to_string(),
+            Node::Identifier(name) => match GREEK_LETTERS.iter().find(|(greek, _)| greek == name) {
+                Some(_) => format!("\\{}", name),
+                None if name.chars().count() == 1 => name.clone(),
+                None => format!("\\mathrm{{{}}}", escape_latex(name)),
+            },
+            Node::Function(name) if LATEX_FUNCTIONS.contains(&name.as_str()) => {
+                format!("\\{}", name)
+            }
+            Node::Function(name) => format!("\\operatorname{{{}}}", escape_latex(name)),
+            Node::Text(text) => format!("\\text{{{}}}", escape_latex(text)),
+            Node::Operator(operator) => operator.latex.to_string(),
+            Node::Row(nodes) => nodes
+                .iter()
+                .map(Node::to_latex)
+                .collect::<Vec<_>>()
+                .join(" "),
+            Node::Fenced(open, inner, close) => format!(
+                "\\left{} {} \\right{}",
+                open.latex,
+                inner.to_latex(),
+                close.latex
+            ),
+            Node::Fraction(numerator, denominator) => format!(
+                "\\frac{{{}}}{{{}}}",
+                numerator.to_latex(),
+                denominator.to_latex()
+            ),
+            Node::Power(base, exponent) => {
+                format!("{}^{{{}}}", base.to_latex(), exponent.to_latex())
+            }
+            Node::Subscript(base, subscript) => {
+                format!("{}_{{{}}}", base.to_latex(), subscript.to_latex())
+            }
+            Node::Sqrt(radicand) => format!("\\sqrt{{{}}}", radicand.to_latex()),
+            Node::Root(radicand, index) => {
+                format!("\\sqrt[{}]{{{}}}", index.to_latex(), radicand.to_latex())
+            }
+            Node::BigOperator(symbol, lower, upper) => format!(
+                "{}_{{{}}}^{{{}}}",
+                symbol.latex,
+                lower.to_latex(),
+                upper.to_latex()
+            ),
+            Node::Cases(cases) => {
+                let cases = cases
+                    .iter()
+                    .map(|(value, condition)| match condition {
+                        Some(condition) => {
+                            format!(
+                                "{} & \\text{{if }} {}",
+                                value.to_latex(),
+                                condition.to_latex()
+                            )
+                        }
+                        None => format!("{} & \\text{{otherwise}}", value.to_latex()),
+                    })
+                    .collect::<Vec<_>>();
+                format!("\\begin{{cases}} {} \\end{{cases}}", cases.join(" \\\\ "))
+            }
+            Node::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(Node::to_latex)
+                            .collect::<Vec<_>>()
+                            .join(" & ")
+                    })
+                    .collect::<Vec<_>>();
+                format!(
+                    "\\begin{{bmatrix}} {} \\end{{bmatrix}}",
+                    rows.join(" \\\\ ")
+                )
+            }
+        }
+    }
+
+    fn to_mathml(&self) -> String {
+        match self {
+            Node::Number(value) => format!("<mn>{}</mn>", value),
+            Node::Identifier(name) => match GREEK_LETTERS.iter().find(|(greek, _)| greek == name) {
+                Some((_, letter)) => format!("<mi>{}</mi>", letter),
+                None => format!("<mi>{}</mi>", escape_xml(name)),
+            },
+            Node::Function(name) => format!("<mi>{}</mi>", escape_xml(name)),
+            Node::Text(text) => format!("<mtext>{}</mtext>", escape_xml(text)),
+            Node::Operator(operator) => format!("<mo>{}</mo>", escape_xml(operator.mathml)),
+            Node::Row(nodes) => format!(
+                "<mrow>{}</mrow>",
+                nodes.iter().map(Node::to_mathml).collect::<String>()
+            ),
+            Node::Fenced(open, inner, close) => format!(
+                "<mrow><mo>{}</mo>{}<mo>{}</mo></mrow>",
+                escape_xml(open.mathml),
+                inner.to_mathml(),
+                escape_xml(close.mathml)
+            ),
+            Node::Fraction(numerator, denominator) => format!(
+                "<mfrac>{}{}</mfrac>",
+                numerator.to_mathml(),
+                denominator.to_mathml()
+            ),
+            Node::Power(base, exponent) => {
+                format!("<msup>{}{}</msup>", base.to_mathml(), exponent.to_mathml())
+            }
+            Node::Subscript(base, subscript) => {
+                format!("<msub>{}{}</msub>", base.to_mathml(), subscript.to_mathml())
+            }
+            Node::Sqrt(radicand) => format!("<msqrt>{}</msqrt>", radicand.to_mathml()),
+            Node::Root(radicand, index) => {
+                format!(
+                    "<mroot>{}{}</mroot>",
+                    radicand.to_mathml(),
+                    index.to_mathml()
+                )
+            }
+            Node::BigOperator(symbol, lower, upper) => format!(
+                "<munderover><mo>{}</mo>{}{}</munderover>",
+                symbol.mathml,
+                lower.to_mathml(),
+                upper.to_mathml()
+            ),
+            Node::Cases(cases) => {
+                let rows = cases
+                    .iter()
+                    .map(|(value, condition)| {
+                        let condition = match condition {
+                            Some(condition) => {
+                                format!("<mtext>if\u{a0}</mtext>{}", condition.to_mathml())
+                            }
+                            None => "<mtext>otherwise</mtext>".to_string(),
+                        };
+                        format!(
+                            "<mtr><mtd>{}</mtd><mtd>{}</mtd></mtr>",
+                            value.to_mathml(),
+                            condition
+                        )
+                    })
+                    .collect::<String>();
+                format!("<mrow><mo>{{</mo><mtable>{}</mtable></mrow>", rows)
+            }
+            Node::Matrix(rows) => {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        let cells = row
+                            .iter()
+                            .map(|cell| format!("<mtd>{}</mtd>", cell.to_mathml()))
+                            .collect::<String>();
+                        format!("<mtr>{}</mtr>", cells)
+                    })
+                    .collect::<String>();
+                format!("<mrow><mo>[</mo><mtable>{}</mtable><mo>]</mo></mrow>", rows)
+            }
+        }
+    }
+}
+
+/// Escapes the characters LaTeX treats specially, as labels are free text
+fn escape_latex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '{' | '}' | '$' | '%' | '#' | '&' | '_' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limits::TOO_DEEP;
+    use crate::parser::Expression;
+    use crate::{lexer, parser};
+
+    fn parse(input: &str) -> Expression {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_latex() {
+        let cases = [
+            ("1 / 2 * x^2", "\\frac{1}{2} \\cdot x^{2}"),
+            ("(a + b) / (c - d)", "\\frac{a + b}{c - d}"),
+            ("(1 / x)^2", "\\left( \\frac{1}{x} \\right)^{2}"),
+            ("-(a + b) * c", "- \\left( a + b \\right) \\cdot c"),
+            ("sqrt(x^2 + 1) + cbrt(8)", "\\sqrt{x^{2} + 1} + \\sqrt[3]{8}"),
+            ("log(2, x) + log(x)", "\\log_{2} \\left( x \\right) + \\ln \\left( x \\right)"),
+            ("abs(x) + asin(x)", "\\left| x \\right| + \\arcsin \\left( x \\right)"),
+            ("2 pi r", "2 \\, \\pi \\cdot r"),
+            ("500 Mbit in GB", "500 \\, \\mathrm{Mbit} \\to \\mathrm{GB}"),
+            ("x <= 1 and not y", "x \\le 1 \\land \\lnot y"),
+            ("sum(k, 1, n, k^2)", "\\sum_{k = 1}^{n} k^{2}"),
+            (
+                "if(x > 0, x, -x)",
+                "\\begin{cases} x & \\text{if } x > 0 \\\\ - x & \\text{otherwise} \\end{cases}",
+            ),
+            (
+                "[[1, 2], [3, 4]]",
+                "\\begin{bmatrix} 1 & 2 \\\\ 3 & 4 \\end{bmatrix}",
+            ),
+            (
+                "Rent (% of income, $): 1200",
+                "\\text{Rent (\\% of income, \\$):} 1200",
+            ),
+            ("rack_unit(n) = choose(n, 2)", "\\operatorname{rack\\_unit} \\left( n \\right) = \\operatorname{choose} \\left( n , 2 \\right)"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(input).to_latex().unwrap(), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_mathml() {
+        assert_eq!(
+            parse("x^2 / 2").to_mathml().unwrap(),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">\
+             <mfrac><msup><mi>x</mi><mn>2</mn></msup><mn>2</mn></mfrac></math>"
+        );
+        assert_eq!(
+            parse("sqrt(a) < b").to_mathml().unwrap(),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">\
+             <mrow><msqrt><mi>a</mi></msqrt><mo>&lt;</mo><mi>b</mi></mrow></math>"
+        );
+        assert_eq!(
+            parse("log(b, x)").to_mathml().unwrap(),
+            "<math xmlns=\"http://www.w3.org/1998/Math/MathML\">\
+             <mrow><msub><mi>log</mi><mi>b</mi></msub>\
+             <mrow><mo>(</mo><mi>x</mi><mo>)</mo></mrow></mrow></math>"
+        );
+    }
+
+    #[test]
+    fn test_too_deep() {
+        let chain = parse(&vec!["x"; 3000].join(" + "));
+        assert_eq!(chain.to_latex(), Err(TOO_DEEP));
+        assert_eq!(chain.to_mathml(), Err(TOO_DEEP));
+        let chain = parse(&vec!["x"; 50].join(" + "));
+        assert!(chain.to_latex().unwrap().starts_with("x + x"));
+    }
+}
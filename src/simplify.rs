@@ -0,0 +1,471 @@
+//! Algebraic simplification of expression trees, e.g. after [`crate::derivative::derive`]
+//!
+//! Simplification assumes that the expression is defined, i.e. that names stand for
+//! finite numbers for which every step evaluates. Where the expression evaluates to a
+//! finite number, its simplification evaluates to the same, but the simplification
+//! may also have a value where the expression fails or is NaN: `x / x` and `x^2 / x`
+//! become `1` and `x` although they divide by zero at `x = 0`, and `(x^0.5)^2` and
+//! `x^0.5 * x^0.5` become `x` although they are NaN for negative `x`.
+//!
+//! The rules recurse through every operand, so only expressions no taller than
+//! [`Limits::max_depth`] are simplified.
+
+use crate::limits::{self, Limits};
+use crate::parser::Expression;
+
+/// Passes are repeated until nothing changes, up to this many times
+const MAX_PASSES: usize = 16;
+
+/// An equivalent, usually smaller expression with constants folded, identities such
+/// as `x + 0` and `x * 1` removed, like terms such as `2 x + 3 x` collected, and
+/// powers of the same base merged
+pub fn simplify(expression: &Expression) -> Result<Expression, &'static str> {
+    simplify_with_limits(expression, &Limits::default())
+}
+
+/// Simplifies like [`simplify`], failing when the expression, or a step of its
+/// simplification, is taller than the limits allow
+pub fn simplify_with_limits(
+    expression: &Expression,
+    limits: &Limits,
+) -> Result<Expression, &'static str> {
+    let mut current = expression.clone();
+    for _ in 0..MAX_PASSES {
+        // Collecting terms flattens sums and products, which may make them taller
+        if current.height() > limits.max_depth {
+            return Err(limits::TOO_DEEP);
+        }
+        let next = simplify_once(&current);
+        if next == current {
+            break;
+        }
+        current = next;
+    }
+    Ok(current)
+}
+
+fn simplify_once(expression: &Expression) -> Expression {
+    let expression = map_children(expression, simplify_once);
+    match &expression {
+        Expression::Addition(..) | Expression::Subtraction(..) | Expression::Minus(_) => {
+            simplify_sum(&expression).unwrap_or(expression)
+        }
+        Expression::Multiplication(..) | Expression::Division(..) => Product::of(&expression)
+            .filter(|product| product.coefficient.is_finite())
+            .map(Product::into_expression)
+            .unwrap_or(expression),
+        Expression::Exponentiation(base, exponent) => {
+            simplify_power(base, exponent).unwrap_or(expression)
+        }
+        Expression::Not(operand) => match **operand {
+            Expression::BooleanLiteral(value) => Expression::BooleanLiteral(!value),
+            _ => expression,
+        },
+        _ => expression,
+    }
+}
+
+/// The expression with `simplify` applied to each of its direct subexpressions
+fn map_children(expression: &Expression, simplify: fn(&Expression) -> Expression) -> Expression {
+    let s = |operand: &Expression| Box::new(simplify(operand));
+    match expression {
+        Expression::NumericLiteral(_) | Expression::Name(_) | Expression::BooleanLiteral(_) => {
+            expression.clone()
+        }
+        Expression::Minus(operand) => Expression::Minus(s(operand)),
+        Expression::Not(operand) => Expression::Not(s(operand)),
+        Expression::Factorial(operand) => Expression::Factorial(s(operand)),
+        Expression::DoubleFactorial(operand) => Expression::DoubleFactorial(s(operand)),
+        Expression::Subtraction(l, r) => Expression::Subtraction(s(l), s(r)),
+        Expression::Addition(l, r) => Expression::Addition(s(l), s(r)),
+        Expression::Multiplication(l, r) => Expression::Multiplication(s(l), s(r)),
+        Expression::Division(l, r) => Expression::Division(s(l), s(r)),
+        Expression::Exponentiation(l, r) => Expression::Exponentiation(s(l), s(r)),
+        Expression::Conversion(l, r) => Expression::Conversion(s(l), s(r)),
+        Expression::LessThan(l, r) => Expression::LessThan(s(l), s(r)),
+        Expression::LessThanOrEqual(l, r) => Expression::LessThanOrEqual(s(l), s(r)),
+        Expression::GreaterThan(l, r) => Expression::GreaterThan(s(l), s(r)),
+        Expression::GreaterThanOrEqual(l, r) => Expression::GreaterThanOrEqual(s(l), s(r)),
+        Expression::Equal(l, r) => Expression::Equal(s(l), s(r)),
+        Expression::NotEqual(l, r) => Expression::NotEqual(s(l), s(r)),
+        Expression::ApproximatelyEqual(l, r) => Expression::ApproximatelyEqual(s(l), s(r)),
+        Expression::And(l, r) => Expression::And(s(l), s(r)),
+        Expression::Or(l, r) => Expression::Or(s(l), s(r)),
+        Expression::Range(l, r) => Expression::Range(s(l), s(r)),
+        Expression::FunctionCall(name, args) => {
+            Expression::FunctionCall(name.clone(), args.iter().map(simplify).collect())
+        }
+        Expression::List(elements) => Expression::List(elements.iter().map(simplify).collect()),
+        Expression::UnitDefinition(name, definition) => {
+            Expression::UnitDefinition(name.clone(), s(definition))
+        }
+        Expression::Assignment(name, value) => Expression::Assignment(name.clone(), s(value)),
+        Expression::FunctionDefinition(name, parameters, body) => {
+            Expression::FunctionDefinition(name.clone(), parameters.clone(), s(body))
+        }
+        Expression::Labeled(label, expression) => Expression::Labeled(label.clone(), s(expression)),
+    }
+}
+
+/// Collects the terms of a sum, adding up the coefficients of like terms
+fn simplify_sum(expression: &Expression) -> Option<Expression> {
+    let mut terms: Vec<Product> = vec![];
+    for (sign, term) in summands(expression) {
+        let mut term = Product::of(term)?;
+        term.coefficient *= sign;
+        match terms.iter_mut().find(|existing| existing.is_like(&term)) {
+            Some(existing) => existing.coefficient += term.coefficient,
+            None => terms.push(term),
+        }
+    }
+    if terms.iter().any(|term| !term.coefficient.is_finite()) {
+        return None;
+    }
+
+    let mut sum: Option<Expression> = None;
+    for mut term in terms.into_iter().filter(|term| term.coefficient != 0.0) {
+        sum = Some(match sum {
+            None => term.into_expression(),
+            Some(sum) if term.coefficient < 0.0 => {
+                term.coefficient = -term.coefficient;
+                Expression::Subtraction(Box::new(sum), Box::new(term.into_expression()))
+            }
+            Some(sum) => Expression::Addition(Box::new(sum), Box::new(term.into_expression())),
+        });
+    }
+    Some(sum.unwrap_or(Expression::NumericLiteral(0.0)))
+}
+
+/// The terms of a sum with their signs, e.g. `a - (b - c)` is `+a`, `-b` and `+c`
+fn summands(expression: &Expression) -> Vec<(f64, &Expression)> {
+    match expression {
+        Expression::Addition(left, right) => [summands(left), summands(right)].concat(),
+        Expression::Subtraction(left, right) => {
+            let negated = summands(right)
+                .into_iter()
+                .map(|(sign, term)| (-sign, term));
+            summands(left).into_iter().chain(negated).collect()
+        }
+        Expression::Minus(operand) => summands(operand)
+            .into_iter()
+            .map(|(sign, term)| (-sign, term))
+            .collect(),
+        _ => vec![(1.0, expression)],
+    }
+}
+
+fn simplify_power(base: &Expression, exponent: &Expression) -> Option<Expression> {
+    match (base, exponent) {
+        (Expression::NumericLiteral(base), Expression::NumericLiteral(exponent)) => {
+            finite(base.powf(*exponent))
+        }
+        (_, Expression::NumericLiteral(exponent)) if *exponent == 1.0 => Some(base.clone()),
+        (_, Expression::NumericLiteral(exponent)) if *exponent == 0.0 => {
+            Some(Expression::NumericLiteral(1.0))
+        }
+        (Expression::NumericLiteral(base), _) if *base == 1.0 => {
+            Some(Expression::NumericLiteral(1.0))
+        }
+        // (x^a)^n = x^(a n) for integers n
+        (Expression::Exponentiation(inner_base, inner_exponent), Expression::NumericLiteral(n))
+            if n.fract() == 0.0 =>
+        {
+            let exponent = multiply_exponents(inner_exponent, *n);
+            Some(Expression::Exponentiation(
+                inner_base.clone(),
+                Box::new(exponent),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn multiply_exponents(exponent: &Expression, factor: f64) -> Expression {
+    match exponent {
+        Expression::NumericLiteral(exponent) => Expression::NumericLiteral(exponent * factor),
+        _ => Expression::Multiplication(
+            Box::new(exponent.clone()),
+            Box::new(Expression::NumericLiteral(factor)),
+        ),
+    }
+}
+
+fn finite(value: f64) -> Option<Expression> {
+    value
+        .is_finite()
+        .then_some(Expression::NumericLiteral(value))
+}
+
+/// A numeric coefficient times powers of other expressions
+#[derive(Debug, Clone)]
+struct Product {
+    coefficient: f64,
+    /// Bases with their exponents, in order of first appearance
+    factors: Vec<(Expression, Expression)>,
+}
+
+impl Product {
+    /// `None` when the product divides by a literal zero, which must stay an error
+    fn of(expression: &Expression) -> Option<Product> {
+        match expression {
+            Expression::NumericLiteral(value) => Some(Product {
+                coefficient: *value,
+                factors: vec![],
+            }),
+            Expression::Minus(operand) => {
+                let mut product = Product::of(operand)?;
+                product.coefficient = -product.coefficient;
+                Some(product)
+            }
+            Expression::Multiplication(left, right) => {
+                Some(Product::of(left)?.multiply(Product::of(right)?))
+            }
+            Expression::Division(left, right) => {
+                Some(Product::of(left)?.multiply(Product::of(right)?.reciprocal()?))
+            }
+            Expression::Exponentiation(base, exponent) => Some(Product {
+                coefficient: 1.0,
+                factors: vec![((**base).clone(), (**exponent).clone())],
+            }),
+            _ => Some(Product {
+                coefficient: 1.0,
+                factors: vec![(expression.clone(), Expression::NumericLiteral(1.0))],
+            }),
+        }
+    }
+
+    fn multiply(mut self, other: Product) -> Product {
+        self.coefficient *= other.coefficient;
+        for (base, exponent) in other.factors {
+            match self
+                .factors
+                .iter_mut()
+                .find(|(existing, _)| *existing == base)
+            {
+                Some((_, existing)) => *existing = add_exponents(existing, &exponent),
+                None => self.factors.push((base, exponent)),
+            }
+        }
+        self
+    }
+
+    fn reciprocal(self) -> Option<Product> {
+        if self.coefficient == 0.0 {
+            return None;
+        }
+        Some(Product {
+            coefficient: 1.0 / self.coefficient,
+            factors: self
+                .factors
+                .into_iter()
+                .map(|(base, exponent)| (base, negate_exponent(exponent)))
+                .collect(),
+        })
+    }
+
+    /// Whether the products differ only in their coefficients
+    fn is_like(&self, other: &Product) -> bool {
+        let factors = |product: &Product| {
+            product
+                .factors
+                .iter()
+                .filter(|(_, exponent)| *exponent != Expression::NumericLiteral(0.0))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let (mine, theirs) = (factors(self), factors(other));
+        mine.len() == theirs.len() && mine.iter().all(|factor| theirs.contains(factor))
+    }
+
+    fn into_expression(self) -> Expression {
+        if self.coefficient == 0.0 {
+            return Expression::NumericLiteral(0.0);
+        }
+
+        let mut numerator: Option<Expression> = None;
+        let mut denominator: Option<Expression> = None;
+        for (base, exponent) in self.factors {
+            let (target, exponent) = match exponent {
+                Expression::NumericLiteral(0.0) => continue,
+                Expression::NumericLiteral(exponent) if exponent < 0.0 => {
+                    (&mut denominator, Expression::NumericLiteral(-exponent))
+                }
+                exponent => (&mut numerator, exponent),
+            };
+            let factor = match exponent {
+                Expression::NumericLiteral(1.0) => base,
+                exponent => Expression::Exponentiation(Box::new(base), Box::new(exponent)),
+            };
+            *target = Some(match target.take() {
+                None => factor,
+                Some(product) => Expression::Multiplication(Box::new(product), Box::new(factor)),
+            });
+        }
+
+        let magnitude = self.coefficient.abs();
+        let numerator = match numerator {
+            Some(numerator) if magnitude == 1.0 => numerator,
+            Some(numerator) => Expression::Multiplication(
+                Box::new(Expression::NumericLiteral(magnitude)),
+                Box::new(numerator),
+            ),
+            None => Expression::NumericLiteral(magnitude),
+        };
+        let quotient = match denominator {
+            Some(denominator) => Expression::Division(Box::new(numerator), Box::new(denominator)),
+            None => numerator,
+        };
+        if self.coefficient < 0.0 {
+            Expression::Minus(Box::new(quotient))
+        } else {
+            quotient
+        }
+    }
+}
+
+fn add_exponents(left: &Expression, right: &Expression) -> Expression {
+    match (left, right) {
+        (Expression::NumericLiteral(left), Expression::NumericLiteral(right)) => {
+            Expression::NumericLiteral(left + right)
+        }
+        _ => Expression::Addition(Box::new(left.clone()), Box::new(right.clone())),
+    }
+}
+
+fn negate_exponent(exponent: Expression) -> Expression {
+    match exponent {
+        Expression::NumericLiteral(exponent) => Expression::NumericLiteral(-exponent),
+        exponent => Expression::Minus(Box::new(exponent)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::derivative::derive;
+    use crate::evaluator::evaluate_in;
+    use crate::strategies;
+    use crate::value::Value;
+    use crate::{lexer, parser};
+    use proptest::prelude::*;
+
+    fn parse(input: &str) -> Expression {
+        parser::parse(&lexer::lex(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_simplification_rules() {
+        let cases = [
+            ("0 + x", "x"),
+            ("x * 1", "x"),
+            ("x - 0", "x"),
+            ("x * 0", "0"),
+            ("0 / x", "0"),
+            ("x^1", "x"),
+            ("x^0", "1"),
+            ("1^x", "1"),
+            ("2 + 3 * 4", "14"),
+            ("2 x + 3 x", "5 x"),
+            ("x - x", "0"),
+            ("x y + 2 y x - 3 x y", "0"),
+            ("x * x", "x^2"),
+            ("x^2 * x^3", "x^5"),
+            ("(x^2)^3", "x^6"),
+            ("x^3 / x", "x^2"),
+            ("x / x", "1"),
+            ("-(-x)", "x"),
+            ("2 - x - 2", "-x"),
+            ("x / 2 * 4", "2 x"),
+            ("sin(0 + x) + sin(x)", "2 sin(x)"),
+            ("x / y^2", "x / y^2"),
+            ("not true", "false"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(simplify(&parse(input)), Ok(parse(expected)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_division_by_zero_is_kept() {
+        assert_eq!(simplify(&parse("x / 0")), Ok(parse("x / 0")));
+        assert_eq!(simplify(&parse("1 / (x - x)")), Ok(parse("1 / 0")));
+    }
+
+    #[test]
+    fn test_simplified_derivatives() {
+        let derivative = derive(&parse("x^2"), "x").unwrap();
+        assert_eq!(simplify(&derivative), Ok(parse("2 x")));
+        let derivative = derive(&parse("3 x^3 + 2 x"), "x").unwrap();
+        assert_eq!(simplify(&derivative), Ok(parse("9 x^2 + 2")));
+    }
+
+    #[test]
+    fn test_rules_assume_defined_expressions() {
+        // Each is undefined at the point, while its simplification is not
+        let cases = [
+            ("x / x", 0.0, "1"),
+            ("x^2 / x", 0.0, "x"),
+            ("(x^0.5)^2", -4.0, "x"),
+            ("x^0.5 * x^0.5", -4.0, "x"),
+        ];
+        for (input, x, expected) in cases {
+            let expression = parse(input);
+            let value = number_at(&expression, x, 0.0);
+            assert!(!value.is_ok_and(f64::is_finite), "{}: {:?}", input, value);
+            let simplified = simplify(&expression).unwrap();
+            assert_eq!(simplified, parse(expected), "{}", input);
+            assert!(
+                number_at(&simplified, x, 0.0).is_ok_and(f64::is_finite),
+                "{}",
+                input
+            );
+        }
+    }
+
+    /// The number `expression` evaluates to with `x` and `y` bound
+    fn number_at(expression: &Expression, x: f64, y: f64) -> Result<f64, &'static str> {
+        let context =
+            Context::new().with_variables([("x", Value::Number(x)), ("y", Value::Number(y))]);
+        evaluate_in(expression, &context)?.as_number()
+    }
+
+    fn is_close(actual: f64, expected: f64) -> bool {
+        (actual - expected).abs() <= 1e-6 * expected.abs().max(1.0)
+    }
+
+    proptest! {
+        #[test]
+        fn test_simplify_preserves_values(
+            expression in strategies::number(),
+            x in -1000i32..1000,
+            y in -1000i32..1000,
+        ) {
+            let (x, y) = (f64::from(x) / 100.0, f64::from(y) / 100.0);
+            // Simplification may widen the domain, see test_rules_assume_defined_expressions
+            let expected = number_at(&expression, x, y);
+            prop_assume!(expected.is_ok_and(f64::is_finite));
+            let expected = expected.unwrap();
+            // Rounding differs between equivalent forms, which only matters where the
+            // value changes wildly between neighbouring points, as in `sin(x^900)`
+            let nearby = number_at(&expression, x * (1.0 + 1e-12), y * (1.0 + 1e-12));
+            prop_assume!(nearby.is_ok_and(|nearby| is_close(nearby, expected)));
+
+            let simplified = simplify(&expression).unwrap();
+            let actual = number_at(&simplified, x, y);
+            let Ok(actual) = actual else {
+                panic!("{} => {} failed: {:?}", expression, simplified, actual);
+            };
+            prop_assert!(
+                is_close(actual, expected),
+                "{} => {} at x = {}, y = {}: {} != {}",
+                expression,
+                simplified,
+                x,
+                y,
+                actual,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Numeric root finding for `solve` and `root`
+//!
+//! Roots are bracketed by sign changes between samples and then refined with Newton's
+//! method, falling back to bisection whenever a Newton step would leave the bracket.
+
+use crate::limits;
+
+/// A real function that may fail to evaluate, e.g. outside its domain
+pub type RealFunction<'a> = &'a dyn Fn(f64) -> Result<f64, &'static str>;
+
+/// The interval `solve` searches when none is given
+pub const DEFAULT_INTERVAL: (f64, f64) = (-1000.0, 1000.0);
+
+/// Number of subintervals sampled for sign changes
+const SAMPLES: usize = 20_000;
+const MAX_ITERATIONS: usize = 200;
+/// Relative precision of the roots found
+const TOLERANCE: f64 = 1e-12;
+
+const NOT_CONVERGED: &str = "Root finding did not converge";
+pub const NO_SIGN_CHANGE: &str = "No sign change between the bounds";
+
+/// All real roots of `f` between `start` and `end`, in ascending order
+///
+/// Roots closer together than the sampling resolution of `(end - start) / 20000` may
+/// be missed, as may roots where `f` touches zero without a derivative to refine them.
+pub fn roots(
+    f: RealFunction,
+    derivative: Option<RealFunction>,
+    start: f64,
+    end: f64,
+) -> Result<Vec<f64>, &'static str> {
+    if !start.is_finite() || !end.is_finite() {
+        return Err("Interval bounds must be finite");
+    }
+    let (start, end) = (start.min(end), start.max(end));
+
+    // Points where `f` fails to evaluate are skipped, unless it fails everywhere
+    let mut first_error = None;
+    let samples: Vec<(f64, f64)> = (0..=SAMPLES)
+        .map(|i| {
+            let x = start + (end - start) * i as f64 / SAMPLES as f64;
+            let y = match f(x) {
+                Ok(y) => y,
+                Err(error) if limits::is_exhausted(error) => return Err(error),
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                    f64::NAN
+                }
+            };
+            Ok((x, y))
+        })
+        .collect::<Result<_, _>>()?;
+    if samples.iter().all(|(_, y)| y.is_nan()) {
+        return Err(first_error.unwrap_or(NOT_CONVERGED));
+    }
+
+    let mut roots = vec![];
+    for window in samples.windows(2) {
+        let [(x0, y0), (x1, y1)] = [window[0], window[1]];
+        if y0 == 0.0 {
+            roots.push(x0);
+        } else if y0 * y1 < 0.0 {
+            let root = bracketed_root(f, derivative, x0, x1)?;
+            // A sign change across a pole, as in `tan`, is not a root
+            if f(root)?.abs() <= y0.abs().min(y1.abs()) {
+                roots.push(root);
+            }
+        }
+    }
+    if let Some(derivative) = derivative {
+        // A local minimum of |f| without a sign change may be a double root, as in `x^2`
+        for window in samples.windows(3) {
+            let [(x0, y0), (x1, y1), (x2, y2)] = [window[0], window[1], window[2]];
+            if y0 * y1 > 0.0 && y1 * y2 > 0.0 && y1.abs() < y0.abs() && y1.abs() <= y2.abs() {
+                let tolerance = TOLERANCE * y0.abs().max(y2.abs());
+                if let Some(root) = newton(f, derivative, x1)? {
+                    if root > x0
+                        && root < x2
+                        && value_at(f, root)?.is_some_and(|y| y.abs() <= tolerance)
+                    {
+                        roots.push(root);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(&(x, 0.0)) = samples.last() {
+        roots.push(x);
+    }
+
+    roots.sort_by(f64::total_cmp);
+    roots.dedup_by(|a, b| (*a - *b).abs() <= TOLERANCE * a.abs().max(1.0));
+    Ok(roots)
+}
+
+/// A root of `f` between `low` and `high`, where `f` changes sign
+pub fn bracketed_root(
+    f: RealFunction,
+    derivative: Option<RealFunction>,
+    mut low: f64,
+    mut high: f64,
+) -> Result<f64, &'static str> {
+    let mut f_low = f(low)?;
+    let f_high = f(high)?;
+    if f_low == 0.0 {
+        return Ok(low);
+    }
+    if f_high == 0.0 {
+        return Ok(high);
+    }
+    if f_low.is_nan() || f_high.is_nan() || f_low.signum() == f_high.signum() {
+        return Err(NO_SIGN_CHANGE);
+    }
+
+    let mut x = 0.5 * (low + high);
+    for _ in 0..MAX_ITERATIONS {
+        let y = f(x)?;
+        if y == 0.0 {
+            return Ok(x);
+        }
+        if y.is_nan() {
+            return Err(NOT_CONVERGED);
+        }
+        if y.signum() == f_low.signum() {
+            low = x;
+            f_low = y;
+        } else {
+            high = x;
+        }
+        let bisection = 0.5 * (low + high);
+        if (high - low).abs() <= TOLERANCE * bisection.abs().max(1.0) {
+            return Ok(bisection);
+        }
+
+        let slope = match derivative {
+            Some(derivative) => value_at(derivative, x)?,
+            None => None,
+        };
+        let newton_step = slope
+            .map(|slope| x - y / slope)
+            .filter(|next| next.is_finite() && next > &low.min(high) && next < &low.max(high));
+        match newton_step {
+            Some(next) if (next - x).abs() <= TOLERANCE * next.abs().max(1.0) => return Ok(next),
+            Some(next) => x = next,
+            None => x = bisection,
+        }
+    }
+    Err(NOT_CONVERGED)
+}
+
+/// Newton's method from `x`, `None` if it does not settle
+fn newton(
+    f: RealFunction,
+    derivative: RealFunction,
+    mut x: f64,
+) -> Result<Option<f64>, &'static str> {
+    for _ in 0..MAX_ITERATIONS {
+        let Some(y) = value_at(f, x)? else {
+            return Ok(None);
+        };
+        if y == 0.0 {
+            return Ok(Some(x));
+        }
+        let Some(slope) = value_at(derivative, x)? else {
+            return Ok(None);
+        };
+        let next = x - y / slope;
+        if !next.is_finite() {
+            return Ok(None);
+        }
+        if (next - x).abs() <= TOLERANCE * next.abs().max(1.0) {
+            return Ok(Some(next));
+        }
+        x = next;
+    }
+    Ok(None)
+}
+
+/// The value of `f` at `x`, `None` where it fails to evaluate, unless the evaluation
+/// ran out of its budget, which every later point would too
+fn value_at(f: RealFunction, x: f64) -> Result<Option<f64>, &'static str> {
+    match f(x) {
+        Ok(y) => Ok(Some(y)),
+        Err(error) if limits::is_exhausted(error) => Err(error),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(actual: Vec<f64>, expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_roots() {
+        let f = |x: f64| Ok(x * x - 2.0);
+        let derivative = |x: f64| Ok(2.0 * x);
+        let sqrt2 = 2f64.sqrt();
+        assert_roots(
+            roots(&f, Some(&derivative), -10.0, 10.0).unwrap(),
+            &[-sqrt2, sqrt2],
+        );
+        assert_roots(roots(&f, None, -10.0, 10.0).unwrap(), &[-sqrt2, sqrt2]);
+        assert_roots(roots(&f, None, 0.0, 10.0).unwrap(), &[sqrt2]);
+        assert_roots(roots(&f, None, 5.0, 10.0).unwrap(), &[]);
+    }
+
+    #[test]
+    fn test_double_roots() {
+        let f = |x: f64| Ok((x - 1.5) * (x - 1.5));
+        let derivative = |x: f64| Ok(2.0 * (x - 1.5));
+        let found = roots(&f, Some(&derivative), -10.0, 10.0).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!((found[0] - 1.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_poles_are_not_roots() {
+        let f = |x: f64| Ok(x.tan());
+        let found = roots(&f, None, 1.0, 5.0).unwrap();
+        assert_roots(found, &[std::f64::consts::PI]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let undefined = |_: f64| Err("Unknown name");
+        assert_eq!(roots(&undefined, None, 0.0, 1.0), Err("Unknown name"));
+        let f = |x: f64| Ok(x);
+        assert_eq!(
+            roots(&f, None, 0.0, f64::INFINITY),
+            Err("Interval bounds must be finite")
+        );
+        assert_eq!(bracketed_root(&f, None, 1.0, 2.0), Err(NO_SIGN_CHANGE));
+        let undefined_inside = |x: f64| Ok(if x.abs() < 0.5 { f64::NAN } else { x });
+        assert_eq!(
+            bracketed_root(&undefined_inside, None, -1.0, 2.0),
+            Err(NOT_CONVERGED)
+        );
+    }
+}
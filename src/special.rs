@@ -0,0 +1,182 @@
+//! Special functions: gamma, log-gamma and factorials
+//!
+//! Factorials cover the whole range of a number, up to 170!. Results up to
+//! [`MAX_EXACT_INTEGER`](crate::number_theory::MAX_EXACT_INTEGER) are exact and larger ones are rounded, unlike the
+//! [`number_theory`](crate::number_theory) functions, which reject such results.
+
+use std::f64::consts::PI;
+
+use crate::functions::{Builtin, FunctionRegistry};
+
+/// The largest n for which n! fits into an `f64`
+const MAX_FACTORIAL: f64 = 170.0;
+
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFICIENTS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+pub fn register(registry: &mut FunctionRegistry) {
+    let functions = [
+        Builtin::numeric("factorial", 1..=1, |arguments| factorial(arguments[0]))
+            .doc("n! = 1 * 2 * ... * n, also written as n!"),
+        Builtin::numeric("gamma", 1..=1, |arguments| gamma(arguments[0]))
+            .doc("The gamma function, gamma(n) = (n - 1)!"),
+        Builtin::numeric("lgamma", 1..=1, |arguments| lgamma(arguments[0]))
+            .doc("Natural logarithm of the absolute value of the gamma function"),
+    ];
+    for function in functions {
+        registry.register(function);
+    }
+}
+
+pub fn factorial(n: f64) -> Result<f64, &'static str> {
+    if n < 0.0 || n.fract() != 0.0 {
+        return Err("Factorial of negative or non-integer number");
+    }
+    if n > MAX_FACTORIAL {
+        return Err("Factorial is too large");
+    }
+    // Multiplying in order keeps every result up to 22! exact
+    Ok((2..=n as u64).fold(1.0, |product, k| product * k as f64))
+}
+
+/// n!! = n * (n - 2) * (n - 4) * ..., where (-1)!! = 0!! = 1
+pub fn double_factorial(n: f64) -> Result<f64, &'static str> {
+    if n < -1.0 || n.fract() != 0.0 {
+        return Err("Double factorial of number below -1 or non-integer number");
+    }
+    let mut product = 1.0;
+    let mut k = n;
+    while k > 1.0 {
+        product *= k;
+        // Checked as it goes, as subtracting two no longer changes a huge `k`
+        if !product.is_finite() {
+            return Err("Double factorial is too large");
+        }
+        k -= 2.0;
+    }
+    Ok(product)
+}
+
+/// The gamma function, extending factorials so that gamma(n) = (n - 1)!
+pub fn gamma(x: f64) -> Result<f64, &'static str> {
+    if x <= 0.0 && x.fract() == 0.0 {
+        return Err("Gamma of non-positive integer");
+    }
+    if x.fract() == 0.0 && x <= MAX_FACTORIAL + 1.0 {
+        return factorial(x - 1.0);
+    }
+    if x < 0.5 {
+        // Reflection formula
+        return Ok(PI / ((PI * x).sin() * gamma(1.0 - x)?));
+    }
+
+    let (t, series) = lanczos(x);
+    // The power is split in two, as t^(x - 0.5) alone overflows from x = 143 on
+    let power = t.powf((x - 0.5) / 2.0);
+    let result = (2.0 * PI).sqrt() * power * (-t).exp() * power * series;
+    if !result.is_finite() {
+        return Err("Gamma is too large");
+    }
+    Ok(result)
+}
+
+/// The natural logarithm of the absolute value of the gamma function, finite even
+/// where gamma itself overflows
+pub fn lgamma(x: f64) -> Result<f64, &'static str> {
+    if x <= 0.0 && x.fract() == 0.0 {
+        return Err("Gamma of non-positive integer");
+    }
+    if x < 0.5 {
+        return Ok((PI / (PI * x).sin().abs()).ln() - lgamma(1.0 - x)?);
+    }
+
+    let (t, series) = lanczos(x);
+    Ok(0.5 * (2.0 * PI).ln() + (x - 0.5) * t.ln() - t + series.ln())
+}
+
+/// The Lanczos approximation's shifted argument and series for x >= 0.5
+fn lanczos(x: f64) -> (f64, f64) {
+    let x = x - 1.0;
+    let series = LANCZOS_COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(LANCZOS_COEFFICIENTS[0], |sum, (i, coefficient)| {
+            sum + coefficient / (x + i as f64 + 1.0)
+        });
+    (x + LANCZOS_G + 0.5, series)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number_theory::MAX_EXACT_INTEGER;
+
+    fn assert_close(actual: f64, expected: f64) {
+        let tolerance = 1e-12 * expected.abs().max(1.0);
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_factorial() {
+        assert_eq!(factorial(0.0).unwrap(), 1.0);
+        assert_eq!(factorial(5.0).unwrap(), 120.0);
+        assert_eq!(factorial(20.0).unwrap(), 2432902008176640000.0);
+        // Beyond u64, which used to overflow, and rounded beyond 2^53
+        assert!(factorial(25.0).unwrap() > MAX_EXACT_INTEGER as f64);
+        assert_eq!(factorial(25.0).unwrap(), 15511210043330985984000000.0);
+        assert_close(factorial(170.0).unwrap(), 7.257415615307994e306);
+        assert!(factorial(171.0).is_err());
+        assert!(factorial(-1.0).is_err());
+        assert!(factorial(1.5).is_err());
+    }
+
+    #[test]
+    fn test_double_factorial() {
+        assert_eq!(double_factorial(-1.0).unwrap(), 1.0);
+        assert_eq!(double_factorial(0.0).unwrap(), 1.0);
+        assert_eq!(double_factorial(7.0).unwrap(), 105.0);
+        assert_eq!(double_factorial(8.0).unwrap(), 384.0);
+        assert!(double_factorial(-2.0).is_err());
+        assert!(double_factorial(1000.0).is_err());
+    }
+
+    #[test]
+    fn test_gamma() {
+        assert_eq!(gamma(5.0).unwrap(), 24.0);
+        assert_close(gamma(0.5).unwrap(), PI.sqrt());
+        assert_close(gamma(1.5).unwrap(), PI.sqrt() / 2.0);
+        assert_close(gamma(-0.5).unwrap(), -2.0 * PI.sqrt());
+        assert_close(gamma(4.5).unwrap(), 3.5 * 2.5 * 1.5 * 0.5 * PI.sqrt());
+        // Large enough for the power in the Lanczos approximation to overflow alone
+        assert_close(gamma(144.5).unwrap(), 4.621_231_641_283_711e248);
+        assert_close(gamma(150.5).unwrap(), 4.661_072_627_097_377e261);
+        assert!(gamma(0.0).is_err());
+        assert!(gamma(-3.0).is_err());
+        assert!(gamma(200.5).is_err());
+    }
+
+    #[test]
+    fn test_lgamma() {
+        assert_close(lgamma(1.0).unwrap(), 0.0);
+        assert_close(lgamma(10.0).unwrap(), 362880.0f64.ln());
+        assert_close(lgamma(0.5).unwrap(), PI.sqrt().ln());
+        assert_close(lgamma(-0.5).unwrap(), (2.0 * PI.sqrt()).ln());
+        // Far beyond where gamma overflows
+        assert_close(lgamma(1000.0).unwrap(), 5_905.220_423_209_181);
+    }
+}
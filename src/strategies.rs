@@ -0,0 +1,117 @@
+//! Proptest strategies generating expression trees, for property tests of the parser
+//! and the evaluators
+//!
+//! Numbers and conditions are generated apart, so that most expressions have a value
+//! rather than failing on a boolean where a number belongs.
+
+use proptest::prelude::*;
+
+use crate::parser::Expression;
+
+/// Variables the tests bind
+const VARIABLES: [&str; 2] = ["x", "y"];
+
+const UNITS: [&str; 2] = ["km", "s"];
+
+/// Built-in functions of numbers to call, with their number of arguments
+const FUNCTIONS: [(&str, usize); 6] = [
+    ("sqrt", 1),
+    ("abs", 1),
+    ("sin", 1),
+    ("round", 1),
+    ("max", 2),
+    ("min", 3),
+];
+
+fn leaf() -> impl Strategy<Value = Expression> {
+    prop_oneof![
+        2 => (0u32..1000).prop_map(|n| Expression::NumericLiteral(n.into())),
+        2 => (0u32..100_000).prop_map(|n| Expression::NumericLiteral(f64::from(n) / 100.0)),
+        3 => prop::sample::select(&VARIABLES[..]).prop_map(|name| Expression::Name(name.into())),
+        1 => prop::sample::select(&UNITS[..]).prop_map(|name| Expression::Name(name.into())),
+        // Factorials of anything but small integers fail
+        1 => (0u32..12, any::<bool>()).prop_map(|(n, double)| {
+            let operand = Box::new(Expression::NumericLiteral(n.into()));
+            match double {
+                false => Expression::Factorial(operand),
+                true => Expression::DoubleFactorial(operand),
+            }
+        }),
+    ]
+}
+
+/// Comparisons of `number`s combined with `and`, `or` and `not`
+fn condition(number: BoxedStrategy<Expression>) -> BoxedStrategy<Expression> {
+    let comparison = (0..7usize, number.clone(), number).prop_map(|(operator, left, right)| {
+        [
+            Expression::LessThan,
+            Expression::LessThanOrEqual,
+            Expression::GreaterThan,
+            Expression::GreaterThanOrEqual,
+            Expression::Equal,
+            Expression::NotEqual,
+            Expression::ApproximatelyEqual,
+        ][operator](Box::new(left), Box::new(right))
+    });
+    let condition = prop_oneof![
+        any::<bool>().prop_map(Expression::BooleanLiteral),
+        comparison
+    ];
+    condition
+        .prop_recursive(2, 8, 2, |inner| {
+            prop_oneof![
+                inner
+                    .clone()
+                    .prop_map(|operand| Expression::Not(Box::new(operand))),
+                (inner.clone(), inner.clone())
+                    .prop_map(|(left, right)| Expression::And(Box::new(left), Box::new(right))),
+                (inner.clone(), inner)
+                    .prop_map(|(left, right)| Expression::Or(Box::new(left), Box::new(right))),
+            ]
+        })
+        .boxed()
+}
+
+/// Numeric expressions of literals, names, operators and function calls, nested a few
+/// levels deep
+pub(crate) fn number() -> BoxedStrategy<Expression> {
+    leaf()
+        .prop_recursive(5, 48, 3, |inner| {
+            let binary =
+                (0..5usize, inner.clone(), inner.clone()).prop_map(|(operator, left, right)| {
+                    [
+                        Expression::Addition,
+                        Expression::Subtraction,
+                        Expression::Multiplication,
+                        Expression::Division,
+                        Expression::Exponentiation,
+                    ][operator](Box::new(left), Box::new(right))
+                });
+            let minus = inner
+                .clone()
+                .prop_map(|operand| Expression::Minus(Box::new(operand)));
+            let branch = (condition(inner.clone()), inner.clone(), inner.clone()).prop_map(
+                |(condition, then, otherwise)| {
+                    Expression::FunctionCall("if".to_string(), vec![condition, then, otherwise])
+                },
+            );
+            let aggregate = prop::collection::vec(inner.clone(), 1..4).prop_map(|elements| {
+                Expression::FunctionCall("sum".to_string(), vec![Expression::List(elements)])
+            });
+            let call = prop::sample::select(&FUNCTIONS[..]).prop_flat_map(move |(name, arity)| {
+                prop::collection::vec(inner.clone(), arity)
+                    .prop_map(move |args| Expression::FunctionCall(name.to_string(), args))
+            });
+            prop_oneof![6 => binary, 1 => minus, 1 => branch, 1 => aggregate, 2 => call]
+        })
+        .boxed()
+}
+
+/// Numbers, conditions and lists of numbers
+pub(crate) fn expression() -> impl Strategy<Value = Expression> {
+    prop_oneof![
+        6 => number(),
+        2 => condition(number()),
+        1 => prop::collection::vec(number(), 0..4).prop_map(Expression::List),
+    ]
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+pub const EXPONENT_TOO_LARGE: &str = "Exponent too large";
+
+/// The base dimensions every unit is expressed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseDimension {
+    Length,
+    Mass,
+    Time,
+    Current,
+    Temperature,
+    Amount,
+    Luminosity,
+    Information,
+}
+
+impl BaseDimension {
+    pub const ALL: [BaseDimension; 8] = [
+        BaseDimension::Length,
+        BaseDimension::Mass,
+        BaseDimension::Time,
+        BaseDimension::Current,
+        BaseDimension::Temperature,
+        BaseDimension::Amount,
+        BaseDimension::Luminosity,
+        BaseDimension::Information,
+    ];
+
+    /// Symbol of the unit the base dimension is measured in
+    fn base_unit(&self) -> &'static str {
+        match self {
+            BaseDimension::Length => "m",
+            BaseDimension::Mass => "kg",
+            BaseDimension::Time => "s",
+            BaseDimension::Current => "A",
+            BaseDimension::Temperature => "K",
+            BaseDimension::Amount => "mol",
+            BaseDimension::Luminosity => "cd",
+            BaseDimension::Information => "bit",
+        }
+    }
+}
+
+/// Exponents of each base unit, e.g. `{"m": 1, "s": -1}` for velocity
+///
+/// Base units are either the built-in [`BaseDimension`]s or primitive units
+/// declared at runtime, such as `story_point`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Dimension {
+    exponents: BTreeMap<String, i8>,
+}
+
+impl Dimension {
+    pub fn dimensionless() -> Dimension {
+        Dimension::default()
+    }
+
+    pub fn base(base: BaseDimension) -> Dimension {
+        Dimension::primitive(base.base_unit())
+    }
+
+    /// Whether `unit` measures one of the built-in base dimensions, so that a
+    /// primitive unit of that name would share its dimension
+    pub fn is_base_unit(unit: &str) -> bool {
+        BaseDimension::ALL
+            .iter()
+            .any(|base| base.base_unit() == unit)
+    }
+
+    /// A new dimension measured in the given primitive unit
+    pub fn primitive(unit: &str) -> Dimension {
+        Dimension {
+            exponents: BTreeMap::from([(unit.to_string(), 1)]),
+        }
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.exponents.is_empty()
+    }
+
+    pub fn exponent(&self, base: BaseDimension) -> i8 {
+        self.exponents
+            .get(base.base_unit())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn multiply(&self, other: &Dimension) -> Result<Dimension, &'static str> {
+        self.combine(other, 1)
+    }
+
+    pub fn divide(&self, other: &Dimension) -> Result<Dimension, &'static str> {
+        self.combine(other, -1)
+    }
+
+    pub fn pow(&self, exponent: i8) -> Result<Dimension, &'static str> {
+        Dimension::default().combine(self, exponent)
+    }
+
+    /// Adds the exponents of `other` times `factor`, failing where an exponent leaves
+    /// the range whose negation an `i8` holds
+    fn combine(&self, other: &Dimension, factor: i8) -> Result<Dimension, &'static str> {
+        let mut result = self.clone();
+        for (unit, exponent) in &other.exponents {
+            let value = result.exponents.entry(unit.clone()).or_default();
+            *value = exponent
+                .checked_mul(factor)
+                .and_then(|exponent| value.checked_add(exponent))
+                .filter(|value| *value != i8::MIN)
+                .ok_or(EXPONENT_TOO_LARGE)?;
+            if *value == 0 {
+                result.exponents.remove(unit);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Display for Dimension {
+    /// Formats the dimension in base units, e.g. `bit/s` or `kg*m^2`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format_factors = |positive: bool| {
+            self.exponents
+                .iter()
+                .filter_map(|(unit, exponent)| {
+                    let exponent = if positive { *exponent } else { -exponent };
+                    match exponent {
+                        e if e <= 0 => None,
+                        1 => Some(unit.clone()),
+                        e => Some(format!("{}^{}", unit, e)),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("*")
+        };
+
+        let numerator = format_factors(true);
+        let denominator = format_factors(false);
+        match (numerator.is_empty(), denominator.is_empty()) {
+            (_, true) => write!(f, "{}", numerator),
+            (true, false) => write!(f, "1/{}", denominator),
+            (false, false) => write!(f, "{}/{}", numerator, denominator),
+        }
+    }
+}
+
+/// Which prefixes a unit accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Prefixes {
+    None,
+    /// Decimal prefixes, e.g. `k`, `M`, `m`
+    Si,
+    /// Decimal and binary prefixes, e.g. `k` and `Ki`
+    SiAndIec,
+}
+
+const SI_PREFIXES: [(&str, f64); 20] = [
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("da", 1e1),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+    ("z", 1e-21),
+];
+
+const IEC_PREFIXES: [(&str, f64); 8] = [
+    ("Ki", 1024.0),
+    ("Mi", 1048576.0),
+    ("Gi", 1073741824.0),
+    ("Ti", 1099511627776.0),
+    ("Pi", 1125899906842624.0),
+    ("Ei", 1152921504606846976.0),
+    ("Zi", 1180591620717411303424.0),
+    ("Yi", 1208925819614629174706176.0),
+];
+
+/// Uppercase `K` for kilo, as commonly written in `KB`, only for information units
+const UPPERCASE_KILO: [(&str, f64); 1] = [("K", 1e3)];
+
+/// A unit expressed as a multiple of the base units of its dimension
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub factor: f64,
+    pub dimension: Dimension,
+}
+
+struct BuiltinUnit {
+    names: &'static [&'static str],
+    factor: f64,
+    dimension: &'static [(BaseDimension, i8)],
+    prefixes: Prefixes,
+}
+
+const BUILTIN_UNITS: &[BuiltinUnit] = &[
+    // Information
+    BuiltinUnit {
+        names: &["bit"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Information, 1)],
+        prefixes: Prefixes::SiAndIec,
+    },
+    BuiltinUnit {
+        names: &["B", "byte"],
+        factor: 8.0,
+        dimension: &[(BaseDimension::Information, 1)],
+        prefixes: Prefixes::SiAndIec,
+    },
+    BuiltinUnit {
+        names: &["bps"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Information, 1), (BaseDimension::Time, -1)],
+        prefixes: Prefixes::Si,
+    },
+    // Time
+    BuiltinUnit {
+        names: &["s", "sec"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Time, 1)],
+        prefixes: Prefixes::Si,
+    },
+    BuiltinUnit {
+        names: &["min", "minute"],
+        factor: 60.0,
+        dimension: &[(BaseDimension::Time, 1)],
+        prefixes: Prefixes::None,
+    },
+    BuiltinUnit {
+        names: &["h", "hour"],
+        factor: 3600.0,
+        dimension: &[(BaseDimension::Time, 1)],
+        prefixes: Prefixes::None,
+    },
+    BuiltinUnit {
+        names: &["d", "day"],
+        factor: 86400.0,
+        dimension: &[(BaseDimension::Time, 1)],
+        prefixes: Prefixes::None,
+    },
+    BuiltinUnit {
+        names: &["wk", "week"],
+        factor: 604800.0,
+        dimension: &[(BaseDimension::Time, 1)],
+        prefixes: Prefixes::None,
+    },
+    // Length
+    BuiltinUnit {
+        names: &["m", "meter", "metre"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Length, 1)],
+        prefixes: Prefixes::Si,
+    },
+    BuiltinUnit {
+        names: &["in", "inch"],
+        factor: 0.0254,
+        dimension: &[(BaseDimension::Length, 1)],
+        prefixes: Prefixes::None,
+    },
+    BuiltinUnit {
+        names: &["ft", "foot"],
+        factor: 0.3048,
+        dimension: &[(BaseDimension::Length, 1)],
+        prefixes: Prefixes::None,
+    },
+    BuiltinUnit {
+        names: &["mi", "mile"],
+        factor: 1609.344,
+        dimension: &[(BaseDimension::Length, 1)],
+        prefixes: Prefixes::None,
+    },
+    // Remaining SI base units
+    BuiltinUnit {
+        names: &["g", "gram"],
+        factor: 1e-3,
+        dimension: &[(BaseDimension::Mass, 1)],
+        prefixes: Prefixes::Si,
+    },
+    BuiltinUnit {
+        names: &["A", "ampere"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Current, 1)],
+        prefixes: Prefixes::Si,
+    },
+    BuiltinUnit {
+        names: &["K", "kelvin"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Temperature, 1)],
+        prefixes: Prefixes::Si,
+    },
+    BuiltinUnit {
+        names: &["mol"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Amount, 1)],
+        prefixes: Prefixes::Si,
+    },
+    BuiltinUnit {
+        names: &["cd", "candela"],
+        factor: 1.0,
+        dimension: &[(BaseDimension::Luminosity, 1)],
+        prefixes: Prefixes::Si,
+    },
+];
+
+impl BuiltinUnit {
+    fn unit(&self, prefix_factor: f64) -> Unit {
+        let mut dimension = Dimension::dimensionless();
+        for (base, exponent) in self.dimension {
+            dimension = Dimension::base(*base)
+                .pow(*exponent)
+                .and_then(|power| dimension.multiply(&power))
+                .expect("built-in units have small exponents");
+        }
+        Unit {
+            factor: self.factor * prefix_factor,
+            dimension,
+        }
+    }
+}
+
+/// Looks up a built-in unit by name, accepting SI prefixes (`kB`, `ms`) and,
+/// for information units, IEC binary prefixes (`KiB`, `Mibit`) and `K` for kilo (`KB`)
+pub fn lookup(name: &str) -> Option<Unit> {
+    if let Some(unit) = BUILTIN_UNITS.iter().find(|unit| unit.names.contains(&name)) {
+        return Some(unit.unit(1.0));
+    }
+
+    let prefixed = |prefixes: &[(&str, f64)], accepts: fn(Prefixes) -> bool| {
+        prefixes.iter().find_map(|(prefix, prefix_factor)| {
+            let rest = name.strip_prefix(prefix)?;
+            BUILTIN_UNITS
+                .iter()
+                .find(|unit| accepts(unit.prefixes) && unit.names[0] == rest)
+                .map(|unit| unit.unit(*prefix_factor))
+        })
+    };
+
+    prefixed(&IEC_PREFIXES, |prefixes| prefixes == Prefixes::SiAndIec)
+        .or_else(|| prefixed(&UPPERCASE_KILO, |prefixes| prefixes == Prefixes::SiAndIec))
+        .or_else(|| prefixed(&SI_PREFIXES, |prefixes| prefixes != Prefixes::None))
+}
+
+/// The unit a quantity is displayed in, e.g. `GiB` with a factor of 2^33 bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisplayUnit {
+    pub name: String,
+    pub factor: f64,
+}
+
+/// A magnitude with a physical dimension
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    /// Magnitude expressed in the base units of the dimension
+    pub value: f64,
+    pub dimension: Dimension,
+    /// Unit to display the quantity in, base units are used when absent
+    pub unit: Option<DisplayUnit>,
+}
+
+impl Quantity {
+    /// A quantity of exactly one of the named unit
+    pub fn of_unit(name: impl Into<String>, unit: &Unit) -> Quantity {
+        Quantity {
+            value: unit.factor,
+            dimension: unit.dimension.clone(),
+            unit: Some(DisplayUnit {
+                name: name.into(),
+                factor: unit.factor,
+            }),
+        }
+    }
+
+    /// The magnitude expressed in the display unit
+    pub fn display_value(&self) -> f64 {
+        match &self.unit {
+            Some(unit) => self.value / unit.factor,
+            None => self.value,
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.unit {
+            Some(unit) => write!(f, "{} {}", self.display_value(), unit.name),
+            None => write!(f, "{} {}", self.value, self.dimension),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup_plain_units() {
+        let byte = lookup("B").unwrap();
+        assert_eq!(byte.factor, 8.0);
+        assert_eq!(byte.dimension, Dimension::base(BaseDimension::Information));
+
+        let hour = lookup("h").unwrap();
+        assert_eq!(hour.factor, 3600.0);
+        assert_eq!(hour.dimension, Dimension::base(BaseDimension::Time));
+    }
+
+    #[test]
+    fn test_lookup_prefixed_units() {
+        assert_eq!(lookup("kB").unwrap().factor, 8e3);
+        assert_eq!(lookup("TB").unwrap().factor, 8e12);
+        assert_eq!(lookup("Mbit").unwrap().factor, 1e6);
+        assert_eq!(lookup("KiB").unwrap().factor, 8.0 * 1024.0);
+        // Uppercase kilo is decimal like `kB`
+        assert_eq!(lookup("KB").unwrap().factor, 8e3);
+        assert_eq!(lookup("Kbit").unwrap().factor, 1e3);
+        assert_eq!(lookup("GiB").unwrap().factor, 8.0 * 1073741824.0);
+        assert_eq!(lookup("ms").unwrap().factor, 1e-3);
+        assert_eq!(lookup("km").unwrap().factor, 1e3);
+    }
+
+    #[test]
+    fn test_lookup_rejects_invalid_prefixes() {
+        // Binary prefixes only apply to information units
+        assert_eq!(lookup("Kim"), None);
+        assert_eq!(lookup("Km"), None);
+        // Units without prefixes
+        assert_eq!(lookup("kh"), None);
+        assert_eq!(lookup("foo"), None);
+    }
+
+    #[test]
+    fn test_dimension_display() {
+        let information = Dimension::base(BaseDimension::Information);
+        let time = Dimension::base(BaseDimension::Time);
+        assert_eq!(information.divide(&time).unwrap().to_string(), "bit/s");
+        assert_eq!(
+            Dimension::dimensionless()
+                .divide(&time)
+                .unwrap()
+                .to_string(),
+            "1/s"
+        );
+        let area = Dimension::base(BaseDimension::Length).pow(2).unwrap();
+        assert_eq!(area.to_string(), "m^2");
+    }
+
+    #[test]
+    fn test_exponent_overflow() {
+        let length = Dimension::base(BaseDimension::Length);
+        let power = length.pow(127).unwrap();
+        assert_eq!(power.multiply(&length), Err(EXPONENT_TOO_LARGE));
+        assert_eq!(
+            length.pow(-127).unwrap().divide(&length),
+            Err(EXPONENT_TOO_LARGE)
+        );
+        assert_eq!(length.pow(100).unwrap().pow(2), Err(EXPONENT_TOO_LARGE));
+    }
+}
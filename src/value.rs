@@ -0,0 +1,348 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::parser::Expression;
+use crate::units::{DisplayUnit, Quantity, Unit, EXPONENT_TOO_LARGE};
+
+/// The result of evaluating an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Quantity(Quantity),
+    Bool(bool),
+    List(Vec<Value>),
+    /// An expression as the result of symbolic computation, e.g. `derive(x^2, x)`
+    Expression(Expression),
+}
+
+const BOOLEAN_IN_ARITHMETIC: &str = "Expected a number, found a boolean";
+const LIST_IN_ARITHMETIC: &str = "Expected a number, found a list";
+const EXPRESSION_IN_ARITHMETIC: &str = "Expected a number, found an expression";
+
+/// Relative tolerance of approximate equality, `~=`
+const APPROXIMATE_EQUALITY_TOLERANCE: f64 = 1e-9;
+
+impl Value {
+    /// Wraps a magnitude in the given dimension, collapsing dimensionless results into numbers
+    fn from_quantity(quantity: Quantity) -> Value {
+        if quantity.dimension.is_dimensionless() {
+            Value::Number(quantity.value)
+        } else {
+            Value::Quantity(quantity)
+        }
+    }
+
+    /// Exactly one of the named unit, e.g. `1 GiB`
+    pub fn from_unit(name: &str, unit: &Unit) -> Value {
+        Value::from_quantity(Quantity::of_unit(name, unit))
+    }
+
+    /// The magnitude in base units, regardless of dimension
+    pub fn magnitude(&self) -> Result<f64, &'static str> {
+        match self {
+            Value::Number(value) => Ok(*value),
+            Value::Quantity(quantity) => Ok(quantity.value),
+            Value::Bool(_) => Err(BOOLEAN_IN_ARITHMETIC),
+            Value::List(_) => Err(LIST_IN_ARITHMETIC),
+            Value::Expression(_) => Err(EXPRESSION_IN_ARITHMETIC),
+        }
+    }
+
+    /// A value of the same dimension and display unit with a different magnitude in base units
+    pub fn with_magnitude(&self, magnitude: f64) -> Result<Value, &'static str> {
+        match self {
+            Value::Quantity(quantity) => Ok(Value::Quantity(Quantity {
+                value: magnitude,
+                ..quantity.clone()
+            })),
+            _ => self.magnitude().map(|_| Value::Number(magnitude)),
+        }
+    }
+
+    /// The magnitudes of two values of the same dimension
+    fn magnitudes(&self, other: &Value) -> Result<(f64, f64), &'static str> {
+        let magnitudes = (self.magnitude()?, other.magnitude()?);
+        match (self, other) {
+            (Value::Number(_), Value::Number(_)) => Ok(magnitudes),
+            (Value::Quantity(left), Value::Quantity(right))
+                if left.dimension == right.dimension =>
+            {
+                Ok(magnitudes)
+            }
+            _ => Err("Incompatible units"),
+        }
+    }
+
+    /// The plain number, without units
+    pub fn as_number(&self) -> Result<f64, &'static str> {
+        match self {
+            Value::Number(value) => Ok(*value),
+            Value::Quantity(_) => Err("Expected a dimensionless number"),
+            Value::Bool(_) => Err("Expected a number, found a boolean"),
+            Value::List(_) => Err("Expected a number, found a list"),
+            Value::Expression(_) => Err(EXPRESSION_IN_ARITHMETIC),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, &'static str> {
+        match self {
+            Value::Bool(value) => Ok(*value),
+            _ => Err("Expected a boolean"),
+        }
+    }
+
+    /// Orders two numbers or quantities of the same dimension, `None` when either is NaN
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, &'static str> {
+        let (left, right) = self.magnitudes(other)?;
+        Ok(left.partial_cmp(&right))
+    }
+
+    pub fn equals(&self, other: &Value) -> Result<bool, &'static str> {
+        match (self, other) {
+            (Value::Bool(left), Value::Bool(right)) => Ok(left == right),
+            (Value::List(left), Value::List(right)) => {
+                if left.len() != right.len() {
+                    return Ok(false);
+                }
+                for (left, right) in left.iter().zip(right) {
+                    if !left.equals(right)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            _ => Ok(self.compare(other)? == Some(Ordering::Equal)),
+        }
+    }
+
+    /// Equality within a relative tolerance, so that `sqrt(2)^2 ~= 2` holds
+    pub fn approximately_equals(&self, other: &Value) -> Result<bool, &'static str> {
+        if let (Value::Bool(left), Value::Bool(right)) = (self, other) {
+            return Ok(left == right);
+        }
+        let (left, right) = self.magnitudes(other)?;
+        let scale = left.abs().max(right.abs()).max(1.0);
+        Ok((left - right).abs() <= APPROXIMATE_EQUALITY_TOLERANCE * scale)
+    }
+
+    /// Applies an operation element by element when either side is a list, e.g. `[1, 2] * 3`
+    fn broadcast(
+        self,
+        other: Value,
+        operation: fn(Value, Value) -> Result<Value, &'static str>,
+    ) -> Result<Value, &'static str> {
+        let elements: Result<Vec<Value>, &'static str> = match (self, other) {
+            (Value::List(left), Value::List(right)) => {
+                if left.len() != right.len() {
+                    return Err("List lengths differ");
+                }
+                left.into_iter()
+                    .zip(right)
+                    .map(|(left, right)| operation(left, right))
+                    .collect()
+            }
+            (Value::List(list), scalar) => list
+                .into_iter()
+                .map(|element| operation(element, scalar.clone()))
+                .collect(),
+            (scalar, Value::List(list)) => list
+                .into_iter()
+                .map(|element| operation(scalar.clone(), element))
+                .collect(),
+            (left, right) => return operation(left, right),
+        };
+        Ok(Value::List(elements?))
+    }
+
+    pub fn checked_add(self, other: Value) -> Result<Value, &'static str> {
+        match (self, other) {
+            (left @ Value::List(_), right) | (left, right @ Value::List(_)) => {
+                left.broadcast(right, Value::checked_add)
+            }
+            (left, right) => left.add_or_subtract(right, |a, b| a + b),
+        }
+    }
+
+    pub fn checked_sub(self, other: Value) -> Result<Value, &'static str> {
+        match (self, other) {
+            (left @ Value::List(_), right) | (left, right @ Value::List(_)) => {
+                left.broadcast(right, Value::checked_sub)
+            }
+            (left, right) => left.add_or_subtract(right, |a, b| a - b),
+        }
+    }
+
+    fn add_or_subtract(
+        self,
+        other: Value,
+        operation: impl Fn(f64, f64) -> f64,
+    ) -> Result<Value, &'static str> {
+        self.magnitudes(&other)?;
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => {
+                Ok(Value::Number(operation(left, right)))
+            }
+            (Value::Quantity(left), Value::Quantity(right)) => {
+                if left.dimension != right.dimension {
+                    return Err("Incompatible units");
+                }
+                Ok(Value::Quantity(Quantity {
+                    value: operation(left.value, right.value),
+                    ..left
+                }))
+            }
+            _ => Err("Incompatible units"),
+        }
+    }
+
+    pub fn negate(self) -> Result<Value, &'static str> {
+        match self {
+            Value::Number(value) => Ok(Value::Number(-value)),
+            Value::Quantity(quantity) => Ok(Value::Quantity(Quantity {
+                value: -quantity.value,
+                ..quantity
+            })),
+            Value::Bool(_) => Err(BOOLEAN_IN_ARITHMETIC),
+            Value::List(list) => Ok(Value::List(
+                list.into_iter()
+                    .map(Value::negate)
+                    .collect::<Result<_, _>>()?,
+            )),
+            Value::Expression(_) => Err(EXPRESSION_IN_ARITHMETIC),
+        }
+    }
+
+    pub fn checked_mul(self, other: Value) -> Result<Value, &'static str> {
+        Ok(match (self, other) {
+            (left @ Value::List(_), right) | (left, right @ Value::List(_)) => {
+                return left.broadcast(right, Value::checked_mul)
+            }
+            (Value::Number(left), Value::Number(right)) => Value::Number(left * right),
+            (Value::Number(scalar), Value::Quantity(quantity))
+            | (Value::Quantity(quantity), Value::Number(scalar)) => Value::Quantity(Quantity {
+                value: quantity.value * scalar,
+                ..quantity
+            }),
+            (Value::Quantity(left), Value::Quantity(right)) => Value::from_quantity(Quantity {
+                value: left.value * right.value,
+                dimension: left.dimension.multiply(&right.dimension)?,
+                unit: combine_units(left.unit, right.unit, '*'),
+            }),
+            (Value::Bool(_), _) | (_, Value::Bool(_)) => return Err(BOOLEAN_IN_ARITHMETIC),
+            (Value::Expression(_), _) | (_, Value::Expression(_)) => {
+                return Err(EXPRESSION_IN_ARITHMETIC)
+            }
+        })
+    }
+
+    pub fn checked_div(self, other: Value) -> Result<Value, &'static str> {
+        Ok(match (self, other) {
+            (left @ Value::List(_), right) | (left, right @ Value::List(_)) => {
+                return left.broadcast(right, Value::checked_div)
+            }
+            (_, right) if right.magnitude()? == 0.0 => return Err("Division by zero"),
+            (Value::Number(left), Value::Number(right)) => Value::Number(left / right),
+            (Value::Quantity(quantity), Value::Number(scalar)) => Value::Quantity(Quantity {
+                value: quantity.value / scalar,
+                ..quantity
+            }),
+            (Value::Number(scalar), Value::Quantity(quantity)) => Value::Quantity(Quantity {
+                value: scalar / quantity.value,
+                dimension: quantity.dimension.pow(-1)?,
+                unit: quantity.unit.map(|unit| DisplayUnit {
+                    name: format!("1/{}", unit.name),
+                    factor: 1.0 / unit.factor,
+                }),
+            }),
+            (Value::Quantity(left), Value::Quantity(right)) => Value::from_quantity(Quantity {
+                value: left.value / right.value,
+                dimension: left.dimension.divide(&right.dimension)?,
+                unit: combine_units(left.unit, right.unit, '/'),
+            }),
+            (Value::Bool(_), _) | (_, Value::Bool(_)) => return Err(BOOLEAN_IN_ARITHMETIC),
+            (Value::Expression(_), _) | (_, Value::Expression(_)) => {
+                return Err(EXPRESSION_IN_ARITHMETIC)
+            }
+        })
+    }
+
+    pub fn checked_pow(self, exponent: Value) -> Result<Value, &'static str> {
+        match (self, exponent) {
+            (left @ Value::List(_), right) | (left, right @ Value::List(_)) => {
+                left.broadcast(right, Value::checked_pow)
+            }
+            (Value::Number(base), Value::Number(exponent)) => {
+                Ok(Value::Number(base.powf(exponent)))
+            }
+            (Value::Quantity(base), Value::Number(exponent)) => {
+                if exponent.fract() != 0.0 || !exponent.is_finite() {
+                    return Err("Units can only be raised to integer powers");
+                }
+                // Exponents too large for an `i8` saturate in the cast and fail the conversion
+                let dimension_exponent =
+                    i8::try_from(exponent as i64).map_err(|_| EXPONENT_TOO_LARGE)?;
+                Ok(Value::from_quantity(Quantity {
+                    value: base.value.powf(exponent),
+                    dimension: base.dimension.pow(dimension_exponent)?,
+                    unit: base.unit.map(|unit| match exponent {
+                        1.0 => unit,
+                        _ => DisplayUnit {
+                            name: format!("{}^{}", unit.name, exponent),
+                            factor: unit.factor.powf(exponent),
+                        },
+                    }),
+                }))
+            }
+            (Value::Bool(_), _) | (_, Value::Bool(_)) => Err(BOOLEAN_IN_ARITHMETIC),
+            (Value::Expression(_), _) | (_, Value::Expression(_)) => Err(EXPRESSION_IN_ARITHMETIC),
+            (_, Value::Quantity(_)) => Err("Exponent must be dimensionless"),
+        }
+    }
+
+    /// Expresses the value in the unit of `target`, e.g. `1.5 TB` in `GiB`
+    pub fn convert(self, target: Value) -> Result<Value, &'static str> {
+        if let Value::List(_) = self {
+            return self.broadcast(target, Value::convert);
+        }
+        let (Value::Quantity(quantity), Value::Quantity(target)) = (self, target) else {
+            return Err("Conversion target must be a unit");
+        };
+        if quantity.dimension != target.dimension {
+            return Err("Incompatible units");
+        }
+        Ok(Value::Quantity(Quantity {
+            unit: target.unit,
+            ..quantity
+        }))
+    }
+}
+
+fn combine_units(
+    left: Option<DisplayUnit>,
+    right: Option<DisplayUnit>,
+    operator: char,
+) -> Option<DisplayUnit> {
+    let (left, right) = (left?, right?);
+    let factor = match operator {
+        '*' => left.factor * right.factor,
+        _ => left.factor / right.factor,
+    };
+    Some(DisplayUnit {
+        name: format!("{}{}{}", left.name, operator, right.name),
+        factor,
+    })
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(value) => write!(f, "{}", value),
+            Value::Quantity(quantity) => write!(f, "{}", quantity),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::List(list) => write!(f, "[{}]", list.iter().join(", ")),
+            Value::Expression(expression) => write!(f, "{}", expression),
+        }
+    }
+}